@@ -0,0 +1,195 @@
+//! Benchmarks on synthetic SVD files
+//!
+//! Parsing, applying and serializing are measured separately, so changes of the XML tree and of the
+//! patch matching can be judged on their own. See the README for comparing against the checked-in baselines.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use patch_xml::{Document, Patch};
+
+const PERIPHERALS: usize = 40;
+const REGISTERS: usize = 50;
+const FIELDS: usize = 8;
+
+// The patches that are applied, each one exercises a different part of the matching
+const PATCHES: &[(&str, &str)] = &[
+    (
+        "simple_values",
+        r#"
+device:
+  name: BENCH_PATCHED
+  peripherals:
+    peripheral:
+      registers:
+        register:
+          size: 16
+          resetValue: "0xFFFFFFFF"
+"#,
+    ),
+    (
+        "regex_keys",
+        r#"
+device:
+  "periph.+s":
+    "periph.*":
+      "regist.+":
+        "reg.*":
+          fields:
+            "fi.+":
+              bitWidth: 2
+"#,
+    ),
+    (
+        "filters",
+        r#"
+device:
+  peripherals:
+    peripheral:
+      $if: { name: ^PERIPH1.* }
+      registers:
+        register:
+          $if:
+            name: ^REG(1|2)\d
+            size: 32
+          access: read-only
+"#,
+    ),
+    (
+        "move_copy",
+        r#"
+device:
+  peripherals:
+    peripheral:
+      registers:
+        - register:
+            $if: { name: ^REG\d$ }
+            $copy: ../aliases/
+        - register:
+            $if: { name: ^REG4\d }
+            $move: ../legacy/
+"#,
+    ),
+    (
+        "adds",
+        r#"
+device:
+  peripherals:
+    peripheral:
+      registers:
+        register:
+          $modify:
+            +description: Generated register
+          fields:
+            $modify:
+              +field:
+                name: RESERVED
+                bitOffset: 31
+                bitWidth: 1
+"#,
+    ),
+];
+
+// Builds an SVD-like document with PERIPHERALS * REGISTERS registers of FIELDS fields each
+fn svd() -> String {
+    let mut xml = String::from(r#"<device schemaVersion="1.1"><name>BENCH</name><peripherals>"#);
+    for p in 0..PERIPHERALS {
+        write!(
+            xml,
+            "<peripheral><name>PERIPH{}</name><baseAddress>0x{:08X}</baseAddress><registers>",
+            p,
+            0x4000_0000 + p * 0x400
+        )
+        .unwrap();
+        for r in 0..REGISTERS {
+            write!(
+                xml,
+                "<register><name>REG{}</name><addressOffset>0x{:X}</addressOffset><size>32</size><access>read-write</access><resetValue>0x00000000</resetValue><fields>",
+                r,
+                r * 4
+            )
+            .unwrap();
+            for f in 0..FIELDS {
+                write!(
+                    xml,
+                    "<field><name>FIELD{}</name><bitOffset>{}</bitOffset><bitWidth>4</bitWidth></field>",
+                    f,
+                    f * 4
+                )
+                .unwrap();
+            }
+            xml.push_str("</fields></register>");
+        }
+        xml.push_str("</registers></peripheral>");
+    }
+    xml.push_str("</peripherals></device>");
+    xml
+}
+
+fn parse(c: &mut Criterion) {
+    let xml = svd();
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(xml.len() as u64));
+    group.bench_function("svd", |b| b.iter(|| Document::parse(&xml).unwrap()));
+    group.finish();
+}
+
+fn apply(c: &mut Criterion) {
+    let xml = svd();
+    let unchanged = Document::parse(&xml).unwrap().to_xml_string().unwrap();
+    let mut group = c.benchmark_group("apply");
+    for (name, patch) in PATCHES {
+        let patch = Patch::from_yaml(patch).unwrap();
+        // A patch that matches nothing would not measure anything
+        let mut document = Document::parse(&xml).unwrap();
+        patch.apply(&mut document).unwrap();
+        assert!(
+            document.to_xml_string().unwrap() != unchanged,
+            "{} does not change the document",
+            name
+        );
+        // Parsing is done in the setup and not measured
+        group.bench_function(*name, |b| {
+            b.iter_batched(
+                || Document::parse(&xml).unwrap(),
+                |mut document| {
+                    patch.apply(&mut document).unwrap();
+                    document
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn serialize(c: &mut Criterion) {
+    let xml = svd();
+    let document = Document::parse(&xml).unwrap();
+    let mut group = c.benchmark_group("serialize");
+    group.throughput(Throughput::Bytes(xml.len() as u64));
+    group.bench_function("svd", |b| {
+        b.iter(|| {
+            let mut result = Vec::with_capacity(xml.len());
+            document.write(&mut result).unwrap();
+            result
+        })
+    });
+    group.finish();
+}
+
+fn config() -> Criterion {
+    Criterion::default()
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(5))
+        .output_directory(&Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/baselines"))
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = parse, apply, serialize
+}
+criterion_main!(benches);
@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::patch::Patch;
+
+/// Applies one patch to many XML files on a thread pool
+///
+/// Every file is patched by one thread with [`Patch::apply_to_file`], while the patch is shared by all threads.
+/// An output is only replaced if its file was patched successfully, so it may also be the input.
+/// A failing file does not stop the other files; its error is reported in the [`BatchSummary`].
+/// This includes panics while a file is patched, which are reported as the error of the file.
+///
+/// # Example
+///
+/// ```no_run
+/// use patch_xml::{Batch, Patch};
+/// let patch = Patch::from_yaml("device: { $modify: { +patched: true } }").unwrap();
+/// let summary = Batch::new()
+///     .add_glob("svd/*.svd", "patched")
+///     .unwrap()
+///     .threads(8)
+///     .run(&patch)
+///     .unwrap();
+/// println!("{}", summary);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    jobs: Vec<(PathBuf, PathBuf)>,
+    threads: Option<usize>,
+}
+
+impl Batch {
+    /// Creates an empty batch
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+    /// Adds an input file and the path its result is written to
+    pub fn add<I: Into<PathBuf>, O: Into<PathBuf>>(mut self, input: I, output: O) -> Batch {
+        self.jobs.push((input.into(), output.into()));
+        self
+    }
+    /// Adds all files matching the glob pattern. The results are written to the output directory
+    /// with the file name of the input. A pattern that matches no file is an error.
+    pub fn add_glob<O: AsRef<Path>>(
+        mut self,
+        pattern: &str,
+        output_dir: O,
+    ) -> Result<Batch, String> {
+        let paths =
+            glob::glob(pattern).map_err(|e| format!("Invalid pattern \"{}\": {}", pattern, e))?;
+        let count = self.jobs.len();
+        for path in paths {
+            let input = path.map_err(|e| e.to_string())?;
+            if !input.is_file() {
+                continue;
+            }
+            let output = match input.file_name() {
+                Some(file_name) => output_dir.as_ref().join(file_name),
+                None => continue,
+            };
+            self.jobs.push((input, output));
+        }
+        if self.jobs.len() == count {
+            return Err(format!("No file is matching \"{}\"", pattern));
+        }
+        Ok(self)
+    }
+    /// Sets the number of threads. By default, one thread per CPU is used.
+    pub fn threads(mut self, threads: usize) -> Batch {
+        self.threads = Some(threads);
+        self
+    }
+    /// Returns the number of files in the batch
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+    /// Returns whether the batch contains no files
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+    /// Applies the patch to all files of the batch
+    ///
+    /// An error is only returned if the batch cannot be started, e.g. because two inputs would be
+    /// written to the same file. Errors of individual files are part of the summary.
+    pub fn run(&self, patch: &Patch) -> Result<BatchSummary, String> {
+        let mut outputs = HashSet::new();
+        for (_, output) in &self.jobs {
+            if !outputs.insert(output) {
+                return Err(format!(
+                    "\"{}\" is the result of more than one input",
+                    output.display()
+                ));
+            }
+        }
+        let mut builder = rayon::ThreadPoolBuilder::new();
+        if let Some(threads) = self.threads {
+            builder = builder.num_threads(threads);
+        }
+        let pool = builder
+            .build()
+            .map_err(|e| format!("Could not create thread pool: {}", e))?;
+        let results = pool.install(|| {
+            self.jobs
+                .par_iter()
+                .map(|(input, output)| BatchResult {
+                    input: input.clone(),
+                    output: output.clone(),
+                    result: patch_file(patch, input, output),
+                })
+                .collect()
+        });
+        Ok(BatchSummary { results })
+    }
+}
+
+fn patch_file(patch: &Patch, input: &Path, output: &Path) -> Result<(), String> {
+    panic::catch_unwind(AssertUnwindSafe(|| patch.apply_to_file(input, output)))
+        .unwrap_or_else(|panic| Err(format!("Panic while patching: {}", panic_message(&*panic))))
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown cause", String::as_str),
+    }
+}
+
+/// The result of one file of a [`Batch`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    /// The XML file the patch was applied to
+    pub input: PathBuf,
+    /// The file the patched XML is written to
+    pub output: PathBuf,
+    /// The error that occurred while loading, patching or writing the file
+    pub result: Result<(), String>,
+}
+
+/// The results of all files of a [`Batch`], in the order the files were added
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchSummary {
+    /// The result of each file
+    pub results: Vec<BatchResult>,
+}
+
+impl BatchSummary {
+    /// Returns the files that were patched successfully
+    pub fn succeeded(&self) -> impl Iterator<Item = &BatchResult> {
+        self.results.iter().filter(|r| r.result.is_ok())
+    }
+    /// Returns the files that could not be patched
+    pub fn failed(&self) -> impl Iterator<Item = &BatchResult> {
+        self.results.iter().filter(|r| r.result.is_err())
+    }
+    /// Returns whether all files were patched successfully
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|r| r.result.is_ok())
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in self.failed() {
+            if let Err(e) = &result.result {
+                writeln!(f, "{}: {}", result.input.display(), e)?;
+            }
+        }
+        write!(
+            f,
+            "{} of {} files patched, {} failed",
+            self.succeeded().count(),
+            self.results.len(),
+            self.failed().count()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_outputs() {
+        let batch = Batch::new()
+            .add("a/x.xml", "out.xml")
+            .add("b/x.xml", "out.xml");
+        assert_eq!(
+            batch.run(&Patch::default()),
+            Err("\"out.xml\" is the result of more than one input".to_string())
+        );
+    }
+    #[test]
+    fn patch_in_place() {
+        let dir = std::env::temp_dir().join("patch_xml_batch_in_place");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.xml"), "<element>Foo</element>").unwrap();
+        std::fs::write(dir.join("b.xml"), "<element>Foo").unwrap();
+        let pattern = dir.join("*.xml");
+        let summary = Batch::new()
+            .add_glob(pattern.to_str().unwrap(), &dir)
+            .unwrap()
+            .run(&Patch::from_yaml("element: Bar").unwrap())
+            .unwrap();
+        assert_eq!(summary.failed().count(), 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("a.xml")).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><element>Bar</element>"#
+        );
+        // The failed file is kept and no temporary file is left behind
+        assert_eq!(
+            std::fs::read_to_string(dir.join("b.xml")).unwrap(),
+            "<element>Foo"
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    }
+    #[test]
+    fn glob_without_files() {
+        let pattern = std::env::temp_dir().join("patch_xml_batch_missing/*.xml");
+        let pattern = pattern.to_str().unwrap();
+        assert_eq!(
+            Batch::new().add_glob(pattern, "out").unwrap_err(),
+            format!("No file is matching \"{}\"", pattern)
+        );
+    }
+    #[test]
+    fn panic_message() {
+        let panic = std::panic::catch_unwind(|| panic!("broken {}", 1)).unwrap_err();
+        assert_eq!(super::panic_message(&*panic), "broken 1");
+    }
+    #[test]
+    fn summary() {
+        let summary = BatchSummary {
+            results: vec![
+                BatchResult {
+                    input: PathBuf::from("a.xml"),
+                    output: PathBuf::from("out/a.xml"),
+                    result: Ok(()),
+                },
+                BatchResult {
+                    input: PathBuf::from("b.xml"),
+                    output: PathBuf::from("out/b.xml"),
+                    result: Err("broken".to_string()),
+                },
+            ],
+        };
+        assert!(!summary.is_success());
+        assert_eq!(
+            summary.to_string(),
+            "b.xml: broken\n1 of 2 files patched, 1 failed"
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Builders to create patches in Rust code instead of parsing them from patch files
+//!
+//! Names and values use the same syntax as in patch files, e.g. regular expressions for queries,
+//! `+name` or `field[name=EN]` for modifications and `[../name]` references in values.
+//!
+//! # Panics
+//!
+//! Like patch files, invalid regular expressions and references panic when the patch is built.
+
+use indexmap::IndexMap;
+
+use crate::patch::Patch;
+use crate::patch_structure::{
+    AttributeModification, AttributeValue, ComplexQuery, ComplexValue, Filter,
+    ModificationIdentifier, ModificationValue, Query, ReferenceExpression, Regex, SimpleValueType,
+    TextValue,
+};
+
+/// Builds the queries of a patch, starting with [`Patch::query`]
+///
+/// The builder points to the query of the current element. [`child`](QueryBuilder::child) and
+/// [`parent`](QueryBuilder::parent) move down and up, all other methods apply to the current element.
+/// Methods taking a regular expression panic if it is invalid.
+#[derive(Debug, Clone)]
+pub struct QueryBuilder {
+    root: ComplexQuery,
+    path: Vec<Regex>,
+}
+
+impl QueryBuilder {
+    pub(crate) fn new(pattern: &str) -> QueryBuilder {
+        QueryBuilder {
+            root: ComplexQuery::new(),
+            path: vec![Regex::from(pattern)],
+        }
+    }
+    /// Continues with a new query for the root elements matching the given regular expression
+    pub fn query(mut self, pattern: &str) -> QueryBuilder {
+        self.path = vec![Regex::from(pattern)];
+        self
+    }
+    /// Continues with the child elements of the current element matching the given regular expression
+    pub fn child(mut self, pattern: &str) -> QueryBuilder {
+        self.path.push(Regex::from(pattern));
+        self
+    }
+    /// Continues with the parent element
+    pub fn parent(mut self) -> QueryBuilder {
+        self.path.pop();
+        self
+    }
+    /// Applies the query only to elements matching the condition (`$if`).
+    /// Several conditions must all match.
+    pub fn filter(mut self, condition: Condition) -> QueryBuilder {
+        let modifier = &mut self.current().modifier;
+        modifier.filter = Some(Condition::combine(modifier.filter.take(), condition.0));
+        self
+    }
+    /// Modifies the current element (`$modify`)
+    pub fn modify(mut self, modification: Modification) -> QueryBuilder {
+        self.current().modification = Some(ModificationValue::ComplexValue(modification.0));
+        self
+    }
+    /// Moves the current element to the given path (`$move`)
+    pub fn move_to(mut self, target: &str) -> QueryBuilder {
+        self.current().modifier.move_to = Some(ReferenceExpression::from(target));
+        self
+    }
+    /// Copies the current element to the given path (`$copy`)
+    pub fn copy_to(mut self, target: &str) -> QueryBuilder {
+        self.current().modifier.copy = Some(ReferenceExpression::from(target));
+        self
+    }
+    /// Replaces the content of the current element by a value and continues with the parent element
+    pub fn set(self, value: impl Into<Value>) -> QueryBuilder {
+        self.assign(value.into().0)
+    }
+    /// Removes the current element and continues with the parent element
+    pub fn remove(self) -> QueryBuilder {
+        self.assign(SimpleValueType::Remove)
+    }
+    /// Finishes the patch
+    pub fn build(self) -> Patch {
+        Patch::from(Query::Complex(self.root))
+    }
+
+    fn assign(mut self, value: SimpleValueType) -> QueryBuilder {
+        if let Some(regex) = self.path.pop() {
+            self.current()
+                .subqueries
+                .insert(regex, Query::Simple(value));
+        }
+        self
+    }
+    // Returns the query of the current element, replacing simple values on the way by queries
+    fn current(&mut self) -> &mut ComplexQuery {
+        let QueryBuilder { root, path } = self;
+        let mut query = root;
+        for regex in path.iter() {
+            let child = query
+                .subqueries
+                .entry(regex.clone())
+                .or_insert_with(|| Query::Complex(ComplexQuery::new()));
+            if !matches!(child, Query::Complex(_)) {
+                *child = Query::Complex(ComplexQuery::new());
+            }
+            query = match child {
+                Query::Complex(query) => query,
+                _ => unreachable!(),
+            };
+        }
+        query
+    }
+}
+
+impl From<QueryBuilder> for Patch {
+    fn from(builder: QueryBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// A value of an element or attribute, like a scalar in a patch file
+///
+/// Strings may contain references like `[../name]`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Value(SimpleValueType);
+
+impl Value {
+    /// The value that removes an element or attribute (`~`)
+    pub fn remove() -> Value {
+        Value(SimpleValueType::Remove)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value(SimpleValueType::Pattern(ReferenceExpression::from(value)))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value(SimpleValueType::Pattern(ReferenceExpression::from(
+            value.as_str(),
+        )))
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value(SimpleValueType::Boolean(value))
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value(SimpleValueType::UnsignedInteger(value))
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value(SimpleValueType::UnsignedInteger(value.into()))
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value(SimpleValueType::SignedInteger(value))
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value(SimpleValueType::SignedInteger(value.into()))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value(SimpleValueType::Float(value))
+    }
+}
+
+/// A condition on an element (`$if`)
+///
+/// Conditions with an invalid regular expression panic when they are created.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Condition(Filter);
+
+impl Condition {
+    /// Parses a condition like a string in a patch file, e.g. `>4`, `!=RW` or `^regex$`
+    pub fn parse(expression: &str) -> Condition {
+        Condition(Filter::from(expression))
+    }
+    /// The text of the element equals the value; numbers are compared by value
+    pub fn equals(value: impl Into<Value>) -> Condition {
+        Condition::compare("=", value.into())
+    }
+    /// The text of the element differs from the value
+    pub fn not_equals(value: impl Into<Value>) -> Condition {
+        Condition::compare("!=", value.into())
+    }
+    /// The text of the element is greater than the value
+    pub fn greater_than(value: impl Into<Value>) -> Condition {
+        Condition::compare(">", value.into())
+    }
+    /// The text of the element is greater than or equal to the value
+    pub fn greater_equal(value: impl Into<Value>) -> Condition {
+        Condition::compare(">=", value.into())
+    }
+    /// The text of the element is less than the value
+    pub fn less_than(value: impl Into<Value>) -> Condition {
+        Condition::compare("<", value.into())
+    }
+    /// The text of the element is less than or equal to the value
+    pub fn less_equal(value: impl Into<Value>) -> Condition {
+        Condition::compare("<=", value.into())
+    }
+    /// The whole text of the element matches the regular expression
+    pub fn matches(pattern: &str) -> Condition {
+        Condition(Filter::Regex(Regex::from(pattern)))
+    }
+    /// The element has no text (`~`)
+    pub fn empty() -> Condition {
+        Condition(Filter::NotSet)
+    }
+    /// Any child element matching the regular expression fulfills the condition
+    pub fn child(pattern: &str, condition: Condition) -> Condition {
+        Condition(Filter::Child((Regex::from(pattern), Box::new(condition.0))))
+    }
+    /// There is no child element matching the regular expression (`name: ~`)
+    pub fn missing_child(pattern: &str) -> Condition {
+        Condition::child(pattern, Condition::empty())
+    }
+    /// Both conditions must match (`$and`)
+    pub fn and(self, other: Condition) -> Condition {
+        Condition(Condition::combine(Some(self.0), other.0))
+    }
+    /// Any of both conditions must match (`$or`)
+    pub fn or(self, other: Condition) -> Condition {
+        let mut filters = match self.0 {
+            Filter::Or(filters) => filters,
+            filter => vec![filter],
+        };
+        match other.0 {
+            Filter::Or(mut other) => filters.append(&mut other),
+            filter => filters.push(filter),
+        }
+        Condition(Filter::Or(filters))
+    }
+
+    fn compare(prefix: &str, value: Value) -> Condition {
+        Condition(Filter::from(
+            format!("{}{}", prefix, value.0.to_scalar()).as_str(),
+        ))
+    }
+    // Combines two filters to a flat conjunction, as a map with several entries in a patch file
+    fn combine(filter: Option<Filter>, other: Filter) -> Filter {
+        let mut filters = match filter {
+            None => return other,
+            Some(Filter::And(filters)) => filters,
+            Some(filter) => vec![filter],
+        };
+        match other {
+            Filter::And(mut other) => filters.append(&mut other),
+            filter => filters.push(filter),
+        }
+        Filter::And(filters)
+    }
+}
+
+/// A modification of an element (`$modify`)
+///
+/// Child names use the syntax of patch files, e.g. `+name` adds and `~name` replaces a child element.
+/// Invalid regular expressions for attribute names panic.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Modification(ComplexValue);
+
+impl Modification {
+    /// Creates a modification that changes nothing
+    pub fn new() -> Modification {
+        Modification(ComplexValue::new())
+    }
+    /// Sets the text of the matching child elements
+    pub fn set(self, name: &str, value: impl Into<Value>) -> Modification {
+        self.subvalue(name, ModificationValue::SimpleValue(value.into().0))
+    }
+    /// Adds a child element with the given text (`+name`)
+    pub fn add(self, name: &str, value: impl Into<Value>) -> Modification {
+        self.set(&format!("+{}", name), value)
+    }
+    /// Removes the matching child elements
+    pub fn remove(self, name: &str) -> Modification {
+        self.set(name, Value::remove())
+    }
+    /// Modifies the matching child elements
+    pub fn child(self, name: &str, modification: Modification) -> Modification {
+        self.subvalue(name, ModificationValue::ComplexValue(modification.0))
+    }
+    /// Sets the text of the element itself, keeping its child elements (`$text`)
+    pub fn text(mut self, value: impl Into<Value>) -> Modification {
+        self.0.text = Some(TextValue::Set(value.into().0));
+        self
+    }
+    /// Sets the value of the attributes matching the regular expression (`$attributes`)
+    pub fn attribute(self, pattern: &str, value: impl Into<Value>) -> Modification {
+        self.attribute_value(pattern, AttributeValue::Simple(value.into().0))
+    }
+    /// Removes the attributes matching the regular expression
+    pub fn remove_attribute(self, pattern: &str) -> Modification {
+        self.attribute(pattern, Value::remove())
+    }
+    /// Renames the attributes matching the regular expression (`$rename`)
+    pub fn rename_attribute(self, pattern: &str, name: &str) -> Modification {
+        let modification = AttributeModification {
+            value: None,
+            rename: Some(ReferenceExpression::from(name)),
+            move_to: None,
+            required: false,
+        };
+        self.attribute_value(pattern, AttributeValue::Complex(modification))
+    }
+    /// Applies the modification only to elements matching the condition (`$if`)
+    pub fn filter(mut self, condition: Condition) -> Modification {
+        let modifier = &mut self.0.modifier;
+        modifier.filter = Some(Condition::combine(modifier.filter.take(), condition.0));
+        self
+    }
+    /// Moves the element to the given path (`$move`)
+    pub fn move_to(mut self, target: &str) -> Modification {
+        self.0.modifier.move_to = Some(ReferenceExpression::from(target));
+        self
+    }
+    /// Copies the element to the given path (`$copy`)
+    pub fn copy_to(mut self, target: &str) -> Modification {
+        self.0.modifier.copy = Some(ReferenceExpression::from(target));
+        self
+    }
+
+    fn subvalue(mut self, name: &str, value: ModificationValue) -> Modification {
+        self.0
+            .subvalues
+            .insert(ModificationIdentifier::from(name), value);
+        self
+    }
+    fn attribute_value(mut self, pattern: &str, value: AttributeValue) -> Modification {
+        self.0
+            .attributes
+            .get_or_insert_with(IndexMap::new)
+            .insert(Regex::from(pattern), value);
+        self
+    }
+}
+
+impl Default for Modification {
+    fn default() -> Self {
+        Modification::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch_structure::parse;
+    use indoc::indoc;
+
+    fn assert_same_as_yaml(patch: Patch, yaml: &str) {
+        assert_eq!(patch.query, parse(yaml).unwrap());
+    }
+
+    #[test]
+    fn query_and_modification() {
+        assert_same_as_yaml(
+            Patch::query("peripherals")
+                .child("peripheral")
+                .filter(Condition::child("name", Condition::equals("UART")))
+                .filter(Condition::child("size", Condition::greater_than(8u64)))
+                .modify(
+                    Modification::new()
+                        .set("description", "UART [name]")
+                        .add("enabled", true)
+                        .remove("reserved")
+                        .child("~register[name=CR]", Modification::new().set("size", 32u64))
+                        .attribute("access", "read-write")
+                        .rename_attribute("id", "uid"),
+                )
+                .build(),
+            indoc!(
+                r#"
+                peripherals:
+                  peripheral:
+                    $if:
+                      name: UART
+                      size: ">8"
+                    $modify:
+                      description: UART [name]
+                      +enabled: true
+                      reserved: ~
+                      ~register[name=CR]:
+                        size: 32
+                      $attributes:
+                        access: read-write
+                        id:
+                          $rename: uid
+                "#
+            ),
+        );
+    }
+    #[test]
+    fn several_queries() {
+        assert_same_as_yaml(
+            Patch::query("device")
+                .child("name")
+                .set("STM32")
+                .child("old")
+                .remove()
+                .child("cpu")
+                .move_to("../../cpus")
+                .parent()
+                .child("peripheral")
+                .filter(Condition::matches("UART.*").or(Condition::missing_child("base")))
+                .copy_to("..")
+                .query("other")
+                .set(1u64)
+                .build(),
+            indoc!(
+                r#"
+                device:
+                  name: STM32
+                  old: ~
+                  cpu:
+                    $move: ../../cpus
+                  peripheral:
+                    $if:
+                      $or:
+                        - "^UART.*$"
+                        - base: ~
+                    $copy: ..
+                other: 1
+                "#
+            ),
+        );
+    }
+}
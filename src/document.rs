@@ -0,0 +1,57 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::xml_structure::bidirectional_xml_tree::XmlTree;
+
+/// An XML document that patches are applied to
+///
+/// # Example
+///
+/// ```
+/// use patch_xml::{Document, Patch};
+/// let patch = Patch::from_yaml("element: Bar").unwrap();
+/// let mut document = Document::parse("<element>Foo</element>").unwrap();
+/// patch.apply(&mut document).unwrap();
+/// assert_eq!(
+///     document.to_xml_string().unwrap(),
+///     r#"<?xml version="1.0" encoding="UTF-8"?><element>Bar</element>"#
+/// );
+/// ```
+pub struct Document {
+    pub(crate) xml_tree: XmlTree,
+}
+
+impl Document {
+    /// Loads a document from a string
+    pub fn parse(xml: &str) -> Result<Document, String> {
+        Document::from_reader(xml.as_bytes())
+    }
+    /// Loads a document from a reader, e.g. a file
+    pub fn from_reader<R: Read>(reader: R) -> Result<Document, String> {
+        let element = xmltree::Element::parse(reader)
+            .map_err(|e| format!("Error while reading XML: {}", e))?;
+        Ok(Document {
+            xml_tree: XmlTree::new(&element),
+        })
+    }
+    /// Writes the document to a writer, e.g. a file
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), String> {
+        self.xml_tree
+            .write(writer)
+            .map_err(|e| format!("Error while generating XML result: {}", e))
+    }
+    /// Writes the document to a string
+    pub fn to_xml_string(&self) -> Result<String, String> {
+        let mut result_bytes = Vec::new();
+        self.write(&mut result_bytes)?;
+        String::from_utf8(result_bytes).map_err(|e| e.to_string())
+    }
+}
+
+impl FromStr for Document {
+    type Err = String;
+
+    fn from_str(xml: &str) -> Result<Self, Self::Err> {
+        Document::parse(xml)
+    }
+}
@@ -8,12 +8,19 @@
 // ToDo: Add missing documentation and uncomment the following line
 #![deny(missing_docs, unused_imports)]
 
+mod batch;
+pub mod builder;
+mod document;
+mod patch;
 mod patch_processor;
 mod patch_structure;
 mod xml_structure;
 
-use patch_processor::PatchProcessor;
-use std::string::FromUtf8Error;
+pub use batch::{Batch, BatchResult, BatchSummary};
+pub use builder::{Condition, Modification, QueryBuilder, Value};
+pub use document::Document;
+pub use patch::Patch;
+pub use patch_structure::PatchFormat;
 
 /// Patches an XML file with a generic patch in YAML format
 ///
@@ -44,20 +51,71 @@ use std::string::FromUtf8Error;
 /// assert_eq!(result, result_xml);
 /// ```
 pub fn patch_xml(xmltree: String, patch: String) -> Result<String, String> {
-    let mut processor = PatchProcessor::new(xmltree.as_str());
-    match patch_structure::parse(&patch) {
-        Ok(Some(patch)) => {
-            processor.apply(&patch);
-        }
-        Ok(None) => {}
+    patch_xml_with_format(xmltree, patch, PatchFormat::Yaml)
+}
+
+/// Patches an XML file with a generic patch in the given format
+///
+/// Patches in JSON or TOML use the same structure as YAML patches and give the same result.
+/// [`PatchFormat::from_path`] selects the format by the extension of the patch file.
+///
+/// # Example
+///
+/// ```
+/// use patch_xml::PatchFormat;
+/// let original_xml = r#"<element>Foo</element>"#;
+/// let patch = r#"{ "element": { "$if": "Foo", "$modify": "Bar" } }"#;
+/// let result_xml = r#"<?xml version="1.0" encoding="UTF-8"?><element>Bar</element>"#;
+/// let result =
+///     patch_xml::patch_xml_with_format(original_xml.to_string(), patch.to_string(), PatchFormat::Json)
+///         .unwrap();
+/// assert_eq!(result, result_xml);
+/// ```
+pub fn patch_xml_with_format(
+    xmltree: String,
+    patch: String,
+    format: PatchFormat,
+) -> Result<String, String> {
+    apply_patch(xmltree, &Patch::parse(&patch, format)?)
+}
+
+/// Patches an XML file with a [`Patch`], e.g. one created by the [`builder`]
+///
+/// See [`Patch::query`] for an example.
+pub fn apply_patch(xmltree: String, patch: &Patch) -> Result<String, String> {
+    let mut document = Document::parse(&xmltree)?;
+    patch.apply(&mut document)?;
+    document.to_xml_string()
+}
+
+/// Parses a patch and writes it back in a normalized form
+///
+/// The result is parsed to the same patch as the input, so it can replace the original patch.
+/// Patches with comments are rejected, because the comments would be lost.
+///
+/// # Example
+///
+/// ```
+/// let patch = "element: { $if: '>4', subelement: Bar }";
+/// let result = patch_xml::format_patch(patch.to_string()).unwrap();
+/// assert_eq!(result, "element:\n  $if: \">4\"\n  subelement: Bar\n");
+/// ```
+pub fn format_patch(patch: String) -> Result<String, String> {
+    if patch_structure::has_comments(&patch) {
+        return Err("The patch has comments, which would be lost by formatting".to_string());
+    }
+    let query = match patch_structure::parse(&patch) {
+        Ok(Some(query)) => query,
+        Ok(None) => return Ok(String::new()),
         Err(e) => return Err(format!("Error while reading patch: {}", e)),
     };
-    let mut result_bytes = Vec::new();
-    match processor.xml_tree.to_xmltree().write(&mut result_bytes) {
-        Ok(_) => {}
-        Err(msg) => return Err(format!("Error while generating XML result: {}", msg)),
+    let result = patch_structure::serialize(&query)
+        .map_err(|e| format!("Error while writing patch: {}", e))?;
+    // The result must replace the original patch without changing it
+    match patch_structure::parse(&result) {
+        Ok(Some(formatted)) if formatted == query => Ok(result),
+        _ => Err("The formatted patch would differ from the original patch".to_string()),
     }
-    String::from_utf8(result_bytes).map_err(|e| FromUtf8Error::to_string(&e))
 }
 
 #[cfg(test)]
@@ -85,4 +143,8 @@ mod tests {
     fn test_lib_call_with_wrong_patch() {
         assert!(patch_xml(r#"<element></element>"#.to_string(), ":".to_string()).is_err());
     }
+    #[test]
+    fn test_lib_call_with_wrong_xml() {
+        assert!(patch_xml("<element>".to_string(), "element: Bar".to_string()).is_err());
+    }
 }
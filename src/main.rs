@@ -1,10 +1,10 @@
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs;
+use std::process;
 
 struct InputOutput {
-    xml_input_content: String,
-    patch_content: String,
+    xml_path: String,
+    patch: patch_xml::Patch,
     result_path: String,
 }
 
@@ -12,50 +12,169 @@ impl InputOutput {
     fn from_args(args: Vec<String>) -> Result<InputOutput, String> {
         if args.len() != 4 {
             return Err(format!(
-                "usage: {} <XML-file> <patch-file (yaml)> <result-file>",
-                args.get(0)
+                "usage: {} <XML-file> <patch-file (yaml, json or toml)> <result-file>",
+                args.first()
                     .ok_or("Could not get program path as first argument")?
             ));
         }
 
-        let mut xml_file =
-            File::open(args.get(1).ok_or("Could not get XML path")?).map_err(|e| e.to_string())?;
-        let mut xml_input_content = String::new();
-        xml_file
-            .read_to_string(&mut xml_input_content)
-            .map_err(|e| e.to_string())?;
-
-        let mut patch_file = File::open(args.get(2).ok_or("Could not get patch path")?)
-            .map_err(|e| e.to_string())?;
-        let mut patch_content = String::new();
-        patch_file
-            .read_to_string(&mut patch_content)
-            .map_err(|e| e.to_string())?;
+        let xml_path = args.get(1).ok_or("Could not get XML path")?.clone();
+        let patch_path = args.get(2).ok_or("Could not get patch path")?;
         Ok(InputOutput {
-            xml_input_content,
-            patch_content,
+            xml_path,
+            patch: patch_xml::Patch::from_file(patch_path)?,
             result_path: args.get(3).ok_or("Could not get result path")?.clone(),
         })
     }
+    /**
+    Applies the patch to the XML file. Large XML files are streamed if the patch allows it.
+    The result file is only replaced on success, so it may be the XML file itself.
+     **/
+    fn run(&self) -> Result<(), String> {
+        self.patch.apply_to_file(&self.xml_path, &self.result_path)
+    }
+}
+
+// Arguments of `patch-xml fmt [--check] <patch-file (yaml)>...`
+struct FormatOptions {
+    check: bool,
+    patch_paths: Vec<String>,
+}
+
+impl FormatOptions {
+    fn from_args(args: &[String]) -> Result<FormatOptions, String> {
+        let check = args.iter().any(|a| a == "--check");
+        let patch_paths: Vec<String> = args.iter().filter(|a| *a != "--check").cloned().collect();
+        if patch_paths.is_empty() {
+            return Err("usage: fmt [--check] <patch-file (yaml)>...".to_string());
+        }
+        Ok(FormatOptions { check, patch_paths })
+    }
+    /**
+    Rewrites the patch files in the canonical format or, with `--check`, only reports the files
+    that are not formatted. Returns whether all files were already formatted.
+    Files with comments are rejected, because the comments would be lost.
+     **/
+    fn run(&self) -> Result<bool, String> {
+        let mut formatted = true;
+        for path in &self.patch_paths {
+            if patch_xml::PatchFormat::from_path(path) != patch_xml::PatchFormat::Yaml {
+                return Err(format!("{}: only YAML patches can be formatted", path));
+            }
+            let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let result =
+                patch_xml::format_patch(content.clone()).map_err(|e| format!("{}: {}", path, e))?;
+            if result == content {
+                continue;
+            }
+            formatted = false;
+            if self.check {
+                println!("{} is not formatted", path);
+            } else {
+                fs::write(path, result).map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+        Ok(formatted)
+    }
+}
+
+// Arguments of `patch-xml batch [--jobs <n>] <patch-file> <output-dir> <XML-file or glob>...`
+struct BatchOptions {
+    jobs: Option<usize>,
+    patch_path: String,
+    output_dir: String,
+    inputs: Vec<String>,
+}
+
+impl BatchOptions {
+    fn from_args(args: &[String]) -> Result<BatchOptions, String> {
+        let usage = "usage: batch [--jobs <n>] <patch-file (yaml, json or toml)> <output-dir> <XML-file or glob>...";
+        let mut jobs = None;
+        let mut positional = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--jobs" {
+                let value = args.next().ok_or(usage)?;
+                jobs = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("\"{}\" is not a valid number of jobs", value))?,
+                );
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        if positional.len() < 3 {
+            return Err(usage.to_string());
+        }
+        let inputs = positional.split_off(2);
+        Ok(BatchOptions {
+            jobs,
+            patch_path: positional[0].clone(),
+            output_dir: positional[1].clone(),
+            inputs,
+        })
+    }
+    /**
+    Applies the patch to all inputs and writes the results with the same file names to the output directory.
+    Inputs may be glob patterns. Returns the summary of all files.
+     **/
+    fn run(&self) -> Result<patch_xml::BatchSummary, String> {
+        let patch = patch_xml::Patch::from_file(&self.patch_path)?;
+        fs::create_dir_all(&self.output_dir).map_err(|e| format!("{}: {}", self.output_dir, e))?;
+        let mut batch = patch_xml::Batch::new();
+        for input in &self.inputs {
+            batch = batch.add_glob(input, &self.output_dir)?;
+        }
+        if let Some(jobs) = self.jobs {
+            batch = batch.threads(jobs);
+        }
+        batch.run(&patch)
+    }
 }
 
 fn main() {
     //ToDo: Implement "Import" functionality
     //ToDo: Increase test coverage to more than 95%
-    let input_output = InputOutput::from_args(env::args().collect()).unwrap();
-    File::create(input_output.result_path)
-        .unwrap()
-        .write_all(
-            patch_xml::patch_xml(input_output.xml_input_content, input_output.patch_content)
-                .unwrap()
-                .as_bytes(),
-        )
-        .unwrap();
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("fmt") {
+        let result = FormatOptions::from_args(&args[2..]).and_then(|options| {
+            let formatted = options.run()?;
+            Ok(formatted || !options.check)
+        });
+        match result {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2)
+            }
+        }
+    }
+    if args.get(1).map(String::as_str) == Some("batch") {
+        match BatchOptions::from_args(&args[2..]).and_then(|options| options.run()) {
+            Ok(summary) => {
+                println!("{}", summary);
+                if !summary.is_success() {
+                    process::exit(1)
+                }
+                return;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2)
+            }
+        }
+    }
+    if let Err(e) = InputOutput::from_args(args).and_then(|input_output| input_output.run()) {
+        eprintln!("{}", e);
+        process::exit(1)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::InputOutput;
+    use crate::{BatchOptions, FormatOptions, InputOutput};
 
     #[test]
     fn test_input_output() {
@@ -67,4 +186,80 @@ mod tests {
         ])
         .unwrap();
     }
+    #[test]
+    fn test_in_place() {
+        let dir = std::env::temp_dir().join("patch_xml_in_place_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+        std::fs::write(path("in.xml"), "<element>Foo</element>").unwrap();
+        std::fs::write(path("patch.yaml"), "element: Bar").unwrap();
+        let args = vec![
+            "./program".to_string(),
+            path("in.xml"),
+            path("patch.yaml"),
+            path("in.xml"),
+        ];
+        InputOutput::from_args(args.clone()).unwrap().run().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(path("in.xml")).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><element>Bar</element>"#
+        );
+        // A failing patch leaves the file unchanged
+        std::fs::write(path("in.xml"), "<element>Foo").unwrap();
+        assert!(InputOutput::from_args(args).unwrap().run().is_err());
+        assert_eq!(
+            std::fs::read_to_string(path("in.xml")).unwrap(),
+            "<element>Foo"
+        );
+    }
+    #[test]
+    fn test_format() {
+        let path = std::env::temp_dir().join("patch_xml_format_test.yaml");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "element: {subelement: Bar, $if: '>4'}").unwrap();
+        let check = FormatOptions::from_args(&["--check".to_string(), path.clone()]).unwrap();
+        assert!(!check.run().unwrap());
+        let format = FormatOptions::from_args(std::slice::from_ref(&path)).unwrap();
+        assert!(!format.run().unwrap());
+        assert!(check.run().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "element:\n  $if: \">4\"\n  subelement: Bar\n"
+        );
+        std::fs::write(&path, "# comment\nelement: Bar\n").unwrap();
+        assert!(check.run().is_err());
+        assert!(format.run().is_err());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# comment\nelement: Bar\n"
+        );
+        assert!(FormatOptions::from_args(&["--check".to_string()]).is_err());
+    }
+    #[test]
+    fn test_batch() {
+        let dir = std::env::temp_dir().join("patch_xml_batch_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("in")).unwrap();
+        std::fs::write(dir.join("in/a.xml"), "<element>Foo</element>").unwrap();
+        std::fs::write(dir.join("in/b.xml"), "<element>Foo").unwrap();
+        std::fs::write(dir.join("patch.yaml"), "element: Bar").unwrap();
+        let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+        let options = BatchOptions::from_args(&[
+            "--jobs".to_string(),
+            "2".to_string(),
+            path("patch.yaml"),
+            path("out"),
+            path("in/*.xml"),
+        ])
+        .unwrap();
+        let summary = options.run().unwrap();
+        assert_eq!(summary.succeeded().count(), 1);
+        assert_eq!(summary.failed().count(), 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out/a.xml")).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><element>Bar</element>"#
+        );
+        assert!(BatchOptions::from_args(&[path("patch.yaml"), path("out")]).is_err());
+    }
 }
@@ -0,0 +1,219 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::builder::QueryBuilder;
+use crate::document::Document;
+use crate::patch_processor::PatchProcessor;
+use crate::patch_structure::{self, PatchFormat, Query};
+
+/// A patch that can be applied to XML documents
+///
+/// Patches are parsed from patch files with [`Patch::from_yaml`] or [`Patch::parse`], or built in Rust code
+/// with [`Patch::query`], which results in the same rules as the equivalent patch file.
+/// The regular expressions of keys and filters are compiled when the patch is created, so a patch should be
+/// created once and applied to all documents. Only the regular expressions in paths, like the destinations
+/// of `$move` and `$copy`, are compiled when the patch is applied, because they may contain references.
+/// Patches can be shared between threads.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Patch {
+    pub(crate) query: Option<Query>,
+}
+
+impl Patch {
+    /// Parses a patch in YAML format
+    pub fn from_yaml(content: &str) -> Result<Patch, String> {
+        Patch::parse(content, PatchFormat::Yaml)
+    }
+    /// Parses a patch in the given format
+    pub fn parse(content: &str, format: PatchFormat) -> Result<Patch, String> {
+        match patch_structure::parse_with_format(content, format) {
+            Ok(query) => Ok(Patch { query }),
+            Err(e) => Err(format!("Error while reading patch: {}", e)),
+        }
+    }
+    /// Reads a patch file in the format given by its extension and loads the files of its tables
+    ///
+    /// Table files (`file: access.csv`) are resolved relative to the directory of the patch file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Patch, String> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut patch = Patch::parse(&content, PatchFormat::from_path(&path.to_string_lossy()))?;
+        patch.load_tables(path.parent().unwrap_or_else(|| Path::new("")))?;
+        Ok(patch)
+    }
+    /// Loads the files of all tables, relative paths are resolved against `base_dir`
+    ///
+    /// Parsing a patch does not read any files, so a patch that is parsed from a string
+    /// and uses table files must be loaded before it is applied.
+    pub fn load_tables<P: AsRef<Path>>(&mut self, base_dir: P) -> Result<(), String> {
+        match &mut self.query {
+            Some(query) => query
+                .load_tables(base_dir.as_ref())
+                .map_err(|e| format!("Error while reading patch: {}", e)),
+            None => Ok(()),
+        }
+    }
+    /// Applies the patch to the document
+    ///
+    /// If an error occurs, the document may be partially patched.
+    pub fn apply(&self, document: &mut Document) -> Result<(), String> {
+        match &self.query {
+            Some(query) => PatchProcessor::apply(&mut document.xml_tree, query)
+                .map_err(|e| format!("Error while applying patch: {}", e)),
+            None => Ok(()),
+        }
+    }
+    /// Returns whether the patch can be applied while the XML is streamed
+    ///
+    /// This is the case if the patch only changes elements at known paths and everything it does
+    /// to an element stays inside this element: no `$move`, `$copy` or pulled paths that leave it
+    /// and no references to the elements above it. The root element itself must not be changed.
+    pub fn is_streamable(&self) -> bool {
+        self.query
+            .as_ref()
+            .is_some_and(PatchProcessor::is_streamable)
+    }
+    /// Applies the patch to the XML read from `reader` and writes the result to `writer`
+    ///
+    /// If the patch [is streamable](Patch::is_streamable), the elements are written while the XML is read,
+    /// so only the elements that are changed are kept in memory. Otherwise, the whole document is loaded
+    /// like with [`Patch::apply`]. The result is the same in both cases.
+    /// If an error occurs, the written result may be incomplete.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use patch_xml::Patch;
+    /// let patch = Patch::from_yaml("device: { cpu: { name: M4 } }").unwrap();
+    /// assert!(patch.is_streamable());
+    /// let mut result = Vec::new();
+    /// patch
+    ///     .apply_streaming("<device><cpu><name>M0</name></cpu></device>".as_bytes(), &mut result)
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(result).unwrap(),
+    ///     r#"<?xml version="1.0" encoding="UTF-8"?><device><cpu><name>M4</name></cpu></device>"#
+    /// );
+    /// ```
+    pub fn apply_streaming<R: Read, W: Write>(&self, reader: R, writer: W) -> Result<(), String> {
+        match &self.query {
+            Some(query) if PatchProcessor::is_streamable(query) => {
+                PatchProcessor::apply_streaming(query, reader, writer)
+            }
+            _ => {
+                let mut document = Document::from_reader(reader)?;
+                self.apply(&mut document)?;
+                document.write(writer)
+            }
+        }
+    }
+    /// Applies the patch to the XML file `input` and writes the result to the file `output`
+    ///
+    /// The result is written with [`Patch::apply_streaming`] to a temporary file next to `output`,
+    /// which replaces `output` only if the whole result was written. So `output` may be `input`,
+    /// and if an error occurs, `output` is left unchanged.
+    pub fn apply_to_file<I: AsRef<Path>, O: AsRef<Path>>(
+        &self,
+        input: I,
+        output: O,
+    ) -> Result<(), String> {
+        let (input, output) = (input.as_ref(), output.as_ref());
+        let temp = TempFile::next_to(output)?;
+        let reader = File::open(input).map_err(|e| e.to_string())?;
+        let file = File::create(&temp.0).map_err(|e| format!("{}: {}", temp.0.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        self.apply_streaming(BufReader::new(reader), &mut writer)?;
+        writer
+            .flush()
+            .map_err(|e| format!("{}: {}", temp.0.display(), e))?;
+        fs::rename(&temp.0, output).map_err(|e| format!("{}: {}", output.display(), e))
+    }
+    /// Starts a patch with a query for the root elements matching the given regular expression
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use patch_xml::{Condition, Modification, Patch};
+    /// let patch = Patch::query("peripherals")
+    ///     .child("peripheral")
+    ///     .filter(Condition::child("name", Condition::equals("UART")))
+    ///     .modify(Modification::new().add("enabled", true))
+    ///     .build();
+    /// let result = patch_xml::apply_patch(
+    ///     r#"<peripherals><peripheral><name>UART</name></peripheral></peripherals>"#.to_string(),
+    ///     &patch,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(
+    ///     result,
+    ///     r#"<?xml version="1.0" encoding="UTF-8"?><peripherals><peripheral><name>UART</name><enabled>true</enabled></peripheral></peripherals>"#
+    /// );
+    /// ```
+    pub fn query(pattern: &str) -> QueryBuilder {
+        QueryBuilder::new(pattern)
+    }
+    /// Writes the patch in YAML format
+    pub fn to_yaml(&self) -> Result<String, String> {
+        match &self.query {
+            Some(query) => patch_structure::serialize(query)
+                .map_err(|e| format!("Error while writing patch: {}", e)),
+            None => Ok(String::new()),
+        }
+    }
+}
+
+impl From<Query> for Patch {
+    fn from(query: Query) -> Self {
+        Patch { query: Some(query) }
+    }
+}
+
+// A file that is removed when it is dropped, unless it was renamed before
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn next_to(path: &Path) -> Result<TempFile, String> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("\"{}\" is not a file name", path.display()))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(".patch-xml.tmp");
+        Ok(TempFile(path.with_file_name(temp_name)))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn patch_is_send_and_sync() {
+        assert_send_sync::<Patch>();
+    }
+    #[test]
+    fn document_is_send_and_sync() {
+        assert_send_sync::<Document>();
+    }
+    #[test]
+    fn apply_repeatedly() {
+        let patch = Patch::from_yaml("counter: { $modify: { +tick: x } }").unwrap();
+        let mut document = Document::parse("<counter />").unwrap();
+        patch.apply(&mut document).unwrap();
+        patch.apply(&mut document).unwrap();
+        assert_eq!(
+            document.to_xml_string().unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><counter><tick>x</tick><tick>x</tick></counter>"#
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use indexmap::IndexMap;
+
+use crate::patch_processor::PatchProcessor;
+use crate::patch_structure::{ArrayCollapse, ArrayLayout};
+use crate::xml_structure::bidirectional_xml_tree::*;
+
+struct ArrayMember {
+    node: NodeId,
+    index: String,
+    offset: Option<(u64, String)>,
+}
+
+impl PatchProcessor {
+    /**
+    Replaces an array element by one concrete element per array index.
+    The placeholder is substituted by the index in all texts and attributes, the offsets are incremented
+    and the children that describe the array are removed.
+     **/
+    pub(super) fn expand_array(
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+        layout: &ArrayLayout,
+    ) -> Result<(), String> {
+        let dim_node = tree.child_by_name(xml_node, &layout.dim);
+        let increment_node = tree.child_by_name(xml_node, &layout.dim_increment);
+        let index_node = tree.child_by_name(xml_node, &layout.dim_index);
+        let dim_node = dim_node.ok_or_else(|| {
+            format!(
+                "Array element \"{}\" has no \"{}\" child",
+                tree[xml_node].name().unwrap_or_default(),
+                layout.dim
+            )
+        })?;
+        let dim = parse_integer(&tree.text(dim_node))?;
+        let indices = match index_node {
+            Some(index_node) => parse_dim_index(&tree.text(index_node)),
+            None => (0..dim).map(|i| i.to_string()).collect(),
+        };
+        if indices.len() as u64 != dim {
+            return Err(format!(
+                "Array has {} elements but {} indices",
+                dim,
+                indices.len()
+            ));
+        }
+        let increment = match increment_node {
+            Some(increment_node) => Some(parse_integer(&tree.text(increment_node))?),
+            None => None,
+        };
+        let base_offset = match tree.child_by_name(xml_node, &layout.offset) {
+            Some(offset_node) => {
+                let text = tree.text(offset_node);
+                Some((parse_integer(&text)?, text))
+            }
+            None => None,
+        };
+        let parent = match tree.parent(xml_node) {
+            Some(parent) => parent,
+            None => return Err("The root element cannot be expanded".to_string()),
+        };
+        let next = tree.next_sibling(xml_node);
+        for description_node in vec![Some(dim_node), increment_node, index_node]
+            .into_iter()
+            .flatten()
+        {
+            tree.remove(description_node);
+        }
+        for (i, index) in indices.iter().enumerate() {
+            let element = tree.deep_clone(xml_node);
+            tree[element].set_regex(None);
+            tree.replace_in_texts(element, &layout.placeholder, index);
+            if let (Some((base_offset, format)), Some(increment)) = (&base_offset, increment) {
+                let offset_node = tree.child_by_name(element, &layout.offset).unwrap();
+                let offset = base_offset + i as u64 * increment;
+                tree.set_text(offset_node, Some(format_integer(offset, format)));
+            }
+            tree.insert_node_before(parent, next, element);
+        }
+        tree.remove(xml_node);
+        Ok(())
+    }
+
+    /**
+    Folds similar children into array elements. Children are grouped by their name with the index replaced
+    by the placeholder. Each group with at least two members is reduced to its first member (ordered by offset)
+    which gets the children that describe the array.
+     **/
+    pub(super) fn collapse_array(
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+        collapse: &ArrayCollapse,
+    ) -> Result<(), String> {
+        let layout = &collapse.layout;
+        let mut groups: IndexMap<String, Vec<ArrayMember>> = IndexMap::new();
+        for child in tree.children(xml_node) {
+            match (&collapse.element, tree[child].name()) {
+                (_, None) => continue,
+                (Some(element), Some(name)) if !element.regex.is_match(name) => continue,
+                _ => {}
+            }
+            let (name_text, offset_text) = match tree.child_by_name(child, &collapse.name_child) {
+                None => continue,
+                Some(name_node) => (
+                    tree.text(name_node),
+                    tree.child_by_name(child, &layout.offset)
+                        .map(|offset_node| tree.text(offset_node)),
+                ),
+            };
+            let captures = match collapse.name.regex.captures(&name_text) {
+                None => continue,
+                Some(captures) => captures,
+            };
+            let index = captures
+                .name("index")
+                .or_else(|| captures.get(1))
+                .ok_or_else(|| {
+                    format!(
+                        "\"{}\" has no capture for the array index",
+                        collapse.name.regex.as_str()
+                    )
+                })?;
+            let array_name = format!(
+                "{}{}{}",
+                &name_text[..index.start()],
+                layout.placeholder,
+                &name_text[index.end()..]
+            );
+            let offset = match offset_text {
+                Some(text) => Some((parse_integer(&text)?, text)),
+                None => None,
+            };
+            groups.entry(array_name).or_default().push(ArrayMember {
+                node: child,
+                index: index.as_str().to_string(),
+                offset,
+            });
+        }
+        for (array_name, mut members) in groups {
+            if members.len() < 2 {
+                continue;
+            }
+            let increment = if members.iter().all(|m| m.offset.is_some()) {
+                members.sort_by_key(|m| m.offset.as_ref().unwrap().0);
+                let offsets: Vec<u64> = members
+                    .iter()
+                    .map(|m| m.offset.as_ref().unwrap().0)
+                    .collect();
+                let increment = offsets[1] - offsets[0];
+                if offsets.windows(2).any(|w| w[1] - w[0] != increment) {
+                    return Err(format!(
+                        "Elements of array \"{}\" are not equally spaced",
+                        array_name
+                    ));
+                }
+                Some(format_integer(
+                    increment,
+                    &members[0].offset.as_ref().unwrap().1,
+                ))
+            } else if members.iter().any(|m| m.offset.is_some()) {
+                return Err(format!(
+                    "Not all elements of array \"{}\" have an offset",
+                    array_name
+                ));
+            } else {
+                None
+            };
+            let indices: Vec<String> = members.iter().map(|m| m.index.clone()).collect();
+            let template = members[0].node;
+            for member in members.into_iter().skip(1) {
+                tree.remove(member.node);
+            }
+            let name_node = tree.child_by_name(template, &collapse.name_child).unwrap();
+            tree.set_text(name_node, Some(array_name));
+            let mut description = vec![
+                (layout.dim.clone(), indices.len().to_string()),
+                (layout.dim_increment.clone(), increment.unwrap_or_default()),
+                (layout.dim_index.clone(), format_dim_index(&indices)),
+            ];
+            if description[1].1.is_empty() {
+                description.remove(1);
+            }
+            let first = tree.first_child(template);
+            for (name, value) in description {
+                let child =
+                    tree.insert_before(template, first, XmlNodeData::Element(Element::new(name)));
+                tree.append(child, XmlNodeData::Text(value));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Parses decimal, hexadecimal (0x) and binary (#) numbers like they are used in CMSIS-SVD
+fn parse_integer(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let result = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix('#') {
+        u64::from_str_radix(binary, 2)
+    } else {
+        text.parse()
+    };
+    result.map_err(|_| format!("\"{}\" is not a valid number", text))
+}
+
+// Formats a number in the same notation as `template`
+fn format_integer(value: u64, template: &str) -> String {
+    let template = template.trim();
+    if template.starts_with("0x") || template.starts_with("0X") {
+        if template[2..].chars().any(|c| c.is_ascii_lowercase()) {
+            format!("{}{:x}", &template[..2], value)
+        } else {
+            format!("{}{:X}", &template[..2], value)
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+// Parses a dimIndex like "0-3", "A-D" or "a,b,c"
+fn parse_dim_index(text: &str) -> Vec<String> {
+    let text = text.trim();
+    if let Some((start, end)) = text.split_once('-') {
+        if let (Ok(start), Ok(end)) = (start.parse::<u64>(), end.parse::<u64>()) {
+            return (start..=end).map(|i| i.to_string()).collect();
+        }
+        let (mut start_chars, mut end_chars) = (start.chars(), end.chars());
+        if let (Some(start), None, Some(end), None) = (
+            start_chars.next(),
+            start_chars.next(),
+            end_chars.next(),
+            end_chars.next(),
+        ) {
+            if start.is_ascii_uppercase() && end.is_ascii_uppercase() {
+                return (start..=end).map(|c| c.to_string()).collect();
+            }
+        }
+    }
+    text.split(',').map(|s| s.trim().to_string()).collect()
+}
+
+fn format_dim_index(indices: &[String]) -> String {
+    let numbers: Vec<u64> = indices.iter().filter_map(|i| i.parse().ok()).collect();
+    if numbers.len() == indices.len() && numbers.windows(2).all(|w| w[1] == w[0] + 1) {
+        format!("{}-{}", numbers[0], numbers[numbers.len() - 1])
+    } else {
+        indices.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers() {
+        assert_eq!(parse_integer("0x1C"), Ok(28));
+        assert_eq!(parse_integer("#101"), Ok(5));
+        assert_eq!(parse_integer(" 12 "), Ok(12));
+        assert!(parse_integer("foo").is_err());
+        assert_eq!(format_integer(28, "0x10"), "0x1C");
+        assert_eq!(format_integer(28, "0x1a"), "0x1c");
+        assert_eq!(format_integer(28, "16"), "28");
+    }
+    #[test]
+    fn dim_indices() {
+        assert_eq!(parse_dim_index("0-2"), vec!["0", "1", "2"]);
+        assert_eq!(parse_dim_index("A-C"), vec!["A", "B", "C"]);
+        assert_eq!(parse_dim_index("x, y"), vec!["x", "y"]);
+        assert_eq!(format_dim_index(&parse_dim_index("3-5")), "3-5");
+        assert_eq!(format_dim_index(&parse_dim_index("A-C")), "A,B,C");
+    }
+}
@@ -1,83 +1,233 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use indexmap::IndexMap;
 
-use crate::patch_structure::{ComplexQuery, ComplexValue, ModificationValue, Query};
+use crate::patch_structure::{
+    AttributeModification, AttributeValue, ComplexQuery, ComplexValue, ForEach, MergeOptions,
+    ModificationValue, Modifier, NodePath, NodeQuery, PathSegment, Query, ReferenceExpression,
+    Regex, SimpleValueType, TableRef, TemplateUse, TextEdit, TextValue, Unmapped, ValueMap,
+};
 use crate::xml_structure::bidirectional_xml_tree::*;
 
-pub struct PatchProcessor {
-    pub xml_tree: XmlTree,
-}
+mod array;
+mod streamable;
+mod streaming;
+
+pub struct PatchProcessor;
 
 impl PatchProcessor {
-    pub fn new(xml_string: &str) -> PatchProcessor {
-        PatchProcessor {
-            xml_tree: XmlTree::new(&xmltree::Element::parse(xml_string.as_bytes()).unwrap()),
-        }
-    }
-    //ToDo: return Result-Type and do not panic anymore
-    pub fn apply(&mut self, patch: &Query) {
+    //ToDo: do not panic anymore
+    pub fn apply(xml_tree: &mut XmlTree, patch: &Query) -> Result<(), String> {
         //Go through patch rules and apply each on the given xml-structure
         //Work just on one xml structure. Each entry is executed on the result of the previous one
-        Self::apply_query(
-            &patch,
-            &Rc::new(RefCell::new(XmlNode {
-                parent: None,
-                //Encapsulate parsed xml-tree to simplify traversal
-                data: XmlNodeData::Element(Element {
-                    prefix: None,
-                    name: "internal_root".to_string(),
-                    attributes: Vec::new(),
-                    applied_regexp: None,
-                    children: vec![self.xml_tree.root.clone()],
-                }),
-            })),
-        );
+        //Encapsulate parsed xml-tree to simplify traversal and to allow replacing the root
+        let internal_root = xml_tree.create(XmlNodeData::Element(Element::new(
+            "internal_root".to_string(),
+        )));
+        let root = xml_tree.root;
+        xml_tree.append_node(internal_root, root);
+        let result = Self::apply_query(patch, xml_tree, internal_root);
+        let roots: Vec<_> = xml_tree
+            .children(internal_root)
+            .filter(|c| matches!(xml_tree[*c].data, XmlNodeData::Element(_)))
+            .collect();
+        if xml_tree.parent(root) == Some(internal_root) {
+            xml_tree.remove(root);
+        }
+        let result = match (result, roots.len()) {
+            (Err(e), _) => Err(e),
+            (Ok(()), 1) => {
+                xml_tree.remove(roots[0]);
+                xml_tree.root = roots[0];
+                Ok(())
+            }
+            (Ok(()), 0) => Err("The patch removes the root element".to_string()),
+            (Ok(()), n) => Err(format!(
+                "The patch leaves {} root elements, but a document must have exactly one",
+                n
+            )),
+        };
+        // Drops the internal root and the removed nodes
+        xml_tree.compact();
+        result
     }
     /**
     This method applies a QueryChildType on a given XML element. Depending on the type either:
       - a simple value is assigned
       - or the recursion will continue
      **/
-    fn apply_query(query: &Query, xml_node: &Rc<RefCell<XmlNode>>) {
+    fn apply_query(query: &Query, tree: &mut XmlTree, xml_node: NodeId) -> Result<(), String> {
         // Do we have a simple value assignment or sub-queries?
         match query {
             Query::Simple(v) => {
                 // Apply the simple value:
-                match v.to_xml_node(xml_node) {
+                match v.to_xml_node(tree, xml_node)? {
                     None => {
                         // If no XML node is returned, then the simple value indicates a removal of the current XML element:
-                        XmlNode::remove(xml_node.clone());
+                        tree.remove(xml_node);
                     }
                     Some(c) => {
-                        let children = xml_node.borrow().children();
-                        children.for_each(|c| {
-                            XmlNode::remove(c);
-                        });
-                        XmlTree::append(&xml_node, c);
+                        tree.clear_children(xml_node);
+                        tree.append(xml_node, c);
                     }
                 }
             }
-            Query::ComplexVec(v) => v
-                .iter()
-                .for_each(|q| Self::apply_complex_query(q, xml_node)),
-            Query::Complex(complex_query) => Self::apply_complex_query(&complex_query, &xml_node),
+            Query::ComplexVec(v) => {
+                for q in v {
+                    Self::apply_complex_query(q, tree, xml_node)?;
+                }
+            }
+            Query::Complex(complex_query) => {
+                Self::apply_complex_query(complex_query, tree, xml_node)?
+            }
         }
+        Ok(())
     }
 
-    fn apply_complex_query(complex_query: &ComplexQuery, xml_node: &Rc<RefCell<XmlNode>>) {
+    fn apply_complex_query(
+        complex_query: &ComplexQuery,
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+    ) -> Result<(), String> {
+        let tables: Vec<_> = complex_query
+            .tables
+            .iter()
+            .map(|(name, table)| Ok((name.clone(), table.entries()?)))
+            .collect::<Result<_, String>>()?;
+        let count = tables.len();
+        tree[xml_node].bind_tables(tables);
+        let result = Self::repeat(
+            complex_query.modifier.foreach.as_ref(),
+            tree,
+            xml_node,
+            |tree| Self::apply_complex_query_once(complex_query, tree, xml_node),
+        );
+        tree[xml_node].unbind_tables(count);
+        result
+    }
+    /**
+    Runs the body once or, if a `$foreach` is given, once per loop value.
+    The loop variables are bound on the current node while the body is running.
+     **/
+    fn repeat(
+        foreach: Option<&ForEach>,
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+        body: impl Fn(&mut XmlTree) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let foreach = match foreach {
+            None => return body(tree),
+            Some(foreach) => foreach,
+        };
+        for bindings in foreach.bindings(tree, xml_node)? {
+            Self::with_variables(tree, xml_node, bindings, &body)?;
+        }
+        Ok(())
+    }
+    fn with_variables(
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+        bindings: Vec<(String, String)>,
+        body: impl Fn(&mut XmlTree) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let count = bindings.len();
+        tree[xml_node].bind_variables(bindings);
+        let result = body(tree);
+        tree[xml_node].unbind_variables(count);
+        result
+    }
+    /**
+    Instantiates a template at the given node. The arguments are evaluated at the node
+    and bound to the template parameters while the template is applied.
+     **/
+    fn use_template(
+        template_use: &TemplateUse,
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+    ) -> Result<(), String> {
+        let template = match &template_use.resolved {
+            Some(template) => template,
+            None => {
+                return Err(format!(
+                    "Template \"{}\" is not resolved",
+                    template_use.template
+                ))
+            }
+        };
+        let bindings = template_use
+            .args
+            .iter()
+            .map(|(param, value)| {
+                let value = value
+                    .eval_to_string(tree, xml_node)?
+                    .ok_or_else(|| format!("Argument \"{}\" must not be empty", param))?;
+                Ok((param.clone(), value))
+            })
+            .collect::<Result<_, String>>()?;
+        Self::with_variables(tree, xml_node, bindings, |tree| {
+            if let Some(query) = &template.query {
+                Self::apply_query(query, tree, xml_node)?;
+            }
+            if let Some(modification) = &template.modification {
+                Self::modify(modification, tree, xml_node)?;
+            }
+            Ok(())
+        })
+    }
+    fn apply_complex_query_once(
+        complex_query: &ComplexQuery,
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+    ) -> Result<(), String> {
         let ComplexQuery {
             subqueries,
             modification,
             modifier,
+            comments,
+            cdatas,
+            processing_instructions,
+            expand_array,
+            collapse_array,
+            templates,
+            tables,
+            wrap,
+            unwrap,
+            otherwise,
         } = complex_query;
-        if subqueries.len() == 0
+        if let Some(filter) = &modifier.filter {
+            if !filter.matches(tree, xml_node)? {
+                return match otherwise {
+                    Some(otherwise) => Self::apply_complex_query(otherwise, tree, xml_node),
+                    None => Ok(()),
+                };
+            }
+        }
+        if subqueries.is_empty()
             && modification.is_none()
+            && comments.is_none()
+            && cdatas.is_none()
+            && processing_instructions.is_none()
+            && expand_array.is_none()
+            && collapse_array.is_none()
+            && templates.is_empty()
+            && tables.is_empty()
+            && wrap.is_none()
+            && !unwrap
+            && otherwise.is_none()
+            && modifier.use_template.is_none()
+            && modifier.copy_from.is_none()
+            && modifier.move_from.is_none()
+            && modifier.merge_from.is_none()
+            && modifier.merge.is_none()
+            && modifier.map.is_none()
             && modifier.copy.is_none()
             && modifier.move_to.is_none()
         {
             // If empty set is assigned to a query: Clear the corresponding element
-            xml_node.borrow_mut().clear_children();
+            tree.clear_children(xml_node);
         } else {
+            if let Some(template_use) = &modifier.use_template {
+                Self::use_template(template_use, tree, xml_node)?;
+            }
+            Self::pull(modifier, tree, xml_node)?;
             for (regex, query) in subqueries {
                 //What do we get for each found query?
                 //  - List of selection structures (selection list) that contains
@@ -97,132 +247,494 @@ impl PatchProcessor {
                 //What will we do for each found subelement?
                 //  1. Run filter ($if). If filter is not matching: Skip!
                 //  2. Run apply_query_child_type for each elemment in selection list by appending the path by their individual name
-                let children = xml_node.borrow_mut().children();
+                // Plain names are looked up in the name index instead of testing every child
+                let children: Vec<NodeId> = match regex.as_literal() {
+                    Some(literal) => tree.children_by_name(xml_node, literal).collect(),
+                    None => tree.children(xml_node).collect(),
+                };
                 for child_candidate in children {
-                    let name = match &child_candidate.borrow().name() {
-                        Some(name) => Some(name.clone()),
-                        None => None,
-                    };
-                    match name {
-                        Some(name) => {
-                            if regex.regex.is_match(name.as_str()) {
-                                child_candidate
-                                    .borrow_mut()
-                                    .set_regex(Some(regex.regex.clone()));
-                                Self::apply_query(&query, &child_candidate);
-                                child_candidate.borrow_mut().set_regex(None);
-                            }
-                        }
-                        None => {}
+                    // Children removed by a previous sibling are skipped
+                    if tree.parent(child_candidate).is_none() {
+                        continue;
+                    }
+                    if tree[child_candidate]
+                        .name()
+                        .is_some_and(|name| regex.regex.is_match(name))
+                    {
+                        tree[child_candidate].set_regex(Some(regex.regex.clone()));
+                        let result = Self::apply_query(query, tree, child_candidate);
+                        tree[child_candidate].set_regex(None);
+                        result?;
                     }
                 }
             }
+            //  Rewrite or remove the selected non-element children
+            if let Some(node_query) = comments {
+                Self::apply_node_query(node_query, tree, xml_node, |d| {
+                    matches!(d, XmlNodeData::Comment(_))
+                })?;
+            }
+            if let Some(node_query) = cdatas {
+                Self::apply_node_query(node_query, tree, xml_node, |d| {
+                    matches!(d, XmlNodeData::CData(_))
+                })?;
+            }
+            if let Some(node_query) = processing_instructions {
+                Self::apply_node_query(node_query, tree, xml_node, |d| {
+                    matches!(d, XmlNodeData::ProcessingInstruction(_, _))
+                })?;
+            }
             //  3. Run applyModifications on current path
-            match &modification {
-                None => {}
-                Some(value_type) => {
-                    Self::modify(&value_type, &xml_node);
-                }
+            if let Some(value_type) = modification {
+                Self::modify(value_type, tree, xml_node)?;
+            }
+            if let Some(map) = &modifier.map {
+                Self::map_value(map, tree, xml_node)?;
             }
             //  4. Run move/copy on current path
-            match &modifier.copy {
-                None => {}
-                Some(copy_expression) => {
-                    XmlNode::move_copy_node(&xml_node, copy_expression, MoveCopyAction::Copy)
-                }
+            if let Some(copy_expression) = &modifier.copy {
+                tree.move_copy_node(xml_node, copy_expression, MoveCopyAction::Copy)?;
+            }
+            if let Some(move_expression) = &modifier.move_to {
+                tree.move_copy_node(xml_node, move_expression, MoveCopyAction::Move)?;
+            }
+            if let Some(wrapper) = wrap {
+                let name = wrapper.evaluate(tree, xml_node)?;
+                tree.wrap(xml_node, name)?;
+            }
+            //  5. Fold or unfold arrays
+            if let Some(collapse) = collapse_array {
+                Self::collapse_array(tree, xml_node, collapse)?;
             }
-            match &modifier.move_to {
-                None => {}
-                Some(move_expression) => {
-                    XmlNode::move_copy_node(&xml_node, move_expression, MoveCopyAction::Move)
+            if let Some(layout) = expand_array {
+                Self::expand_array(tree, xml_node, layout)?;
+            }
+            if *unwrap {
+                tree.unwrap(xml_node)?;
+            }
+        }
+        Ok(())
+    }
+    fn apply_node_query(
+        node_query: &NodeQuery,
+        tree: &mut XmlTree,
+        xml_node: NodeId,
+        is_selected: fn(&XmlNodeData) -> bool,
+    ) -> Result<(), String> {
+        let children: Vec<NodeId> = tree.children(xml_node).collect();
+        for child in children {
+            let content = match &tree[child].data {
+                data if is_selected(data) => data.content().map(String::from),
+                _ => None,
+            };
+            let value = match content.and_then(|c| node_query.value_for(&c)) {
+                None => continue,
+                Some(value) => value,
+            };
+            match value.eval_to_string(tree, xml_node)? {
+                None => {
+                    tree.remove(child);
+                }
+                Some(new_content) => {
+                    tree[child].data.set_content(new_content);
                 }
             }
         }
+        Ok(())
     }
-    fn modify(value_type: &ModificationValue, current_node: &Rc<RefCell<XmlNode>>) {
+    fn modify(
+        value_type: &ModificationValue,
+        tree: &mut XmlTree,
+        current_node: NodeId,
+    ) -> Result<(), String> {
         match value_type {
             ModificationValue::SimpleValue(v) => {
-                current_node.borrow_mut().clear_children();
-                match v.to_xml_node(&current_node) {
-                    None => {}
-                    Some(n) => {
-                        XmlTree::append(current_node, n);
-                    }
+                tree.clear_children(current_node);
+                if let Some(n) = v.to_xml_node(tree, current_node)? {
+                    tree.append(current_node, n);
                 }
             }
             ModificationValue::ComplexValue(complex_value) => {
-                Self::modify_by_complex_value(&current_node, complex_value)
+                Self::modify_by_complex_value(tree, current_node, complex_value)?
+            }
+            ModificationValue::ComplexValueVec(v) => {
+                for complex_value in v {
+                    Self::modify_by_complex_value(tree, current_node, complex_value)?;
+                }
             }
-            ModificationValue::ComplexValueVec(v) => v.iter().for_each(|complex_value| {
-                Self::modify_by_complex_value(&current_node, complex_value)
-            }),
         }
+        Ok(())
     }
 
-    fn modify_by_complex_value(current_node: &&Rc<RefCell<XmlNode>>, complex_value: &ComplexValue) {
+    fn modify_text(
+        text_value: &TextValue,
+        tree: &mut XmlTree,
+        current_node: NodeId,
+    ) -> Result<(), String> {
+        match text_value {
+            TextValue::Set(value) => {
+                let text = value.eval_to_string(tree, current_node)?;
+                tree.set_text(current_node, text)
+            }
+            TextValue::Edit(TextEdit {
+                set,
+                prepend,
+                append,
+            }) => {
+                if let Some(value) = set {
+                    let text = value.eval_to_string(tree, current_node)?;
+                    tree.set_text(current_node, text);
+                }
+                if let Some(text) = Self::evaluate_optional(prepend, tree, current_node)? {
+                    tree.prepend_text(current_node, text);
+                }
+                if let Some(text) = Self::evaluate_optional(append, tree, current_node)? {
+                    tree.append_text(current_node, text);
+                }
+            }
+        }
+        Ok(())
+    }
+    // Evaluates a value that may be missing; both a missing value and `~` give `None`
+    fn evaluate_optional(
+        value: &Option<SimpleValueType>,
+        tree: &XmlTree,
+        current_node: NodeId,
+    ) -> Result<Option<String>, String> {
+        match value {
+            Some(value) => value.eval_to_string(tree, current_node),
+            None => Ok(None),
+        }
+    }
+
+    fn modify_by_complex_value(
+        tree: &mut XmlTree,
+        current_node: NodeId,
+        complex_value: &ComplexValue,
+    ) -> Result<(), String> {
+        Self::repeat(
+            complex_value.modifier.foreach.as_ref(),
+            tree,
+            current_node,
+            |tree| Self::modify_by_complex_value_once(tree, current_node, complex_value),
+        )
+    }
+    fn modify_by_complex_value_once(
+        tree: &mut XmlTree,
+        current_node: NodeId,
+        complex_value: &ComplexValue,
+    ) -> Result<(), String> {
+        if !Self::value_applies(complex_value, tree, current_node)? {
+            return match &complex_value.otherwise {
+                Some(otherwise) => Self::modify_by_complex_value(tree, current_node, otherwise),
+                None => Ok(()),
+            };
+        }
+        if let Some(template_use) = &complex_value.modifier.use_template {
+            Self::use_template(template_use, tree, current_node)?;
+        }
+        Self::pull(&complex_value.modifier, tree, current_node)?;
         let ComplexValue {
+            modifier,
             subvalues,
             attributes,
+            text,
+            append_text,
+            comment,
+            cdata,
+            processing_instructions,
             ..
         } = complex_value;
         for (mod_type, value_type) in subvalues {
+            let name = mod_type.identifier.evaluate(tree, current_node)?;
+            let key = match &mod_type.key {
+                Some(key) => Some((key.name.as_str(), key.value.evaluate(tree, current_node)?)),
+                None => None,
+            };
             let mut updated = false;
-            if mod_type.mod_type.is_modify() {
-                for child in current_node.borrow().children() {
-                    //ToDo: Evaluation must be applied correctly
-                    let name = child.borrow().name();
-                    if name.is_some() && name.unwrap() == mod_type.identifier.evaluate(current_node)
-                    {
-                        updated = true;
-                        Self::modify(value_type, &child);
+            if !mod_type.mod_type.is_add() {
+                let children: Vec<_> = tree
+                    .children_by_name(current_node, &name)
+                    .filter(|child| {
+                        key.as_ref()
+                            .is_none_or(|(key, value)| tree.has_key(*child, key, value))
+                    })
+                    .collect();
+                if let (Some((key, value)), true) = (&key, children.len() > 1) {
+                    return Err(format!(
+                        "More than one \"{}\" is matching {}={}",
+                        name, key, value
+                    ));
+                }
+                for child in children {
+                    updated = true;
+                    // Existing children are left untouched by `?name`
+                    if !mod_type.mod_type.is_add_if_absent() {
+                        Self::modify(value_type, tree, child)?;
+                    }
+                }
+            }
+            if !updated && !mod_type.mod_type.is_replace() {
+                let new_child = tree.append(current_node, XmlNodeData::Element(Element::new(name)));
+                if let Some((key, value)) = &key {
+                    tree.set_key(new_child, key, value.clone());
+                }
+                // A conditional value only keeps the created child if the child satisfies the condition
+                let applies = match value_type {
+                    ModificationValue::SimpleValue(_) => true,
+                    ModificationValue::ComplexValue(v) => {
+                        Self::value_or_else_applies(v, tree, new_child)?
+                    }
+                    ModificationValue::ComplexValueVec(v) => {
+                        let mut applies = false;
+                        for v in v {
+                            applies = applies || Self::value_or_else_applies(v, tree, new_child)?;
+                        }
+                        applies
                     }
+                };
+                if applies {
+                    Self::modify(value_type, tree, new_child)?;
+                } else {
+                    tree.remove(new_child);
                 }
             }
-            if updated == false && !mod_type.mod_type.is_replace() {
-                let new_child = XmlTree::append(
+        }
+        if let Some(attributes) = attributes {
+            Self::modify_attributes(tree, current_node, attributes)?;
+        }
+        if let Some(text_value) = text {
+            Self::modify_text(text_value, tree, current_node)?;
+        }
+        if let Some(text) = Self::evaluate_optional(append_text, tree, current_node)? {
+            tree.append_text(current_node, text);
+        }
+        if let Some(text) = Self::evaluate_optional(comment, tree, current_node)? {
+            tree.append(current_node, XmlNodeData::Comment(text));
+        }
+        if let Some(text) = Self::evaluate_optional(cdata, tree, current_node)? {
+            tree.append(current_node, XmlNodeData::CData(text));
+        }
+        if let Some(processing_instructions) = processing_instructions {
+            for (target, data) in processing_instructions {
+                let data = data.eval_to_string(tree, current_node)?;
+                tree.append(
                     current_node,
-                    XmlNodeData::Element(Element {
-                        prefix: None,
-                        name: mod_type.identifier.evaluate(current_node),
-                        attributes: Vec::new(),
-                        applied_regexp: None,
-                        children: vec![],
-                    }),
+                    XmlNodeData::ProcessingInstruction(target.clone(), data),
                 );
-                Self::modify(value_type, &new_child);
-            }
-        }
-        match attributes {
-            Some(attributes) => {
-                for (patch_attribute_name, patch_attribute_value) in attributes {
-                    match &mut current_node.borrow_mut().data {
-                        XmlNodeData::Element(e) => {
-                            match (
-                                e.attributes
-                                    .iter_mut()
-                                    .find(|(key, _)| key == patch_attribute_name)
-                                    .map(|(_, v)| v),
-                                patch_attribute_value.eval_to_string(current_node),
-                            ) {
-                                (None, None) => {
-                                    //In this case, an unavailable attribute should be removed. We could throw an error here
-                                }
-                                (Some(_), None) => e
-                                    .attributes
-                                    .retain(|(name, _)| name != patch_attribute_name),
-                                (None, Some(value)) => {
-                                    e.attributes.push((patch_attribute_name.clone(), value))
-                                }
-                                (Some(target_attribute_value), Some(value)) => {
-                                    *target_attribute_value = value
-                                }
-                            }
+            }
+        }
+        if let Some(map) = &modifier.map {
+            Self::map_value(map, tree, current_node)?;
+        }
+        if let Some(copy_expression) = &modifier.copy {
+            tree.move_copy_node(current_node, copy_expression, MoveCopyAction::Copy)?;
+        }
+        if let Some(move_expression) = &modifier.move_to {
+            tree.move_copy_node(current_node, move_expression, MoveCopyAction::Move)?;
+        }
+        Ok(())
+    }
+    /// Translates the text or an attribute of the node through the table of the map
+    fn map_value(map: &ValueMap, tree: &mut XmlTree, xml_node: NodeId) -> Result<(), String> {
+        let table = match &map.table {
+            TableRef::Inline(table) => table.entries()?,
+            TableRef::Named(name) => tree
+                .table(xml_node, name)
+                .ok_or_else(|| format!("Unknown table \"{}\"", name))?,
+        };
+        let value = match &map.attribute {
+            Some(attribute) => match tree[xml_node].get_attribute(attribute) {
+                Some(value) => value,
+                None => return Ok(()),
+            },
+            None => tree.text(xml_node),
+        };
+        let mapped = match table.get(&value).or(map.default.as_ref()) {
+            Some(mapped) => mapped.clone(),
+            None if map.unmapped == Unmapped::Error => {
+                return Err(format!("No mapping for \"{}\"", value))
+            }
+            None => return Ok(()),
+        };
+        match &map.attribute {
+            Some(attribute) => {
+                tree[xml_node].set_attribute(attribute, mapped);
+            }
+            None => tree.set_text(xml_node, Some(mapped)),
+        }
+        Ok(())
+    }
+    fn pull(modifier: &Modifier, tree: &mut XmlTree, xml_node: NodeId) -> Result<(), String> {
+        if let Some(path) = &modifier.copy_from {
+            tree.pull_nodes(xml_node, path, MoveCopyAction::Copy)?;
+        }
+        if let Some(path) = &modifier.move_from {
+            tree.pull_nodes(xml_node, path, MoveCopyAction::Move)?;
+        }
+        if let Some(path) = &modifier.merge_from {
+            tree.merge_nodes(xml_node, &MergeOptions::from(path.clone()))?;
+        }
+        if let Some(options) = &modifier.merge {
+            tree.merge_nodes(xml_node, options)?;
+        }
+        Ok(())
+    }
+    fn value_applies(
+        complex_value: &ComplexValue,
+        tree: &XmlTree,
+        current_node: NodeId,
+    ) -> Result<bool, String> {
+        match &complex_value.modifier.filter {
+            None => Ok(true),
+            Some(filter) => filter.matches(tree, current_node),
+        }
+    }
+    fn value_or_else_applies(
+        complex_value: &ComplexValue,
+        tree: &XmlTree,
+        current_node: NodeId,
+    ) -> Result<bool, String> {
+        if Self::value_applies(complex_value, tree, current_node)? {
+            return Ok(true);
+        }
+        match &complex_value.otherwise {
+            Some(otherwise) => Self::value_or_else_applies(otherwise, tree, current_node),
+            None => Ok(false),
+        }
+    }
+
+    fn modify_attributes(
+        tree: &mut XmlTree,
+        current_node: NodeId,
+        attributes: &IndexMap<Regex, AttributeValue>,
+    ) -> Result<(), String> {
+        for (attribute_regex, attribute_value) in attributes {
+            let (value, rename, move_to, required) = match attribute_value {
+                AttributeValue::Simple(value) => (Some(value), None, None, false),
+                AttributeValue::Complex(AttributeModification {
+                    value,
+                    rename,
+                    move_to,
+                    required,
+                }) => (value.as_ref(), rename.as_ref(), move_to.as_ref(), *required),
+            };
+            let mut matching_names: Vec<String> = tree[current_node]
+                .attribute_names()
+                .into_iter()
+                .filter(|name| attribute_regex.regex.is_match(name))
+                .collect();
+            if matching_names.is_empty() {
+                // Unavailable attributes can only be created if their name is given literally
+                let new_value = match value {
+                    Some(value) => value.eval_to_string(tree, current_node)?,
+                    None => None,
+                };
+                match (attribute_regex.as_literal(), new_value) {
+                    (Some(name), Some(new_value)) => {
+                        tree[current_node].set_attribute(name, new_value);
+                        matching_names.push(name.to_string());
+                    }
+                    _ if required => {
+                        return Err(format!(
+                            "No attribute is matching \"{}\"",
+                            attribute_regex.regex.as_str()
+                        ))
+                    }
+                    _ => {}
+                }
+            } else if let Some(value) = value {
+                for name in &matching_names {
+                    tree[current_node]
+                        .set_attribute_match(Some((attribute_regex.regex.clone(), name.clone())));
+                    let new_value = value.eval_to_string(tree, current_node);
+                    let current = &mut tree[current_node];
+                    let new_value = match new_value {
+                        Ok(new_value) => new_value,
+                        Err(e) => {
+                            current.set_attribute_match(None);
+                            return Err(e);
+                        }
+                    };
+                    match new_value {
+                        None => {
+                            current.remove_attribute(name);
+                        }
+                        Some(new_value) => {
+                            current.set_attribute(name, new_value);
                         }
-                        _ => {}
                     }
+                    current.set_attribute_match(None);
+                }
+                if value == &SimpleValueType::Remove {
+                    continue;
                 }
             }
-            None => {}
+            for name in &matching_names {
+                tree[current_node]
+                    .set_attribute_match(Some((attribute_regex.regex.clone(), name.clone())));
+                let result = Self::rename_move_attribute(tree, current_node, name, rename, move_to);
+                tree[current_node].set_attribute_match(None);
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_move_attribute(
+        tree: &mut XmlTree,
+        current_node: NodeId,
+        name: &str,
+        rename: Option<&ReferenceExpression>,
+        move_to: Option<&ReferenceExpression>,
+    ) -> Result<(), String> {
+        let mut name = name.to_string();
+        if let Some(rename) = rename {
+            let new_name = rename.evaluate(tree, current_node)?;
+            tree[current_node].rename_attribute(&name, &new_name);
+            name = new_name;
+        }
+        if let Some(move_to) = move_to {
+            // The path is relative to the current element, the last segment is the new attribute name
+            let mut segments = move_to.split_path();
+            let new_name = match segments.pop() {
+                Some(new_name) => new_name.evaluate(tree, current_node)?,
+                None => String::new(),
+            };
+            let new_name = if new_name.is_empty() {
+                name.clone()
+            } else {
+                new_name
+            };
+            let path = NodePath {
+                segments: segments
+                    .into_iter()
+                    .map(|name| PathSegment { name, key: None })
+                    .collect(),
+                content_only: false,
+            };
+            let options = PathOptions {
+                create: true,
+                multiple: false,
+            };
+            let target_node = match tree
+                .resolve_path(current_node, current_node, &path, options)?
+                .first()
+            {
+                Some(target) => tree.create_missing(target),
+                None => return Err(format!("No element is matching \"{}\"", path)),
+            };
+            let value = match tree[current_node].remove_attribute(&name) {
+                Some(value) => value,
+                None => return Err(format!("Attribute \"{}\" could not be moved", name)),
+            };
+            if !tree[target_node].set_attribute(&new_name, value) {
+                return Err(format!(
+                    "Attribute \"{}\" can only be moved to an element",
+                    name
+                ));
+            }
         }
+        Ok(())
     }
 }
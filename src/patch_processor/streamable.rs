@@ -0,0 +1,438 @@
+use crate::patch_processor::PatchProcessor;
+use crate::patch_structure::{
+    AttributeModification, AttributeValue, ComplexQuery, ComplexValue, Filter, ForEach, LoopValues,
+    ModificationValue, Modifier, NodePath, NodeQuery, Query, ReferenceExpression, Segment,
+    SimpleValueType, TemplateUse, TextEdit, TextValue,
+};
+use std::convert::TryFrom;
+
+/**
+Decides up front whether a patch can be applied while the XML is streamed.
+
+Queries that only select children (no directive besides subqueries) are followed while the elements
+are read. The first query that changes an element makes this element a *unit*: the element is read
+into a tree, patched like in tree mode and written immediately. This is only correct if the patch of a
+unit stays inside the unit, so every path in it (references, `$move`/`$copy`, pulled paths, lookups)
+must not leave the element. Depths are counted from the unit element, which has depth 0.
+ **/
+impl PatchProcessor {
+    pub fn is_streamable(patch: &Query) -> bool {
+        // The root element cannot be a unit, as it is the whole document
+        navigation(patch).is_some_and(|queries| {
+            queries.into_iter().all(|complex_query| {
+                complex_query
+                    .subqueries
+                    .values()
+                    .all(|query| navigation(query).is_some())
+                    && navigation_streamable(complex_query)
+            })
+        })
+    }
+}
+
+/// Returns the queries if all of them only select children, otherwise `None`
+pub(super) fn navigation(query: &Query) -> Option<Vec<&ComplexQuery>> {
+    match query {
+        Query::Simple(_) => None,
+        Query::Complex(complex_query) => {
+            Some(vec![complex_query]).filter(|_| is_navigation(complex_query))
+        }
+        Query::ComplexVec(complex_queries) => Some(complex_queries.iter().collect())
+            .filter(|_| complex_queries.iter().all(is_navigation)),
+    }
+}
+
+fn is_navigation(complex_query: &ComplexQuery) -> bool {
+    let ComplexQuery {
+        subqueries,
+        modification,
+        modifier,
+        comments,
+        cdatas,
+        processing_instructions,
+        expand_array,
+        collapse_array,
+        // Templates are resolved while parsing and do not change the element
+        templates: _,
+        tables,
+        wrap,
+        unwrap,
+        otherwise,
+    } = complex_query;
+    // An empty query clears the element, so it is no navigation
+    !subqueries.is_empty()
+        && modification.is_none()
+        && *modifier == Modifier::new()
+        && comments.is_none()
+        && cdatas.is_none()
+        && processing_instructions.is_none()
+        && expand_array.is_none()
+        && collapse_array.is_none()
+        && tables.is_empty()
+        && wrap.is_none()
+        && !unwrap
+        && otherwise.is_none()
+}
+
+fn navigation_streamable(complex_query: &ComplexQuery) -> bool {
+    complex_query
+        .subqueries
+        .values()
+        .all(|query| match navigation(query) {
+            Some(queries) => queries.into_iter().all(navigation_streamable),
+            None => query_streamable(query, 0),
+        })
+}
+
+fn query_streamable(query: &Query, depth: usize) -> bool {
+    match query {
+        Query::Simple(value) => value_streamable(value, depth),
+        Query::Complex(complex_query) => complex_query_streamable(complex_query, depth),
+        Query::ComplexVec(complex_queries) => complex_queries
+            .iter()
+            .all(|complex_query| complex_query_streamable(complex_query, depth)),
+    }
+}
+
+fn complex_query_streamable(complex_query: &ComplexQuery, depth: usize) -> bool {
+    let ComplexQuery {
+        subqueries,
+        modification,
+        modifier,
+        comments,
+        cdatas,
+        processing_instructions,
+        expand_array: _,
+        collapse_array: _,
+        templates: _,
+        tables: _,
+        wrap,
+        unwrap: _,
+        otherwise,
+    } = complex_query;
+    modifier_streamable(modifier, depth)
+        && subqueries
+            .values()
+            .all(|query| query_streamable(query, depth + 1))
+        && modification
+            .as_ref()
+            .is_none_or(|value| modification_streamable(value, depth))
+        && [comments, cdatas, processing_instructions]
+            .iter()
+            .all(|node_query| match node_query {
+                None => true,
+                Some(NodeQuery::All(value)) => value_streamable(value, depth),
+                Some(NodeQuery::Matching(values)) => {
+                    values.values().all(|value| value_streamable(value, depth))
+                }
+            })
+        && wrap
+            .as_ref()
+            .is_none_or(|wrap| expression_streamable(wrap, depth))
+        && otherwise
+            .as_ref()
+            .is_none_or(|otherwise| complex_query_streamable(otherwise, depth))
+}
+
+fn modification_streamable(modification: &ModificationValue, depth: usize) -> bool {
+    match modification {
+        ModificationValue::SimpleValue(value) => value_streamable(value, depth),
+        ModificationValue::ComplexValue(complex_value) => {
+            complex_value_streamable(complex_value, depth)
+        }
+        ModificationValue::ComplexValueVec(complex_values) => complex_values
+            .iter()
+            .all(|complex_value| complex_value_streamable(complex_value, depth)),
+    }
+}
+
+fn complex_value_streamable(complex_value: &ComplexValue, depth: usize) -> bool {
+    let ComplexValue {
+        modifier,
+        subvalues,
+        attributes,
+        text,
+        append_text,
+        comment,
+        cdata,
+        processing_instructions,
+        otherwise,
+    } = complex_value;
+    let text_streamable = match text {
+        None => true,
+        Some(TextValue::Set(value)) => value_streamable(value, depth),
+        Some(TextValue::Edit(TextEdit {
+            set,
+            prepend,
+            append,
+        })) => [set, prepend, append]
+            .iter()
+            .all(|value| optional_value_streamable(value, depth)),
+    };
+    modifier_streamable(modifier, depth)
+        && subvalues.iter().all(|(identifier, value)| {
+            expression_streamable(&identifier.identifier, depth)
+                && identifier
+                    .key
+                    .as_ref()
+                    .is_none_or(|key| expression_streamable(&key.value, depth))
+                && modification_streamable(value, depth + 1)
+        })
+        && attributes.as_ref().is_none_or(|attributes| {
+            attributes
+                .values()
+                .all(|attribute| attribute_streamable(attribute, depth))
+        })
+        && text_streamable
+        && [append_text, comment, cdata]
+            .iter()
+            .all(|value| optional_value_streamable(value, depth))
+        && processing_instructions.as_ref().is_none_or(|instructions| {
+            instructions
+                .values()
+                .all(|value| value_streamable(value, depth))
+        })
+        && otherwise
+            .as_ref()
+            .is_none_or(|otherwise| complex_value_streamable(otherwise, depth))
+}
+
+fn attribute_streamable(attribute: &AttributeValue, depth: usize) -> bool {
+    match attribute {
+        AttributeValue::Simple(value) => value_streamable(value, depth),
+        AttributeValue::Complex(AttributeModification {
+            value,
+            rename,
+            move_to,
+            required: _,
+        }) => {
+            optional_value_streamable(value, depth)
+                && rename
+                    .as_ref()
+                    .is_none_or(|rename| expression_streamable(rename, depth))
+                // The attribute is moved to the element at the path, the last segment is the new name
+                && move_to.as_ref().is_none_or(|move_to| {
+                    let mut path = move_to.split_path();
+                    path.pop();
+                    expression_streamable(move_to, depth) && path_stays_inside(&path, depth)
+                })
+        }
+    }
+}
+
+fn modifier_streamable(modifier: &Modifier, depth: usize) -> bool {
+    let Modifier {
+        filter,
+        move_to,
+        copy,
+        foreach,
+        use_template,
+        copy_from,
+        move_from,
+        merge_from,
+        merge,
+        map: _,
+    } = modifier;
+    filter
+        .as_ref()
+        .is_none_or(|filter| filter_streamable(filter, depth))
+        && move_to
+            .as_ref()
+            .is_none_or(|move_to| move_copy_streamable(move_to, depth, true))
+        && copy
+            .as_ref()
+            .is_none_or(|copy| move_copy_streamable(copy, depth, false))
+        && foreach
+            .as_ref()
+            .is_none_or(|foreach| foreach_streamable(foreach, depth))
+        && use_template
+            .as_ref()
+            .is_none_or(|template_use| template_use_streamable(template_use, depth))
+        && [copy_from, move_from, merge_from].iter().all(|path| {
+            path.as_ref()
+                .is_none_or(|path| node_path_streamable(path, depth))
+        })
+        && merge
+            .as_ref()
+            .is_none_or(|merge| node_path_streamable(&merge.from, depth))
+}
+
+/**
+The destination of `$move` and `$copy` is relative to the parent of the element.
+Renaming the unit element is fine, but copies of it would be appended to the parent outside the unit.
+ **/
+fn move_copy_streamable(expression: &ReferenceExpression, depth: usize, is_move: bool) -> bool {
+    let mut path = expression.split_path();
+    path.pop();
+    if !expression_streamable(expression, depth) {
+        return false;
+    }
+    match depth.checked_sub(1) {
+        Some(parent_depth) => path_stays_inside(&path, parent_depth),
+        None => is_move && path.is_empty(),
+    }
+}
+
+fn filter_streamable(filter: &Filter, depth: usize) -> bool {
+    match filter {
+        Filter::And(filters) | Filter::Or(filters) => filters
+            .iter()
+            .all(|filter| filter_streamable(filter, depth)),
+        Filter::Child((_, filter)) => filter_streamable(filter, depth + 1),
+        Filter::Expression(_, value) => value_streamable(value, depth),
+        Filter::Regex(_) | Filter::NotSet => true,
+    }
+}
+
+fn foreach_streamable(foreach: &ForEach, depth: usize) -> bool {
+    match &foreach.values {
+        LoopValues::Range(_) => true,
+        LoopValues::List(values) => values.iter().all(|value| value_streamable(value, depth)),
+        LoopValues::Map(values) => values.values().all(|value| value_streamable(value, depth)),
+    }
+}
+
+fn template_use_streamable(template_use: &TemplateUse, depth: usize) -> bool {
+    template_use
+        .args
+        .values()
+        .all(|value| value_streamable(value, depth))
+        && template_use.resolved.as_ref().is_some_and(|template| {
+            template
+                .query
+                .as_ref()
+                .is_none_or(|query| query_streamable(query, depth))
+                && template
+                    .modification
+                    .as_ref()
+                    .is_none_or(|modification| modification_streamable(modification, depth))
+        })
+}
+
+fn node_path_streamable(path: &NodePath, depth: usize) -> bool {
+    path.segments.iter().all(|segment| {
+        expression_streamable(&segment.name, depth)
+            && segment
+                .key
+                .as_ref()
+                .is_none_or(|key| expression_streamable(&key.value, depth))
+    }) && stays_inside(
+        path.segments.iter().map(|segment| literal(&segment.name)),
+        depth,
+    )
+}
+
+fn optional_value_streamable(value: &Option<SimpleValueType>, depth: usize) -> bool {
+    value
+        .as_ref()
+        .is_none_or(|value| value_streamable(value, depth))
+}
+
+fn value_streamable(value: &SimpleValueType, depth: usize) -> bool {
+    match value {
+        SimpleValueType::Pattern(expression) => expression_streamable(expression, depth),
+        _ => true,
+    }
+}
+
+/// Checks the back-references and lookups of an expression that is evaluated at the given depth
+fn expression_streamable(expression: &ReferenceExpression, depth: usize) -> bool {
+    expression.segments.iter().all(|segment| match segment {
+        Segment::String(_) => true,
+        Segment::Reference(reference) => {
+            if reference.path.starts_with('$') {
+                true
+            } else if let Some(call) = reference.path.strip_prefix('%') {
+                match call.split_once('(') {
+                    Some((_, argument)) => {
+                        let argument = argument.strip_suffix(')').unwrap_or(argument);
+                        argument.starts_with('$')
+                            || NodePath::try_from(argument.to_string())
+                                .is_ok_and(|path| node_path_streamable(&path, depth))
+                    }
+                    None => true,
+                }
+            } else {
+                let mut path: Vec<&str> = reference.path.split('/').collect();
+                if path.last() == Some(&"@") {
+                    path.pop();
+                }
+                stays_inside(path.into_iter().map(Some), depth)
+            }
+        }
+    })
+}
+
+fn path_stays_inside(path: &[ReferenceExpression], depth: usize) -> bool {
+    stays_inside(path.iter().map(literal), depth)
+}
+
+fn literal(expression: &ReferenceExpression) -> Option<&str> {
+    match expression.segments.as_slice() {
+        [] => Some(""),
+        [Segment::String(s)] => Some(s),
+        _ => None,
+    }
+}
+
+/**
+Follows the path segments from an element at the given depth and checks that the path never leaves the unit.
+Segments with references can evaluate to anything, so they are only accepted after the path has gone down.
+ **/
+fn stays_inside<'a>(path: impl Iterator<Item = Option<&'a str>>, depth: usize) -> bool {
+    let mut depth = depth as i64;
+    let mut gone_down = false;
+    for segment in path {
+        match segment {
+            Some("..") => depth -= 1,
+            Some(".") | Some("") => {}
+            Some(_) => {
+                depth += 1;
+                gone_down = true;
+            }
+            None if gone_down => depth += 1,
+            None => return false,
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch_structure;
+
+    fn streamable(patch: &str) -> bool {
+        PatchProcessor::is_streamable(&patch_structure::parse(patch).unwrap().unwrap())
+    }
+
+    #[test]
+    fn local_patches() {
+        assert!(streamable(
+            "device: { peripheral: { $if: { name: UART }, size: 32 } }"
+        ));
+        assert!(streamable(
+            "device: { peripheral: { name: \"[.:0]_new\" } }"
+        ));
+        assert!(streamable(
+            "device: { peripheral: { $if: { name: UART }, register: { $move: fields/ } } }"
+        ));
+        assert!(streamable("device: { peripheral: { $move: renamed } }"));
+        assert!(streamable("device: { peripheral: { $wrap: peripherals } }"));
+    }
+    #[test]
+    fn non_local_patches() {
+        assert!(!streamable("device: { $modify: { +name: x } }"));
+        assert!(!streamable(
+            "device: { peripheral: { $move: \"../other/\" } }"
+        ));
+        assert!(!streamable("device: { peripheral: { $copy: copied } }"));
+        assert!(!streamable("device: { peripheral: { name: \"[..:0]\" } }"));
+        assert!(!streamable(
+            "device: { peripheral: { $copy_from: \"../other\" } }"
+        ));
+    }
+}
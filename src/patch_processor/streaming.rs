@@ -0,0 +1,219 @@
+use std::fmt;
+use std::io::{Read, Write};
+
+use indexmap::IndexMap;
+use xml::common::XmlVersion;
+use xml::reader::{EventReader, ParserConfig, XmlEvent as ReaderEvent};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent as WriterEvent};
+
+use crate::patch_processor::streamable::navigation;
+use crate::patch_processor::PatchProcessor;
+use crate::patch_structure::{ComplexQuery, Query};
+use crate::xml_structure::bidirectional_xml_tree::*;
+
+impl PatchProcessor {
+    /**
+    Applies a streamable patch (see `is_streamable`) while the XML is read and writes the result immediately.
+    Elements that are only passed by the patch are copied event by event. Elements that are changed (units)
+    are read into a small tree, patched in tree mode and written, so only one unit is kept in memory at a time.
+    The result is the same as in tree mode.
+     **/
+    pub fn apply_streaming<R: Read, W: Write>(
+        patch: &Query,
+        reader: R,
+        writer: W,
+    ) -> Result<(), String> {
+        let root_queries = match navigation(patch) {
+            Some(queries) => queries,
+            None => return Err("The patch cannot be applied in streaming mode".to_string()),
+        };
+        let mut reader =
+            EventReader::new_with_config(reader, ParserConfig::new().ignore_comments(false));
+        let mut emitter = EventWriter::new_with_config(writer, EmitterConfig::new());
+        emitter
+            .write(WriterEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: None,
+                standalone: None,
+            })
+            .map_err(write_error)?;
+        // The queries for the children of the open elements, the first entry selects the root
+        let mut open = vec![root_queries];
+        loop {
+            match next_event(&mut reader)? {
+                ReaderEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let element = read_element(name, attributes);
+                    let queries = open.last().unwrap();
+                    let matching: Vec<&Query> = queries
+                        .iter()
+                        .flat_map(|complex_query| &complex_query.subqueries)
+                        .filter(|(regex, _)| regex.regex.is_match(&element.name))
+                        .map(|(_, query)| query)
+                        .collect();
+                    let child_queries: Option<Vec<Vec<&ComplexQuery>>> =
+                        matching.into_iter().map(navigation).collect();
+                    match child_queries {
+                        Some(child_queries) => {
+                            XmlTree::write_start(&element, &mut emitter).map_err(write_error)?;
+                            open.push(child_queries.concat());
+                        }
+                        None => {
+                            let (tree, parent) =
+                                Self::apply_to_unit(queries, element, &mut reader)?;
+                            for child in tree.children(parent) {
+                                tree.write_node(child, &mut emitter).map_err(write_error)?;
+                            }
+                        }
+                    }
+                }
+                ReaderEvent::EndElement { .. } => {
+                    open.pop();
+                    emitter
+                        .write(WriterEvent::end_element())
+                        .map_err(write_error)?;
+                }
+                ReaderEvent::EndDocument => break,
+                // Like in tree mode, only the content of the root element is kept
+                _ if open.len() == 1 => {}
+                ReaderEvent::Characters(s) | ReaderEvent::CData(s) => emitter
+                    .write(WriterEvent::Characters(&s))
+                    .map_err(write_error)?,
+                ReaderEvent::Comment(s) => emitter
+                    .write(WriterEvent::Comment(&s))
+                    .map_err(write_error)?,
+                ReaderEvent::ProcessingInstruction { name, data } => emitter
+                    .write(WriterEvent::ProcessingInstruction {
+                        name: &name,
+                        data: data.as_deref(),
+                    })
+                    .map_err(write_error)?,
+                ReaderEvent::StartDocument { .. } | ReaderEvent::Whitespace(_) => {}
+            }
+        }
+        emitter.into_inner().flush().map_err(write_error)
+    }
+    /**
+    Reads the rest of the element and applies the queries of its parent to it. The element is placed in
+    a stand-in for its parent, so it can be removed, replaced or wrapped like in tree mode.
+    Returns the tree and the stand-in, whose children are the result.
+     **/
+    fn apply_to_unit<R: Read>(
+        queries: &[&ComplexQuery],
+        element: Element,
+        reader: &mut EventReader<R>,
+    ) -> Result<(XmlTree, NodeId), String> {
+        // The stand-in has a parent itself, so the element is not treated as root element
+        let mut tree = XmlTree::with_root(Element::new("internal_root".to_string()));
+        let root = tree.root;
+        let parent = tree.append(
+            root,
+            XmlNodeData::Element(Element::new("internal_parent".to_string())),
+        );
+        let mut open = vec![tree.append(parent, XmlNodeData::Element(element))];
+        while let Some(node) = open.last().copied() {
+            match next_event(reader)? {
+                ReaderEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let child =
+                        tree.append(node, XmlNodeData::Element(read_element(name, attributes)));
+                    open.push(child);
+                }
+                ReaderEvent::EndElement { .. } => {
+                    open.pop();
+                }
+                // CDATA sections are read as text, like in tree mode
+                ReaderEvent::Characters(s) | ReaderEvent::CData(s) => {
+                    tree.append(node, XmlNodeData::Text(s));
+                }
+                ReaderEvent::Comment(s) => {
+                    tree.append(node, XmlNodeData::Comment(s));
+                }
+                ReaderEvent::ProcessingInstruction { name, data } => {
+                    tree.append(node, XmlNodeData::ProcessingInstruction(name, data));
+                }
+                _ => {}
+            }
+        }
+        for complex_query in queries {
+            Self::apply_complex_query(complex_query, &mut tree, parent)
+                .map_err(|e| format!("Error while applying patch: {}", e))?;
+        }
+        Ok((tree, parent))
+    }
+}
+
+fn write_error(e: impl fmt::Display) -> String {
+    format!("Error while generating XML result: {}", e)
+}
+
+fn next_event<R: Read>(reader: &mut EventReader<R>) -> Result<ReaderEvent, String> {
+    reader
+        .next()
+        .map_err(|e| format!("Error while reading XML: {}", e))
+}
+
+// Keeps the prefix and the local names of the attributes, like the tree mode
+fn read_element(
+    name: xml::name::OwnedName,
+    attributes: Vec<xml::attribute::OwnedAttribute>,
+) -> Element {
+    let attributes: IndexMap<String, String> = attributes
+        .into_iter()
+        .map(|attribute| (attribute.name.local_name, attribute.value))
+        .collect();
+    let mut element = Element::new(name.local_name);
+    element.prefix = name.prefix;
+    element.attributes = attributes.into_iter().collect();
+    element
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch_structure;
+
+    fn assert_same_as_tree_mode(xml: &str, patch: &str) {
+        let patch = patch_structure::parse(patch).unwrap().unwrap();
+        assert!(PatchProcessor::is_streamable(&patch));
+        let mut result = Vec::new();
+        PatchProcessor::apply_streaming(&patch, xml.as_bytes(), &mut result).unwrap();
+        let mut document = crate::Document::parse(xml).unwrap();
+        PatchProcessor::apply(&mut document.xml_tree, &patch).unwrap();
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            document.to_xml_string().unwrap()
+        );
+    }
+
+    #[test]
+    fn unchanged_content() {
+        assert_same_as_tree_mode(
+            r#"<!-- header --><p:device xmlns:p="urn:x" a="1"><![CDATA[<raw>]]> text &amp; <!-- c --><?pi data?><empty></empty></p:device>"#,
+            "device: { other: x }",
+        );
+    }
+    #[test]
+    fn changed_units() {
+        assert_same_as_tree_mode(
+            r#"<device><peripheral><name>UART</name><size>8</size></peripheral><peripheral><name>SPI</name><size>8</size></peripheral><cpu>M0</cpu></device>"#,
+            indoc::indoc!(
+                r#"
+                device:
+                  - peripheral:
+                      $if: { name: UART }
+                      size: 16
+                      $wrap: uart
+                  - peripheral:
+                      $move: periph
+                    cpu: ~
+                  - "p.*":
+                      $if: { size: 8 }
+                      name: "[..:0]_[.:0]"
+                "#
+            ),
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use crate::patch_structure::regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Names of the child elements that describe an array of elements, defaulting to CMSIS-SVD
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArrayLayout {
+    pub dim: String,
+    pub dim_increment: String,
+    pub dim_index: String,
+    pub offset: String,
+    pub placeholder: String,
+}
+
+impl Default for ArrayLayout {
+    fn default() -> Self {
+        ArrayLayout {
+            dim: "dim".to_string(),
+            dim_increment: "dimIncrement".to_string(),
+            dim_index: "dimIndex".to_string(),
+            offset: "addressOffset".to_string(),
+            placeholder: "%s".to_string(),
+        }
+    }
+}
+
+/// Folds similar siblings into one array element.
+///
+/// Siblings are selected by their element name and by the text of their name child,
+/// which must match `name` with a capture (named `index` or the first one) for the array index.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+pub struct ArrayCollapse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub element: Option<Regex>,
+    pub name: Regex,
+    #[serde(default = "ArrayCollapse::default_name_child")]
+    pub name_child: String,
+    #[serde(flatten)]
+    pub layout: ArrayLayout,
+}
+
+impl ArrayCollapse {
+    fn default_name_child() -> String {
+        "name".to_string()
+    }
+}
@@ -1,10 +1,14 @@
 use core::fmt;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 
-use serde::de;
+use serde::{de, ser, Serialize};
 
 use crate::patch_structure::regex::Regex;
 use crate::patch_structure::SimpleValueType;
+use crate::xml_structure::bidirectional_xml_tree::{NodeId, XmlTree};
 
+#[derive(Clone, Copy)]
 pub enum FilterVariant {
     And,
     Or,
@@ -21,6 +25,60 @@ pub enum Filter {
 }
 
 impl Filter {
+    /**
+    Checks whether the filter matches the given element:
+      - regular expressions and comparisons are evaluated on the text of the element
+      - child filters match if any matching child element matches, `~` matches if there is no such child
+     **/
+    pub fn matches(&self, tree: &XmlTree, node: NodeId) -> Result<bool, String> {
+        Ok(match self {
+            Filter::And(filters) => {
+                for filter in filters {
+                    if !filter.matches(tree, node)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Filter::Or(filters) => {
+                for filter in filters {
+                    if filter.matches(tree, node)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+            Filter::Child((regex, filter)) => {
+                let candidates = match regex.as_literal() {
+                    Some(literal) => tree.children_by_name(node, literal),
+                    None => tree.children(node),
+                };
+                let mut children = candidates.filter(|c| {
+                    tree[*c]
+                        .name()
+                        .is_some_and(|name| regex.regex.is_match(name))
+                });
+                match filter.as_ref() {
+                    Filter::NotSet => children.next().is_none(),
+                    filter => {
+                        for child in children {
+                            if filter.matches(tree, child)? {
+                                return Ok(true);
+                            }
+                        }
+                        false
+                    }
+                }
+            }
+            Filter::Regex(regex) => regex.regex.is_match(&tree.text(node)),
+            Filter::Expression(comparator, value) => match value.eval_to_string(tree, node)? {
+                None => tree.text(node).is_empty(),
+                Some(value) => comparator.compare(&tree.text(node), &value),
+            },
+            Filter::NotSet => tree.text(node).is_empty(),
+        })
+    }
+
     fn expecting(formatter: &mut fmt::Formatter) -> fmt::Result {
         write!(formatter, "a pattern as string, a boolean, a (signed or unsigned) integer, again a query or an array of queries")
     }
@@ -37,23 +95,28 @@ impl Filter {
         Filter::Expression(Comparator::Equals, SimpleValueType::UnsignedInteger(v))
     }
 
-    fn visit_str(s: &str) -> Filter {
+    fn visit_f64(v: f64) -> Filter {
+        Filter::Expression(Comparator::Equals, SimpleValueType::Float(v))
+    }
+
+    fn visit_str(s: &str) -> Result<Filter, String> {
         if s.starts_with("^") {
-            return if s.ends_with("$") {
-                Filter::Regex(Regex::from(&s[1..s.len() - 1]))
+            let expression = if s.ends_with("$") {
+                &s[1..s.len() - 1]
             } else {
-                Filter::Regex(Regex::from(s.split_at(1).1))
+                s.split_at(1).1
             };
+            return Ok(Filter::Regex(Regex::try_from(expression.to_string())?));
         }
 
-        let (prefix, value) = if s.starts_with("<") {
-            (Comparator::LesserThan, s.split_at(1).1)
-        } else if s.starts_with("<=") {
+        let (prefix, value) = if s.starts_with("<=") {
             (Comparator::LesserEqual, s.split_at(2).1)
-        } else if s.starts_with(">") {
-            (Comparator::GreaterThan, s.split_at(1).1)
+        } else if s.starts_with("<") {
+            (Comparator::LesserThan, s.split_at(1).1)
         } else if s.starts_with(">=") {
             (Comparator::GreaterEqual, s.split_at(2).1)
+        } else if s.starts_with(">") {
+            (Comparator::GreaterThan, s.split_at(1).1)
         } else if s.starts_with("=") {
             (Comparator::Equals, s.split_at(1).1)
         } else if s.starts_with("!=") {
@@ -61,9 +124,10 @@ impl Filter {
         } else {
             (Comparator::Equals, s)
         };
-        let (prefix, value) = (prefix, serde_yaml::from_str(value).unwrap());
-
-        Filter::Expression(prefix, value)
+        Ok(Filter::Expression(
+            prefix,
+            SimpleValueType::parse_scalar(value)?,
+        ))
     }
 
     fn visit_unit() -> Filter {
@@ -116,7 +180,7 @@ impl Filter {
         while let Some(regex) = map.next_key()? {
             let regex: Regex = regex;
             let regex_str: String = regex.clone().into();
-            if regex_str == "^$and$".to_string() {
+            if regex_str == "$and" {
                 // Deserialize And....
                 let filter: Filter = map.next_value()?;
                 match filter_variant {
@@ -130,7 +194,7 @@ impl Filter {
                         }
                     },
                 }
-            } else if regex_str == "^$or$".to_string() {
+            } else if regex_str == "$or" {
                 // Deserialize Or....
                 let filter: OrFilter = map.next_value()?;
                 match filter_variant {
@@ -168,6 +232,13 @@ impl Filter {
     }
 }
 
+// Parses a filter string like `>4` or `^regex$` in the same way as in a patch file; panics if the regex is invalid
+impl From<&str> for Filter {
+    fn from(s: &str) -> Self {
+        Filter::visit_str(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Comparator {
     Equals,
@@ -178,6 +249,133 @@ pub enum Comparator {
     LesserEqual,
 }
 
+impl Comparator {
+    // Numbers (decimal or hexadecimal) are compared by value, everything else as strings
+    fn compare(&self, text: &str, value: &str) -> bool {
+        let ordering = match (parse_number(text), parse_number(value)) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(text.trim().cmp(value.trim())),
+        };
+        match ordering {
+            None => false,
+            Some(ordering) => match self {
+                Comparator::Equals => ordering == Ordering::Equal,
+                Comparator::EqualsNot => ordering != Ordering::Equal,
+                Comparator::GreaterThan => ordering == Ordering::Greater,
+                Comparator::GreaterEqual => ordering != Ordering::Less,
+                Comparator::LesserThan => ordering == Ordering::Less,
+                Comparator::LesserEqual => ordering != Ordering::Greater,
+            },
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(|n| n as f64),
+        None => s.parse().ok(),
+    }
+}
+
+impl Comparator {
+    fn prefix(&self) -> &'static str {
+        match self {
+            Comparator::Equals => "=",
+            Comparator::EqualsNot => "!=",
+            Comparator::GreaterThan => ">",
+            Comparator::GreaterEqual => ">=",
+            Comparator::LesserThan => "<",
+            Comparator::LesserEqual => "<=",
+        }
+    }
+}
+
+/**
+Serializes a filter in the form that is parsed back to the same filter. Sequences and maps with several
+entries are conjunctions below `$and` (or at the top level) and disjunctions below `$or`, so nested filters
+of the other kind are written as `$and:`/`$or:` maps.
+ **/
+struct SerializedFilter<'a> {
+    filter: &'a Filter,
+    variant: FilterVariant,
+}
+
+impl Serialize for Filter {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedFilter {
+            filter: self,
+            variant: FilterVariant::And,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'a> Serialize for SerializedFilter<'a> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{SerializeMap, SerializeSeq};
+        let nested = |filter| SerializedFilter {
+            filter,
+            variant: self.variant,
+        };
+        match (self.filter, self.variant) {
+            (Filter::And(filters), FilterVariant::And)
+            | (Filter::Or(filters), FilterVariant::Or) => {
+                let mut seq = serializer.serialize_seq(Some(filters.len()))?;
+                for filter in filters {
+                    seq.serialize_element(&nested(filter))?;
+                }
+                seq.end()
+            }
+            (Filter::And(_), FilterVariant::Or) | (Filter::Or(_), FilterVariant::And) => {
+                let (key, variant) = match self.filter {
+                    Filter::And(_) => ("$and", FilterVariant::And),
+                    _ => ("$or", FilterVariant::Or),
+                };
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(
+                    key,
+                    &SerializedFilter {
+                        filter: self.filter,
+                        variant,
+                    },
+                )?;
+                map.end()
+            }
+            (Filter::Child((regex, filter)), _) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(regex, &nested(filter))?;
+                map.end()
+            }
+            (Filter::Regex(regex), _) => {
+                serializer.serialize_str(&format!("^{}$", String::from(regex.clone())))
+            }
+            (Filter::Expression(Comparator::Equals, SimpleValueType::Boolean(b)), _) => {
+                serializer.serialize_bool(*b)
+            }
+            (Filter::Expression(Comparator::Equals, SimpleValueType::UnsignedInteger(n)), _) => {
+                serializer.serialize_u64(*n)
+            }
+            (Filter::Expression(Comparator::Equals, SimpleValueType::SignedInteger(n)), _) => {
+                serializer.serialize_i64(*n)
+            }
+            (Filter::Expression(comparator, value), _) => {
+                let value = value.to_scalar();
+                let value = value.as_str();
+                let plain = matches!(comparator, Comparator::Equals)
+                    && !value.starts_with(|c| "^<>=!".contains(c))
+                    && Filter::visit_str(value).as_ref() == Ok(self.filter);
+                if plain {
+                    serializer.serialize_str(value)
+                } else {
+                    serializer.serialize_str(&format!("{}{}", comparator.prefix(), value))
+                }
+            }
+            (Filter::NotSet, _) => serializer.serialize_unit(),
+        }
+    }
+}
+
 struct OrFilter {
     filter: Filter,
 }
@@ -210,9 +408,15 @@ impl<'de> de::Deserialize<'de> for OrFilter {
                 })
             }
 
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(OrFilter {
+                    filter: Filter::visit_f64(v),
+                })
+            }
+
             fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
                 Ok(OrFilter {
-                    filter: Filter::visit_str(s),
+                    filter: Filter::visit_str(s).map_err(E::custom)?,
                 })
             }
 
@@ -261,8 +465,12 @@ impl<'de> de::Deserialize<'de> for Filter {
                 Ok(Filter::visit_u64(v))
             }
 
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(Filter::visit_f64(v))
+            }
+
             fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
-                Ok(Filter::visit_str(s))
+                Filter::visit_str(s).map_err(E::custom)
             }
 
             fn visit_unit<E>(self) -> Result<Self::Value, E> {
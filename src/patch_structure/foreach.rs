@@ -0,0 +1,118 @@
+use crate::patch_structure::SimpleValueType;
+use crate::xml_structure::bidirectional_xml_tree::{NodeId, XmlTree};
+use indexmap::map::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Repeats the body of a query or value once for each loop value.
+///
+/// The loop variables are referenced by `[$name]` in every reference expression of the body.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForEach {
+    pub var: LoopVariable,
+    #[serde(rename = "in")]
+    pub values: LoopValues,
+}
+
+/// A single variable is bound to the value (the key for maps),
+/// a pair of variables is bound to the position (the key for maps) and the value.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LoopVariable {
+    Single(String),
+    Pair(String, String),
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LoopValues {
+    Range(LoopRange),
+    List(Vec<SimpleValueType>),
+    Map(IndexMap<String, SimpleValueType>),
+}
+
+/// A range of integers like `0..16` (exclusive) or `0..=15` (inclusive)
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LoopRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl TryFrom<String> for LoopRange {
+    type Error = String;
+
+    fn try_from(range: String) -> Result<Self, Self::Error> {
+        let error = || format!("\"{}\" is not a range like \"0..16\"", range);
+        let (start, end, inclusive) = match range.split_once("..=") {
+            Some((start, end)) => (start, end, true),
+            None => match range.split_once("..") {
+                Some((start, end)) => (start, end, false),
+                None => return Err(error()),
+            },
+        };
+        let start: i64 = start.trim().parse().map_err(|_| error())?;
+        let end: i64 = end.trim().parse().map_err(|_| error())?;
+        let end = match inclusive {
+            true => end.checked_add(1).ok_or_else(error)?,
+            false => end,
+        };
+        Ok(LoopRange { start, end })
+    }
+}
+
+impl From<LoopRange> for String {
+    fn from(range: LoopRange) -> Self {
+        format!("{}..{}", range.start, range.end)
+    }
+}
+
+impl ForEach {
+    /// Evaluates the loop values and returns the variable bindings of each iteration
+    pub fn bindings(
+        &self,
+        tree: &XmlTree,
+        current_node: NodeId,
+    ) -> Result<Vec<Vec<(String, String)>>, String> {
+        let evaluate = |value: &SimpleValueType| {
+            value
+                .eval_to_string(tree, current_node)?
+                .ok_or_else(|| "Loop values must not be empty".to_string())
+        };
+        let (entries, is_map): (Vec<(String, String)>, bool) = match &self.values {
+            LoopValues::Range(LoopRange { start, end }) => (
+                (*start..*end)
+                    .enumerate()
+                    .map(|(position, i)| (position.to_string(), i.to_string()))
+                    .collect(),
+                false,
+            ),
+            LoopValues::List(values) => (
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(position, v)| Ok((position.to_string(), evaluate(v)?)))
+                    .collect::<Result<_, String>>()?,
+                false,
+            ),
+            LoopValues::Map(values) => (
+                values
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), evaluate(v)?)))
+                    .collect::<Result<_, String>>()?,
+                true,
+            ),
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| match &self.var {
+                LoopVariable::Single(name) if is_map => vec![(name.clone(), key)],
+                LoopVariable::Single(name) => vec![(name.clone(), value)],
+                LoopVariable::Pair(key_name, value_name) => {
+                    vec![(key_name.clone(), key), (value_name.clone(), value)]
+                }
+            })
+            .collect())
+    }
+}
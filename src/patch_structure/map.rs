@@ -0,0 +1,281 @@
+use crate::patch_structure::{
+    serialize_some, ComplexQuery, ComplexValue, ModificationValue, Modifier, Query, Template,
+};
+use indexmap::map::IndexMap;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A lookup table, given inline or loaded from a YAML or CSV file (`file: access.csv`).
+///
+/// Files are not read when the patch is parsed, but by [`Table::load`] relative to the directory of the patch.
+/// CSV files have one `key,value` record per line without a header. Fields may be quoted,
+/// lines starting with `#` are skipped.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Table {
+    File {
+        file: String,
+        /// The entries of the file, `None` until the table is loaded
+        #[serde(skip)]
+        entries: Option<Arc<IndexMap<String, String>>>,
+    },
+    Inline(Arc<IndexMap<String, String>>),
+}
+
+impl Table {
+    /// Reads the file of the table, a relative path is resolved against `base_dir`
+    pub fn load(&mut self, base_dir: &Path) -> Result<(), String> {
+        if let Table::File { file, entries } = self {
+            let path = base_dir.join(&file);
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Could not read table \"{}\": {}", path.display(), e))?;
+            let loaded = if file.ends_with(".csv") {
+                Table::parse_csv(&content)
+            } else {
+                serde_yaml::from_str(&content).map_err(|e| e.to_string())
+            }
+            .map_err(|e| format!("Invalid table \"{}\": {}", path.display(), e))?;
+            *entries = Some(Arc::new(loaded));
+        }
+        Ok(())
+    }
+    /// Returns the entries, or an error if the file of the table is not loaded
+    pub fn entries(&self) -> Result<Arc<IndexMap<String, String>>, String> {
+        match self {
+            Table::File {
+                entries: Some(entries),
+                ..
+            } => Ok(entries.clone()),
+            Table::File {
+                file,
+                entries: None,
+            } => Err(format!("Table file \"{}\" is not loaded", file)),
+            Table::Inline(entries) => Ok(entries.clone()),
+        }
+    }
+    fn parse_csv(content: &str) -> Result<IndexMap<String, String>, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(content.as_bytes());
+        let mut entries = IndexMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            match (record.get(0), record.get(1), record.len()) {
+                (Some(key), Some(value), 2) => {
+                    entries.insert(key.to_string(), value.to_string());
+                }
+                _ => {
+                    let line = record.position().map_or(0, |p| p.line());
+                    return Err(format!("line {} is not a key,value record", line));
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Translates the text (or an attribute) of the current element through a table.
+///
+/// The table is either inline or the name of a table defined by `$tables`.
+/// Unmapped values are replaced by `default`, if given, and are kept or rejected otherwise.
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(from = "ValueMapForm")]
+pub struct ValueMap {
+    pub table: TableRef,
+    pub attribute: Option<String>,
+    pub default: Option<String>,
+    pub unmapped: Unmapped,
+}
+
+// A map of a named table without options is written in the short form `$map: access`
+impl Serialize for ValueMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let TableRef::Named(name) = &self.table {
+            if self.attribute.is_none()
+                && self.default.is_none()
+                && self.unmapped == Unmapped::default()
+            {
+                return serializer.serialize_str(name);
+            }
+        }
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("table", &self.table)?;
+        serialize_some(&mut map, "attribute", &self.attribute)?;
+        serialize_some(&mut map, "default", &self.default)?;
+        if self.unmapped != Unmapped::default() {
+            map.serialize_entry("unmapped", &self.unmapped)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TableRef {
+    Named(String),
+    Inline(Table),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmapped {
+    #[default]
+    Keep,
+    Error,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ValueMapForm {
+    Named(String),
+    Options {
+        table: TableRef,
+        attribute: Option<String>,
+        default: Option<String>,
+        #[serde(default)]
+        unmapped: Unmapped,
+    },
+}
+
+impl From<ValueMapForm> for ValueMap {
+    fn from(form: ValueMapForm) -> Self {
+        match form {
+            ValueMapForm::Named(name) => ValueMap {
+                table: TableRef::Named(name),
+                attribute: None,
+                default: None,
+                unmapped: Unmapped::default(),
+            },
+            ValueMapForm::Options {
+                table,
+                attribute,
+                default,
+                unmapped,
+            } => ValueMap {
+                table,
+                attribute,
+                default,
+                unmapped,
+            },
+        }
+    }
+}
+
+impl Query {
+    /**
+    Loads the files of all tables in the query, relative paths are resolved against `base_dir`.
+    The tables are loaded in the template definitions, which are resolved again afterwards,
+    so every `$use` shares the loaded definition.
+     **/
+    pub fn load_tables(&mut self, base_dir: &Path) -> Result<(), String> {
+        match self {
+            Query::Simple(_) => return Ok(()),
+            Query::Complex(complex_query) => complex_query.load_tables(base_dir)?,
+            Query::ComplexVec(complex_queries) => complex_queries
+                .iter_mut()
+                .try_for_each(|complex_query| complex_query.load_tables(base_dir))?,
+        }
+        self.resolve_templates(&mut Vec::new())
+    }
+    fn load_tables_of_subquery(&mut self, base_dir: &Path) -> Result<(), String> {
+        match self {
+            Query::Simple(_) => Ok(()),
+            Query::Complex(complex_query) => complex_query.load_tables(base_dir),
+            Query::ComplexVec(complex_queries) => complex_queries
+                .iter_mut()
+                .try_for_each(|complex_query| complex_query.load_tables(base_dir)),
+        }
+    }
+}
+
+impl ComplexQuery {
+    fn load_tables(&mut self, base_dir: &Path) -> Result<(), String> {
+        for table in self.tables.values_mut() {
+            table.load(base_dir)?;
+        }
+        for template in self.templates.values_mut() {
+            template.load_tables(base_dir)?;
+        }
+        self.modifier.load_tables(base_dir)?;
+        if let Some(modification) = &mut self.modification {
+            modification.load_tables(base_dir)?;
+        }
+        if let Some(otherwise) = &mut self.otherwise {
+            otherwise.load_tables(base_dir)?;
+        }
+        self.subqueries
+            .values_mut()
+            .try_for_each(|query| query.load_tables_of_subquery(base_dir))
+    }
+}
+
+impl ModificationValue {
+    fn load_tables(&mut self, base_dir: &Path) -> Result<(), String> {
+        match self {
+            ModificationValue::SimpleValue(_) => Ok(()),
+            ModificationValue::ComplexValue(complex_value) => complex_value.load_tables(base_dir),
+            ModificationValue::ComplexValueVec(complex_values) => complex_values
+                .iter_mut()
+                .try_for_each(|complex_value| complex_value.load_tables(base_dir)),
+        }
+    }
+}
+
+impl ComplexValue {
+    fn load_tables(&mut self, base_dir: &Path) -> Result<(), String> {
+        self.modifier.load_tables(base_dir)?;
+        if let Some(otherwise) = &mut self.otherwise {
+            otherwise.load_tables(base_dir)?;
+        }
+        self.subvalues
+            .values_mut()
+            .try_for_each(|value| value.load_tables(base_dir))
+    }
+}
+
+impl Template {
+    fn load_tables(&mut self, base_dir: &Path) -> Result<(), String> {
+        if let Some(query) = &mut self.query {
+            query.load_tables_of_subquery(base_dir)?;
+        }
+        if let Some(modification) = &mut self.modification {
+            modification.load_tables(base_dir)?;
+        }
+        Ok(())
+    }
+}
+
+impl Modifier {
+    fn load_tables(&mut self, base_dir: &Path) -> Result<(), String> {
+        if let Some(ValueMap {
+            table: TableRef::Inline(table),
+            ..
+        }) = &mut self.map
+        {
+            table.load(base_dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv() {
+        let entries = Table::parse_csv(
+            "# vendor,svd\nRW, read-write\n\nRO,read-only\n\"A,B\",\"x, \"\"y\"\"\"\n",
+        )
+        .unwrap();
+        assert_eq!(entries.get("RW").unwrap(), "read-write");
+        assert_eq!(entries.get("RO").unwrap(), "read-only");
+        assert_eq!(entries.get("A,B").unwrap(), "x, \"y\"");
+        assert!(Table::parse_csv("RW\n").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::patch_structure::NodePath;
+use serde::{Deserialize, Serialize};
+
+/// Recursively merges the elements found at `from` into the current element.
+///
+/// Children are matched by name and, if given, by the `key` child or attribute (`@id`).
+/// The policy decides which side wins if attributes or texts differ.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeOptions {
+    pub from: NodePath,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub policy: MergePolicy,
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergePolicy {
+    /// Keep the values of the current element
+    Ours,
+    /// Overwrite the values of the current element
+    #[default]
+    Theirs,
+    /// Fail on differing values
+    Error,
+}
+
+impl From<NodePath> for MergeOptions {
+    fn from(from: NodePath) -> Self {
+        MergeOptions {
+            from,
+            key: None,
+            policy: MergePolicy::default(),
+        }
+    }
+}
@@ -1,28 +1,157 @@
 use std::error;
+use std::path::Path;
 
-use serde::Deserialize;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize};
 
-pub use filter::{Comparator, Filter};
+pub use array::{ArrayCollapse, ArrayLayout};
+pub use filter::Filter;
+pub use foreach::{ForEach, LoopValues};
+pub use map::{Table, TableRef, Unmapped, ValueMap};
+pub use merge::{MergeOptions, MergePolicy};
 pub use modification_type::ModificationIdentifier;
+pub use node_path::{NodePath, PathSegment};
+pub use node_query::NodeQuery;
 pub use query::{ComplexQuery, Query};
 pub use reference_expression::ReferenceExpression;
+pub use refex_segment::Segment;
+pub use regex::{is_plain_name, Regex};
 pub use simple_value_type::SimpleValueType;
-pub use value::{ComplexValue, ModificationValue};
+pub use template::{Template, TemplateUse};
+pub use value::{
+    AttributeModification, AttributeValue, ComplexValue, ModificationValue, TextEdit, TextValue,
+};
 
+mod array;
 mod filter;
+mod foreach;
+mod map;
+mod merge;
 mod modification_type;
+mod node_path;
+mod node_query;
 mod query;
 mod reference_expression;
 mod refex_segment;
 mod regex;
 mod simple_value_type;
+mod template;
 mod value;
 
-pub fn parse(content: &String) -> Result<Option<Query>, Box<dyn error::Error>> {
-    if content.is_empty() {
+/// The formats that patches can be written in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PatchFormat {
+    /// YAML, the default format
+    Yaml,
+    /// JSON
+    Json,
+    /// TOML. As TOML has no null value, `~` (removal) cannot be expressed.
+    Toml,
+}
+
+impl PatchFormat {
+    /// Selects the format by the extension of the patch file (`.json`, `.toml`, YAML otherwise)
+    pub fn from_path(path: &str) -> PatchFormat {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("json") => PatchFormat::Json,
+            Some("toml") => PatchFormat::Toml,
+            _ => PatchFormat::Yaml,
+        }
+    }
+}
+
+pub fn parse(content: &str) -> Result<Option<Query>, Box<dyn error::Error>> {
+    parse_with_format(content, PatchFormat::Yaml)
+}
+
+pub fn parse_with_format(
+    content: &str,
+    format: PatchFormat,
+) -> Result<Option<Query>, Box<dyn error::Error>> {
+    if content.trim().is_empty() {
         return Ok(None);
     }
-    Ok(Some(serde_yaml::from_str(content)?))
+    let mut query: Query = match format {
+        PatchFormat::Yaml => serde_yaml::from_str(content)?,
+        PatchFormat::Json => serde_json::from_str(content)?,
+        PatchFormat::Toml => toml::from_str(content)?,
+    };
+    query.resolve_templates(&mut Vec::new())?;
+    Ok(Some(query))
+}
+
+/// Writes the patch as YAML that is parsed back to the same patch
+pub fn serialize(query: &Query) -> Result<String, Box<dyn error::Error>> {
+    let yaml = serde_yaml::to_string(query)?;
+    Ok(yaml.trim_start_matches("---\n").to_string())
+}
+
+/// Returns whether the YAML content has comments, which are lost when the patch is written back
+pub fn has_comments(content: &str) -> bool {
+    content.lines().any(|line| {
+        let mut chars = line.chars().peekable();
+        let mut quote = None;
+        // The last character outside of quotes that is not a whitespace, and whether a whitespace follows it
+        let mut last = None;
+        let mut spaced = true;
+        while let Some(c) = chars.next() {
+            match quote {
+                Some('\'') if c == '\'' && chars.peek() == Some(&'\'') => {
+                    chars.next();
+                }
+                Some('"') if c == '\\' => {
+                    chars.next();
+                }
+                Some(q) if c == q => {
+                    quote = None;
+                    last = Some(c);
+                    spaced = false;
+                }
+                Some(_) => {}
+                None if c.is_whitespace() => spaced = true,
+                None if c == '#' && spaced => return true,
+                None => {
+                    // A quote only starts a quoted scalar at the beginning of a value, not inside `it's`
+                    let starts_value = match last {
+                        None | Some('[') | Some('{') | Some(',') => true,
+                        Some(':') | Some('-') | Some('?') => spaced,
+                        _ => false,
+                    };
+                    if (c == '\'' || c == '"') && starts_value {
+                        quote = Some(c);
+                    }
+                    last = Some(c);
+                    spaced = false;
+                }
+            }
+        }
+        false
+    })
+}
+
+// Writes a map entry only if the value is set
+fn serialize_some<M, T>(map: &mut M, key: &str, value: &Option<T>) -> Result<(), M::Error>
+where
+    M: SerializeMap,
+    T: Serialize,
+{
+    match value {
+        Some(value) => map.serialize_entry(key, value),
+        None => Ok(()),
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+// Keeps an explicit `~` as `Some(...)` instead of treating it like a missing key
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 #[derive(Debug, PartialEq, Deserialize, Clone)]
@@ -33,6 +162,20 @@ pub struct Modifier {
     pub move_to: Option<ReferenceExpression>,
     #[serde(rename = "$copy")]
     pub copy: Option<ReferenceExpression>,
+    #[serde(rename = "$foreach")]
+    pub foreach: Option<ForEach>,
+    #[serde(rename = "$use")]
+    pub use_template: Option<TemplateUse>,
+    #[serde(rename = "$copy_from")]
+    pub copy_from: Option<NodePath>,
+    #[serde(rename = "$move_from")]
+    pub move_from: Option<NodePath>,
+    #[serde(rename = "$merge_from")]
+    pub merge_from: Option<NodePath>,
+    #[serde(rename = "$merge")]
+    pub merge: Option<MergeOptions>,
+    #[serde(rename = "$map")]
+    pub map: Option<ValueMap>,
 }
 
 impl Modifier {
@@ -41,6 +184,13 @@ impl Modifier {
             filter: None,
             move_to: None,
             copy: None,
+            foreach: None,
+            use_template: None,
+            copy_from: None,
+            move_from: None,
+            merge_from: None,
+            merge: None,
+            map: None,
         }
     }
     /*pub fn is_modifying(&self) -> bool {
@@ -50,8 +200,9 @@ impl Modifier {
 
 #[cfg(test)]
 mod tests {
+    use self::filter::Comparator;
     use self::regex::Regex;
-    use indexmap::indexmap;
+    use indexmap::{indexmap, IndexMap};
     use indoc::indoc;
 
     use super::*;
@@ -71,6 +222,16 @@ mod tests {
             let expected_result = Query::Complex(ComplexQuery {
                 modifier: Modifier::new(),
                 modification: None,
+                comments: None,
+                cdatas: None,
+                processing_instructions: None,
+                expand_array: None,
+                collapse_array: None,
+                templates: IndexMap::new(),
+                tables: IndexMap::new(),
+                wrap: None,
+                unwrap: false,
+                otherwise: None,
                 subqueries: [(
                     Regex::from("elementa"),
                     Query::Simple(simple_value_type.clone()),
@@ -171,8 +332,25 @@ mod tests {
                         filter: None,
                         move_to: None,
                         copy: None,
+                        foreach: None,
+                        use_template: None,
+                        copy_from: None,
+                        move_from: None,
+                        merge_from: None,
+                        merge: None,
+                        map: None,
                     },
                     modification: None,
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
                     subqueries: indexmap! { Regex::from("elementa") => Query::Simple(SimpleValueType::Pattern(ReferenceExpression::from("hello"))) },
                 },
                 ComplexQuery {
@@ -180,8 +358,25 @@ mod tests {
                         filter: None,
                         move_to: None,
                         copy: None,
+                        foreach: None,
+                        use_template: None,
+                        copy_from: None,
+                        move_from: None,
+                        merge_from: None,
+                        merge: None,
+                        map: None,
                     },
                     modification: None,
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
                     subqueries: indexmap! { Regex::from("elementa") => Query::Simple(SimpleValueType::Pattern(ReferenceExpression::from("world"))) },
                 },
             ]);
@@ -196,13 +391,39 @@ mod tests {
     }
     mod filter_tests {
         use super::*;
-        use indexmap::IndexMap;
+
+        #[test]
+        fn test_two_character_comparators() {
+            let filter: Filter = serde_yaml::from_str("'<=4'").unwrap();
+            assert_eq!(
+                filter,
+                Filter::Expression(Comparator::LesserEqual, SimpleValueType::UnsignedInteger(4))
+            );
+            let filter: Filter = serde_yaml::from_str("'>=4'").unwrap();
+            assert_eq!(
+                filter,
+                Filter::Expression(
+                    Comparator::GreaterEqual,
+                    SimpleValueType::UnsignedInteger(4)
+                )
+            );
+        }
 
         #[test]
         fn test_simple_filter() {
             let expected_result = Query::Complex(ComplexQuery {
                 modifier: Modifier::new(),
                 modification: None,
+                comments: None,
+                cdatas: None,
+                processing_instructions: None,
+                expand_array: None,
+                collapse_array: None,
+                templates: IndexMap::new(),
+                tables: IndexMap::new(),
+                wrap: None,
+                unwrap: false,
+                otherwise: None,
                 subqueries: indexmap! {
                     Regex::from("elementa") =>
                     Query::Complex(ComplexQuery {
@@ -238,9 +459,7 @@ mod tests {
                                 )),
                                 Filter::Child((
                                     Regex::from("subelement5"),
-                                    Box::new(Filter::Regex(Regex::from(
-                                        "some(pattern)?".to_string(),
-                                    ))),
+                                    Box::new(Filter::Regex(Regex::from("some(pattern)?"))),
                                 )),
                                 Filter::Child((
                                     Regex::from("subelement6"),
@@ -257,8 +476,25 @@ mod tests {
                             ])),
                             move_to: None,
                             copy: None,
+                            foreach: None,
+                            use_template: None,
+                            copy_from: None,
+                            move_from: None,
+                            merge_from: None,
+                            merge: None,
+                            map: None,
                         },
                         modification: None,
+                        comments: None,
+                        cdatas: None,
+                        processing_instructions: None,
+                        expand_array: None,
+                        collapse_array: None,
+                        templates: IndexMap::new(),
+                        tables: IndexMap::new(),
+                        wrap: None,
+                        unwrap: false,
+                        otherwise: None,
                         subqueries: IndexMap::new(),
                     },
                 )},
@@ -284,6 +520,16 @@ mod tests {
             let expected_result = Query::Complex(ComplexQuery {
                 modifier: Modifier::new(),
                 modification: None,
+                comments: None,
+                cdatas: None,
+                processing_instructions: None,
+                expand_array: None,
+                collapse_array: None,
+                templates: IndexMap::new(),
+                tables: IndexMap::new(),
+                wrap: None,
+                unwrap: false,
+                otherwise: None,
                 subqueries: indexmap! {
                     Regex::from("elementa") =>
                     Query::Complex(ComplexQuery {
@@ -304,8 +550,25 @@ mod tests {
                             ])),
                             move_to: None,
                             copy: None,
+                            foreach: None,
+                            use_template: None,
+                            copy_from: None,
+                            move_from: None,
+                            merge_from: None,
+                            merge: None,
+                            map: None,
                         },
                         modification: None,
+                        comments: None,
+                        cdatas: None,
+                        processing_instructions: None,
+                        expand_array: None,
+                        collapse_array: None,
+                        templates: IndexMap::new(),
+                        tables: IndexMap::new(),
+                        wrap: None,
+                        unwrap: false,
+                        otherwise: None,
                         subqueries: IndexMap::new(),
                     },
                 )},
@@ -327,6 +590,16 @@ mod tests {
             let expected_result = Query::Complex(ComplexQuery {
                 modifier: Modifier::new(),
                 modification: None,
+                comments: None,
+                cdatas: None,
+                processing_instructions: None,
+                expand_array: None,
+                collapse_array: None,
+                templates: IndexMap::new(),
+                tables: IndexMap::new(),
+                wrap: None,
+                unwrap: false,
+                otherwise: None,
                 subqueries: indexmap! {
                     Regex::from("elementa") =>
                     Query::Complex(ComplexQuery {
@@ -373,8 +646,25 @@ mod tests {
                             ])),
                             move_to: None,
                             copy: None,
+                            foreach: None,
+                            use_template: None,
+                            copy_from: None,
+                            move_from: None,
+                            merge_from: None,
+                            merge: None,
+                            map: None,
                         },
                         modification: None,
+                        comments: None,
+                        cdatas: None,
+                        processing_instructions: None,
+                        expand_array: None,
+                        collapse_array: None,
+                        templates: IndexMap::new(),
+                        tables: IndexMap::new(),
+                        wrap: None,
+                        unwrap: false,
+                        otherwise: None,
                         subqueries: IndexMap::new(),
                     })
                 },
@@ -398,6 +688,16 @@ mod tests {
             let expected_result = Query::Complex(ComplexQuery {
                 modifier: Modifier::new(),
                 modification: None,
+                comments: None,
+                cdatas: None,
+                processing_instructions: None,
+                expand_array: None,
+                collapse_array: None,
+                templates: IndexMap::new(),
+                tables: IndexMap::new(),
+                wrap: None,
+                unwrap: false,
+                otherwise: None,
                 subqueries: indexmap! {
                     Regex::from("elementa") =>
                     Query::Complex( ComplexQuery{
@@ -453,8 +753,25 @@ mod tests {
 
                             move_to: None,
                             copy: None,
+                            foreach: None,
+                            use_template: None,
+                            copy_from: None,
+                            move_from: None,
+                            merge_from: None,
+                            merge: None,
+                            map: None,
                         },
                         modification: None,
+                        comments: None,
+                        cdatas: None,
+                        processing_instructions: None,
+                        expand_array: None,
+                        collapse_array: None,
+                        templates: IndexMap::new(),
+                        tables: IndexMap::new(),
+                        wrap: None,
+                        unwrap: false,
+                        otherwise: None,
                         subqueries: IndexMap::new(),
                     }),
                 },
@@ -482,13 +799,22 @@ mod tests {
         use value::ModificationValue;
 
         use super::*;
-        use indexmap::IndexMap;
 
         #[test]
         fn test_modifiers_simple() {
             let expected_result = Query::Complex(ComplexQuery {
                 modifier: Modifier::new(),
                 modification: None,
+                comments: None,
+                cdatas: None,
+                processing_instructions: None,
+                expand_array: None,
+                collapse_array: None,
+                templates: IndexMap::new(),
+                tables: IndexMap::new(),
+                wrap: None,
+                unwrap: false,
+                otherwise: None,
                 subqueries: indexmap! {
                 Regex::from("elementa") =>
                     Query::Complex(ComplexQuery {
@@ -499,21 +825,21 @@ mod tests {
                                         Regex::from("subelement1"),
                                         Box::new(Filter::Expression(
                                             Comparator::Equals,
-                                            SimpleValueType::Pattern(ReferenceExpression::from("pattern1".to_string())),
+                                            SimpleValueType::Pattern(ReferenceExpression::from("pattern1")),
                                         )),
                                     )),
                                     Filter::Child((
                                         Regex::from("subelement2"),
                                         Box::new(Filter::Expression(
                                             Comparator::Equals,
-                                            SimpleValueType::Pattern(ReferenceExpression::from("pattern2".to_string())),
+                                            SimpleValueType::Pattern(ReferenceExpression::from("pattern2")),
                                         )),
                                     )),
                                     Filter::Child((
                                         Regex::from("subelement3"),
                                         Box::new(Filter::Expression(
                                             Comparator::Equals,
-                                            SimpleValueType::Pattern(ReferenceExpression::from("pattern3".to_string())),
+                                            SimpleValueType::Pattern(ReferenceExpression::from("pattern3")),
                                         )),
                                     )),
                                 ]),
@@ -521,37 +847,54 @@ mod tests {
                                     Regex::from("subelement4"),
                                     Box::new(Filter::Expression(
                                         Comparator::Equals,
-                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern4".to_string())),
+                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern4")),
                                     )),
                                 )),
                                 Filter::Child((
                                     Regex::from("subelement5"),
                                     Box::new(Filter::Expression(
                                         Comparator::Equals,
-                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern5".to_string())),
+                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern5")),
                                     )),
                                 )),
                                 Filter::Child((
                                     Regex::from("subelement6"),
                                     Box::new(Filter::Expression(
                                         Comparator::Equals,
-                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern6".to_string())),
+                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern6")),
                                     )),
                                 )),
                                 Filter::Child((
                                     Regex::from("subelement7"),
                                     Box::new(Filter::Expression(
                                         Comparator::Equals,
-                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern7".to_string())),
+                                        SimpleValueType::Pattern(ReferenceExpression::from("pattern7")),
                                     )),
                                 )),
                             ])),
                             move_to: Some(ReferenceExpression::from("some other place")),
                             copy: Some(ReferenceExpression::from("some place")),
+                            foreach: None,
+                            use_template: None,
+                            copy_from: None,
+                            move_from: None,
+                            merge_from: None,
+                            merge: None,
+                            map: None,
                         },
                         modification: Some(ModificationValue::SimpleValue(
                             SimpleValueType::Pattern(ReferenceExpression::from("hello world")),
                         )),
+                        comments: None,
+                        cdatas: None,
+                        processing_instructions: None,
+                        expand_array: None,
+                        collapse_array: None,
+                        templates: IndexMap::new(),
+                        tables: IndexMap::new(),
+                        wrap: None,
+                        unwrap: false,
+                        otherwise: None,
                         subqueries: IndexMap::new(),
                     }),
                 },
@@ -584,6 +927,12 @@ mod tests {
                     modifier: Modifier::new(),
                     modification: Some(ModificationValue::ComplexValue( ComplexValue{
                         modifier: Modifier::new(),
+                        text: None,
+                        append_text: None,
+                        comment: None,
+                        cdata: None,
+                        processing_instructions: None,
+                        otherwise: None,
                         subvalues: indexmap!{
                             ModificationIdentifier::from("elementb") =>
                             ModificationValue::SimpleValue(SimpleValueType::Pattern(
@@ -596,6 +945,16 @@ mod tests {
                         },
                         attributes: None,
                     })),
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
                     subqueries: IndexMap::new(),
                 }),
             });
@@ -618,6 +977,12 @@ mod tests {
                     modification: Some(ModificationValue::ComplexValueVec(vec![
                         ComplexValue{
                             modifier: Modifier::new(),
+                            text: None,
+                            append_text: None,
+                            comment: None,
+                            cdata: None,
+                            processing_instructions: None,
+                            otherwise: None,
                             subvalues: indexmap!{
                                 ModificationIdentifier::from("elementb") =>
                                 ModificationValue::SimpleValue(SimpleValueType::Pattern(
@@ -628,6 +993,12 @@ mod tests {
                         },
                         ComplexValue{
                             modifier: Modifier::new(),
+                            text: None,
+                            append_text: None,
+                            comment: None,
+                            cdata: None,
+                            processing_instructions: None,
+                            otherwise: None,
                             subvalues: indexmap!{
                                 ModificationIdentifier::from("elementb") =>
                                 ModificationValue::SimpleValue(SimpleValueType::Pattern(
@@ -637,6 +1008,16 @@ mod tests {
                             attributes: None
                         },
                     ])),
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
                     subqueries: IndexMap::new(),
                 })
             });
@@ -650,12 +1031,57 @@ mod tests {
                 expected_result,
             );
         }
+        #[test]
+        fn test_modify_text() {
+            let expected_result = Query::from(indexmap! {
+                Regex::from("elementa") =>
+                Query::Complex(ComplexQuery {
+                    modifier: Modifier::new(),
+                    modification: Some(ModificationValue::ComplexValue(ComplexValue {
+                        modifier: Modifier::new(),
+                        attributes: None,
+                        text: Some(TextValue::Edit(TextEdit {
+                            set: Some(SimpleValueType::Remove),
+                            prepend: None,
+                            append: Some(SimpleValueType::Pattern(ReferenceExpression::from("hello"))),
+                        })),
+                        append_text: Some(SimpleValueType::UnsignedInteger(5)),
+                        comment: None,
+                        cdata: None,
+                        processing_instructions: None,
+                        otherwise: None,
+                        subvalues: IndexMap::new(),
+                    })),
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
+                    subqueries: IndexMap::new(),
+                }),
+            });
+            complex_test_helper(
+                indoc! {r#"
+                        elementa:
+                          $modify:
+                            $text:
+                              $set: ~
+                              $append: hello
+                            $append_text: 5
+                      "#},
+                expected_result,
+            );
+        }
     }
     mod attribute_tests {
         use value::ModificationValue;
 
         use super::*;
-        use indexmap::IndexMap;
 
         #[test]
         fn test_simple_attributes_modification() {
@@ -665,13 +1091,29 @@ mod tests {
                     modifier: Modifier::new(),
                     modification: Some(ModificationValue::ComplexValue(ComplexValue {
                         modifier: Modifier::new(),
+                        text: None,
+                        append_text: None,
+                        comment: None,
+                        cdata: None,
+                        processing_instructions: None,
+                        otherwise: None,
                         subvalues: IndexMap::new(),
                         attributes: Some(indexmap!{
-                            "attribute1".to_string() =>
-                            SimpleValueType::Pattern(ReferenceExpression::from("hello")),
-                            "attribute2".to_string() => SimpleValueType::Remove
+                            Regex::from("attribute1") =>
+                            AttributeValue::Simple(SimpleValueType::Pattern(ReferenceExpression::from("hello"))),
+                            Regex::from("attribute2") => AttributeValue::Simple(SimpleValueType::Remove)
                         }),
                     })),
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
                     subqueries: IndexMap::new(),
                 }),
             });
@@ -686,5 +1128,289 @@ mod tests {
                 expected_result,
             );
         }
+        #[test]
+        fn test_regex_and_renaming_attributes() {
+            let expected_result = Query::from(indexmap! {
+                Regex::from("elementa") =>
+                Query::Complex( ComplexQuery {
+                    modifier: Modifier::new(),
+                    modification: Some(ModificationValue::ComplexValue(ComplexValue {
+                        modifier: Modifier::new(),
+                        otherwise: None,
+                        subvalues: IndexMap::new(),
+                        attributes: Some(indexmap!{
+                            Regex::from("data-.*") => AttributeValue::Simple(SimpleValueType::Remove),
+                            Regex::from("id") => AttributeValue::Complex(AttributeModification {
+                                value: None,
+                                rename: Some(ReferenceExpression::from("xml:id")),
+                                move_to: None,
+                                required: false,
+                            }),
+                            Regex::from("old") => AttributeValue::Complex(AttributeModification {
+                                value: Some(SimpleValueType::Remove),
+                                rename: None,
+                                move_to: None,
+                                required: true,
+                            }),
+                        }),
+                        text: None,
+                        append_text: None,
+                        comment: None,
+                        cdata: None,
+                        processing_instructions: None,
+                    })),
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
+                    subqueries: IndexMap::new(),
+                }),
+            });
+            complex_test_helper(
+                indoc! {r#"
+                        elementa:
+                          $modify:
+                            $attributes:
+                              data-.*: ~
+                              id:
+                                $rename: xml:id
+                              old:
+                                $value: ~
+                                $required: true
+                      "#},
+                expected_result,
+            );
+        }
+    }
+    mod node_tests {
+        use value::ModificationValue;
+
+        use super::*;
+
+        #[test]
+        fn test_node_queries_and_values() {
+            let expected_result = Query::from(indexmap! {
+                Regex::from("elementa") =>
+                Query::Complex(ComplexQuery {
+                    modifier: Modifier::new(),
+                    modification: Some(ModificationValue::ComplexValue(ComplexValue {
+                        modifier: Modifier::new(),
+                        attributes: None,
+                        text: None,
+                        append_text: None,
+                        comment: Some(SimpleValueType::Pattern(ReferenceExpression::from("hello"))),
+                        cdata: None,
+                        processing_instructions: Some(indexmap! {
+                            "target".to_string() => SimpleValueType::Remove
+                        }),
+                        otherwise: None,
+                        subvalues: IndexMap::new(),
+                    })),
+                    comments: Some(NodeQuery::All(SimpleValueType::Remove)),
+                    cdatas: None,
+                    processing_instructions: Some(NodeQuery::Matching(indexmap! {
+                        Regex::from("xml-.*") =>
+                        SimpleValueType::Pattern(ReferenceExpression::from("data"))
+                    })),
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
+                    subqueries: IndexMap::new(),
+                }),
+            });
+            complex_test_helper(
+                indoc! {r#"
+                        elementa:
+                          $comments: ~
+                          $pis:
+                            xml-.*: data
+                          $modify:
+                            $comment: hello
+                            $pi:
+                              target: ~
+                      "#},
+                expected_result,
+            );
+        }
+    }
+    mod foreach_tests {
+        use super::*;
+        use crate::patch_structure::foreach::{LoopRange, LoopValues, LoopVariable};
+
+        #[test]
+        fn test_foreach() {
+            let expected_result = Query::from(indexmap! {
+                Regex::from("registers") =>
+                Query::Complex(ComplexQuery {
+                    modifier: Modifier {
+                        foreach: Some(ForEach {
+                            var: LoopVariable::Single("i".to_string()),
+                            values: LoopValues::Range(LoopRange { start: 0, end: 16 }),
+                        }),
+                        ..Modifier::new()
+                    },
+                    modification: None,
+                    comments: None,
+                    cdatas: None,
+                    processing_instructions: None,
+                    expand_array: None,
+                    collapse_array: None,
+                    templates: IndexMap::new(),
+                    tables: IndexMap::new(),
+                    wrap: None,
+                    unwrap: false,
+                    otherwise: None,
+                    subqueries: indexmap! {
+                        Regex::from("register") => Query::from(indexmap! {
+                            Regex::from("name") => Query::Simple(SimpleValueType::Pattern(ReferenceExpression::from("CH[$i]")))
+                        })
+                    },
+                }),
+            });
+            complex_test_helper(
+                indoc! {r#"
+                        registers:
+                          $foreach: { var: i, in: "0..16" }
+                          register:
+                            name: CH[$i]
+                      "#},
+                expected_result,
+            );
+            let pair: ForEach = serde_yaml::from_str(indoc! {r#"
+                        var: [key, value]
+                        in: [a, 2]
+                      "#})
+            .unwrap();
+            assert_eq!(
+                pair.values,
+                LoopValues::List(vec![
+                    SimpleValueType::Pattern(ReferenceExpression::from("a")),
+                    SimpleValueType::UnsignedInteger(2)
+                ])
+            );
+            assert_eq!(
+                pair.var,
+                LoopVariable::Pair("key".to_string(), "value".to_string())
+            );
+            assert!(serde_yaml::from_str::<ForEach>("{ var: i, in: \"0-16\" }").is_err());
+        }
+    }
+    mod merge_tests {
+        use super::*;
+
+        #[test]
+        fn test_merge_options() {
+            let options: MergeOptions = serde_yaml::from_str(indoc! {r#"
+                        from: ../board
+                        key: "@id"
+                      "#})
+            .unwrap();
+            assert_eq!(
+                options,
+                MergeOptions {
+                    from: NodePath::from("../board"),
+                    key: Some("@id".to_string()),
+                    policy: MergePolicy::Theirs,
+                }
+            );
+            assert!(serde_yaml::from_str::<MergeOptions>("{ from: x, policy: mine }").is_err());
+        }
+    }
+    mod serialize_tests {
+        use super::*;
+
+        fn round_trip(patch: &str) {
+            let parsed = parse(patch).unwrap().unwrap();
+            let serialized = serialize(&parsed).unwrap();
+            assert_eq!(
+                parse(&serialized).unwrap().unwrap(),
+                parsed,
+                "{}",
+                serialized
+            );
+            assert_eq!(
+                serialize(&parse(&serialized).unwrap().unwrap()).unwrap(),
+                serialized
+            );
+        }
+
+        #[test]
+        fn test_round_trip_resources() {
+            round_trip(include_str!("../../resources/test.yaml"));
+            round_trip(include_str!("../../resources/test2.yaml"));
+        }
+        #[test]
+        fn test_round_trip() {
+            round_trip(indoc! {r#"
+                    element\d+:
+                      $if:
+                        - name: ^CR.*$
+                        - $or: [">4", "<=0x10", { size: ~ }, { $and: [a, "!=b"] }]
+                        - enabled: true
+                      $else: { $modify: { disabled: true } }
+                      $comments: ~
+                      $pis: { xml-.*: data }
+                      $modify:
+                        $attributes:
+                          id: { $value: ~, $rename: key, $required: true }
+                          size: 32
+                        $text: { $prepend: "[..:1]_", $append: '\[x\]' }
+                        $comment: hello
+                        ~field[name=EN_[$i]]: { $foreach: { var: i, in: "0..=3" }, bits: "1.5" }
+                        +list: [{ a: 1 }, { b: -2 }]
+                        ?optional: ~
+                      subelement: { $move: "../other/[.:0]", $copy: "" }
+                      pulled: { $copy_from: "../reg[@id=3]/", $merge: { from: ../x, key: name, policy: error } }
+                    "#});
+            round_trip(indoc! {r#"
+                    device:
+                      $templates:
+                        reg:
+                          params: [n]
+                          query: { $modify: { name: "R[$n]" } }
+                      $tables: { access: { RW: read-write } }
+                      $foreach: { var: [k, v], in: { a: 1, b: "2" } }
+                      $use: { template: reg, args: { n: "[$k]" } }
+                      $expand_array: {}
+                      $collapse_array: { name: "REG(\\d+)" }
+                      $wrap: devices
+                      $unwrap: true
+                      register: { $map: access, field: { $map: { table: { "0": "no" }, default: "yes" } } }
+                    "#});
+        }
+        #[test]
+        fn test_short_forms() {
+            let patch = indoc! {r#"
+                    device:
+                      $tables:
+                        access:
+                          file: access.csv
+                      register:
+                        $map: access
+                    "#};
+            assert_eq!(serialize(&parse(patch).unwrap().unwrap()).unwrap(), patch);
+        }
+        #[test]
+        fn test_has_comments() {
+            assert!(has_comments("# patch\nelement: Bar"));
+            assert!(has_comments("element: Bar # new value"));
+            assert!(!has_comments("element: Bar#1"));
+            assert!(!has_comments("element: \"Bar #1\""));
+            assert!(!has_comments("element: 'it''s #1'"));
+            assert!(!has_comments("element: \"say \\\" #1\""));
+            assert!(has_comments("name: it's # note"));
+            assert!(has_comments("list: ['a', \"b\"] # note"));
+            assert!(has_comments("- 'a' # note"));
+        }
     }
 }
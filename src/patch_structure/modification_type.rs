@@ -1,5 +1,7 @@
 use super::ReferenceExpression;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::{Hash, Hasher};
 
 #[derive(Debug, Deserialize, Clone)]
@@ -7,44 +9,99 @@ pub enum ModificationType {
     Modify,
     Replace,
     Add,
+    AddIfAbsent,
 }
 
 impl ModificationType {
     pub fn is_modify(&self) -> bool {
-        match self {
-            ModificationType::Modify => true,
-            _ => false,
-        }
+        matches!(self, ModificationType::Modify)
     }
     pub fn is_replace(&self) -> bool {
-        match self {
-            ModificationType::Replace => true,
-            _ => false,
-        }
+        matches!(self, ModificationType::Replace)
+    }
+    pub fn is_add(&self) -> bool {
+        matches!(self, ModificationType::Add)
+    }
+    pub fn is_add_if_absent(&self) -> bool {
+        matches!(self, ModificationType::AddIfAbsent)
     }
-    /*pub fn is_add(&self) -> bool {
-        match self {
-            ModificationType::Add => true,
-            _ => false,
-        }
-    }*/
 }
 
-#[derive(Debug, Deserialize, Clone)]
-#[serde(from = "String", into = "String")]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(try_from = "String", into = "String")]
 pub struct ModificationIdentifier {
     pub mod_type: ModificationType,
     pub identifier: ReferenceExpression,
+    pub key: Option<ChildKey>,
 }
 
-impl ModificationIdentifier {
-    pub fn to_string(&self) -> String {
-        match self.mod_type {
-            ModificationType::Modify => format!("Modify[{}]", self.identifier.to_string()),
-            ModificationType::Replace => format!("Replace[{}]", self.identifier.to_string()),
-            ModificationType::Add => format!("Add[{}]", self.identifier.to_string()),
+/// Selects a single child by the text of one of its children (`field[name=EN]`) or by an attribute (`field[@id=3]`)
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChildKey {
+    pub name: String,
+    pub value: ReferenceExpression,
+}
+
+impl ChildKey {
+    // Splits a trailing `[name=value]` from the identifier
+    pub fn split(identifier: &str) -> Result<(&str, Option<ChildKey>), String> {
+        if !identifier.ends_with(']') {
+            return Ok((identifier, None));
+        }
+        let mut depth = 0;
+        for (i, c) in identifier.char_indices().rev() {
+            match c {
+                ']' => depth += 1,
+                '[' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let key = &identifier[i + 1..identifier.len() - 1];
+                        return match key.split_once('=') {
+                            Some((name, value))
+                                if !name.is_empty()
+                                    && !name.contains('[')
+                                    && !identifier[..i].ends_with('\\') =>
+                            {
+                                let key = ChildKey {
+                                    name: name.to_string(),
+                                    value: ReferenceExpression::parse(value.to_string())?,
+                                };
+                                Ok((&identifier[..i], Some(key)))
+                            }
+                            _ => Ok((identifier, None)),
+                        };
+                    }
+                }
+                _ => {}
+            }
         }
+        Ok((identifier, None))
+    }
+}
+
+impl fmt::Display for ChildKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl fmt::Display for ModificationIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = match &self.key {
+            Some(key) => format!("[{}]", key),
+            None => String::new(),
+        };
+        let prefix = match self.mod_type {
+            ModificationType::Modify => "",
+            ModificationType::Replace => "~",
+            ModificationType::Add => "+",
+            ModificationType::AddIfAbsent => "?",
+        };
+        write!(f, "{}{}{}", prefix, self.identifier, key)
     }
+}
+
+impl ModificationIdentifier {
     /*ToDo: pub fn get_expression<'a>(&'a self) -> &'a ReferenceExpression {
         match self {
             ModificationType::Modify(re) => &re,
@@ -61,16 +118,22 @@ impl ModificationIdentifier {}
 
 impl PartialEq for ModificationIdentifier {
     fn eq(&self, other: &Self) -> bool {
-        match (&self.mod_type, &other.mod_type) {
-            (ModificationType::Modify, ModificationType::Modify) => {
-                self.identifier == other.identifier
+        self.key == other.key
+            && match (&self.mod_type, &other.mod_type) {
+                (ModificationType::Modify, ModificationType::Modify) => {
+                    self.identifier == other.identifier
+                }
+                (ModificationType::Replace, ModificationType::Replace) => {
+                    self.identifier == other.identifier
+                }
+                (ModificationType::Add, ModificationType::Add) => {
+                    self.identifier == other.identifier
+                }
+                (ModificationType::AddIfAbsent, ModificationType::AddIfAbsent) => {
+                    self.identifier == other.identifier
+                }
+                (_, _) => false,
             }
-            (ModificationType::Replace, ModificationType::Replace) => {
-                self.identifier == other.identifier
-            }
-            (ModificationType::Add, ModificationType::Add) => self.identifier == other.identifier,
-            (_, _) => false,
-        }
     }
 }
 impl Eq for ModificationIdentifier {}
@@ -80,34 +143,40 @@ impl Hash for ModificationIdentifier {
         H: Hasher,
     {
         state.write(self.to_string().as_bytes());
-        state.finish();
     }
 }
 
-impl From<String> for ModificationIdentifier {
-    fn from(modification_string: String) -> Self {
+impl TryFrom<String> for ModificationIdentifier {
+    type Error = String;
+
+    fn try_from(modification_string: String) -> Result<Self, Self::Error> {
         let (mod_type, pattern) = match modification_string.split_at(1) {
             ("~", pattern) => (ModificationType::Replace, pattern),
             ("+", pattern) => (ModificationType::Add, pattern),
+            ("?", pattern) => (ModificationType::AddIfAbsent, pattern),
             (_, _) => (ModificationType::Modify, modification_string.as_str()),
         };
-        let identifier = ReferenceExpression::from(pattern);
-        ModificationIdentifier {
+        let (pattern, key) = ChildKey::split(pattern)?;
+        let identifier = ReferenceExpression::parse(pattern.to_string())?;
+        Ok(ModificationIdentifier {
             mod_type,
             identifier,
-        }
+            key,
+        })
     }
 }
 
+// For identifiers given in code, like in the builder; panics if the identifier is invalid
 impl From<&str> for ModificationIdentifier {
     fn from(modification_string: &str) -> Self {
-        ModificationIdentifier::from(modification_string.to_string())
+        ModificationIdentifier::try_from(modification_string.to_string())
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
-impl Into<String> for ModificationIdentifier {
-    fn into(self) -> String {
-        self.to_string()
+impl From<ModificationIdentifier> for String {
+    fn from(modification_identifier: ModificationIdentifier) -> Self {
+        modification_identifier.to_string()
     }
 }
 
@@ -121,7 +190,8 @@ mod tests {
             ModificationIdentifier::from("pattern"),
             ModificationIdentifier {
                 mod_type: ModificationType::Modify,
-                identifier: ReferenceExpression::from("pattern")
+                identifier: ReferenceExpression::from("pattern"),
+                key: None,
             }
         );
     }
@@ -131,7 +201,8 @@ mod tests {
             ModificationIdentifier::from("+pattern"),
             ModificationIdentifier {
                 mod_type: ModificationType::Add,
-                identifier: ReferenceExpression::from("pattern")
+                identifier: ReferenceExpression::from("pattern"),
+                key: None,
             }
         );
     }
@@ -141,8 +212,42 @@ mod tests {
             ModificationIdentifier::from("~pattern"),
             ModificationIdentifier {
                 mod_type: ModificationType::Replace,
-                identifier: ReferenceExpression::from("pattern")
+                identifier: ReferenceExpression::from("pattern"),
+                key: None,
+            }
+        );
+    }
+    #[test]
+    fn add_if_absent() {
+        assert_eq!(
+            ModificationIdentifier::from("?pattern"),
+            ModificationIdentifier {
+                mod_type: ModificationType::AddIfAbsent,
+                identifier: ReferenceExpression::from("pattern"),
+                key: None,
             }
         );
     }
+    #[test]
+    fn keyed() {
+        assert_eq!(
+            ModificationIdentifier::from("+field[name=EN_[$i]]"),
+            ModificationIdentifier {
+                mod_type: ModificationType::Add,
+                identifier: ReferenceExpression::from("field"),
+                key: Some(ChildKey {
+                    name: "name".to_string(),
+                    value: ReferenceExpression::from("EN_[$i]"),
+                }),
+            }
+        );
+        assert_eq!(
+            ModificationIdentifier::from("field[@id=3]").key,
+            Some(ChildKey {
+                name: "@id".to_string(),
+                value: ReferenceExpression::from("3"),
+            })
+        );
+        assert_eq!(ModificationIdentifier::from("field_[$i]").key, None);
+    }
 }
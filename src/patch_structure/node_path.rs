@@ -0,0 +1,124 @@
+use crate::patch_structure::modification_type::ChildKey;
+use crate::patch_structure::ReferenceExpression;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A path to existing elements, relative to the current element.
+///
+/// Segments are separated by `/` and are either `..`, `.` or an element name, which is a regular expression
+/// unless it is a plain name (see `is_plain_name`), optionally followed by a key (`register[name=CR1]`). A trailing `/` selects the content of the elements
+/// instead of the elements themselves.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NodePath {
+    pub segments: Vec<PathSegment>,
+    pub content_only: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PathSegment {
+    pub name: ReferenceExpression,
+    pub key: Option<ChildKey>,
+}
+
+impl TryFrom<String> for NodePath {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        // Split at every `/` that is not part of a reference or a key
+        let mut parts = vec![String::new()];
+        let mut depth = 0;
+        let mut escaping = false;
+        for c in path.chars() {
+            match c {
+                _ if escaping => escaping = false,
+                '\\' => escaping = true,
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '/' if depth == 0 => {
+                    parts.push(String::new());
+                    continue;
+                }
+                _ => {}
+            }
+            parts.last_mut().unwrap().push(c);
+        }
+        let content_only = parts.len() > 1 && parts.last().unwrap().is_empty();
+        if content_only {
+            parts.pop();
+        }
+        let segments = parts
+            .iter()
+            .map(|part| {
+                let (name, key) = ChildKey::split(part)?;
+                Ok(PathSegment {
+                    name: ReferenceExpression::parse(name.to_string())?,
+                    key,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(NodePath {
+            segments,
+            content_only,
+        })
+    }
+}
+
+// For paths given in code; panics if the path is invalid
+impl From<&str> for NodePath {
+    fn from(path: &str) -> Self {
+        NodePath::try_from(path.to_string()).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            write!(f, "{}", segment.name)?;
+            if let Some(key) = &segment.key {
+                write!(f, "[{}]", key)?;
+            }
+        }
+        if self.content_only {
+            write!(f, "/")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<NodePath> for String {
+    fn from(path: NodePath) -> Self {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_segments() {
+        let path = NodePath::from("../../register[name=CR_[$i]]/fields/");
+        assert!(path.content_only);
+        assert_eq!(path.segments.len(), 4);
+        assert_eq!(path.segments[2].name, ReferenceExpression::from("register"));
+        assert_eq!(
+            path.segments[2].key,
+            Some(ChildKey {
+                name: "name".to_string(),
+                value: ReferenceExpression::from("CR_[$i]"),
+            })
+        );
+        assert_eq!(path.to_string(), "../../register[name=CR_[$i]]/fields/");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(NodePath::try_from("../a]b".to_string()).is_err());
+        assert!(NodePath::try_from("register[name=CR_[]]".to_string()).is_err());
+    }
+}
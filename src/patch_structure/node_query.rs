@@ -0,0 +1,28 @@
+use crate::patch_structure::regex::Regex;
+use crate::patch_structure::SimpleValueType;
+use indexmap::map::IndexMap;
+use serde::{Deserialize, Serialize};
+
+/// Selects non-element children (comments, CDATA sections or processing instructions) of an element.
+///
+/// A single value is applied on every node of the selected kind, a map applies its values only on
+/// nodes whose content (or target, for processing instructions) matches the regex key.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum NodeQuery {
+    All(SimpleValueType),
+    Matching(IndexMap<Regex, SimpleValueType>),
+}
+
+impl NodeQuery {
+    /// Returns the value to apply on a node with the given content, if the node is selected at all
+    pub fn value_for(&self, content: &str) -> Option<&SimpleValueType> {
+        match self {
+            NodeQuery::All(value) => Some(value),
+            NodeQuery::Matching(values) => values
+                .iter()
+                .find(|(regex, _)| regex.regex.is_match(content))
+                .map(|(_, value)| value),
+        }
+    }
+}
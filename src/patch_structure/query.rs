@@ -1,8 +1,13 @@
+use crate::patch_structure::array::{ArrayCollapse, ArrayLayout};
 use crate::patch_structure::regex::Regex;
 use crate::patch_structure::value::ModificationValue;
-use crate::patch_structure::{Modifier, SimpleValueType};
+use crate::patch_structure::{
+    deserialize_some, serialize_some, Modifier, NodeQuery, ReferenceExpression, SimpleValueType,
+    Table, Template,
+};
 use indexmap::map::IndexMap;
-use serde::Deserialize;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 
 #[derive(Debug, PartialEq, Clone, Deserialize)]
 pub struct ComplexQuery {
@@ -10,24 +15,113 @@ pub struct ComplexQuery {
     pub modifier: Modifier,
     #[serde(rename = "$modify")]
     pub modification: Option<ModificationValue>,
+    #[serde(rename = "$comments", default, deserialize_with = "deserialize_some")]
+    pub comments: Option<NodeQuery>,
+    #[serde(rename = "$cdatas", default, deserialize_with = "deserialize_some")]
+    pub cdatas: Option<NodeQuery>,
+    #[serde(rename = "$pis", default, deserialize_with = "deserialize_some")]
+    pub processing_instructions: Option<NodeQuery>,
+    #[serde(rename = "$expand_array")]
+    pub expand_array: Option<ArrayLayout>,
+    #[serde(rename = "$collapse_array")]
+    pub collapse_array: Option<ArrayCollapse>,
+    #[serde(rename = "$templates", default)]
+    pub templates: IndexMap<String, Template>,
+    /// Lookup tables for `$map` and `[%table]` references below this query
+    #[serde(rename = "$tables", default)]
+    pub tables: IndexMap<String, Table>,
+    /// Encloses the element in a new element with the given name
+    #[serde(rename = "$wrap")]
+    pub wrap: Option<ReferenceExpression>,
+    /// Replaces the element by its children
+    #[serde(rename = "$unwrap", default)]
+    pub unwrap: bool,
+    /// Alternative body for selected elements that do not match the `$if` filter
+    #[serde(rename = "$else")]
+    pub otherwise: Option<Box<ComplexQuery>>,
     #[serde(flatten)]
     pub subqueries: IndexMap<Regex, Query>,
 }
 
-#[derive(Debug, PartialEq, Clone, Deserialize)]
+/**
+Writes the directives in a fixed order, roughly in the order they are applied, followed by the subqueries:
+scopes and loops, the filter, pulled content, the modification, copy and move, arrays and finally the subqueries.
+ **/
+impl Serialize for ComplexQuery {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let modifier = &self.modifier;
+        let mut map = serializer.serialize_map(None)?;
+        if !self.templates.is_empty() {
+            map.serialize_entry("$templates", &self.templates)?;
+        }
+        if !self.tables.is_empty() {
+            map.serialize_entry("$tables", &self.tables)?;
+        }
+        serialize_some(&mut map, "$foreach", &modifier.foreach)?;
+        serialize_some(&mut map, "$if", &modifier.filter)?;
+        serialize_some(&mut map, "$else", &self.otherwise)?;
+        serialize_some(&mut map, "$use", &modifier.use_template)?;
+        serialize_some(&mut map, "$copy_from", &modifier.copy_from)?;
+        serialize_some(&mut map, "$move_from", &modifier.move_from)?;
+        serialize_some(&mut map, "$merge_from", &modifier.merge_from)?;
+        serialize_some(&mut map, "$merge", &modifier.merge)?;
+        serialize_some(&mut map, "$comments", &self.comments)?;
+        serialize_some(&mut map, "$cdatas", &self.cdatas)?;
+        serialize_some(&mut map, "$pis", &self.processing_instructions)?;
+        serialize_some(&mut map, "$modify", &self.modification)?;
+        serialize_some(&mut map, "$map", &modifier.map)?;
+        serialize_some(&mut map, "$copy", &modifier.copy)?;
+        serialize_some(&mut map, "$move", &modifier.move_to)?;
+        serialize_some(&mut map, "$wrap", &self.wrap)?;
+        serialize_some(&mut map, "$collapse_array", &self.collapse_array)?;
+        serialize_some(&mut map, "$expand_array", &self.expand_array)?;
+        if self.unwrap {
+            map.serialize_entry("$unwrap", &true)?;
+        }
+        for (regex, query) in &self.subqueries {
+            map.serialize_entry(regex, query)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum Query {
     Simple(SimpleValueType),
     Complex(ComplexQuery),
     ComplexVec(Vec<ComplexQuery>),
 }
 
-impl From<IndexMap<Regex, Query>> for Query {
+impl ComplexQuery {
+    pub fn new() -> ComplexQuery {
+        ComplexQuery::from(IndexMap::new())
+    }
+}
+
+impl From<IndexMap<Regex, Query>> for ComplexQuery {
     fn from(subqueries: IndexMap<Regex, Query>) -> Self {
-        Query::Complex(ComplexQuery {
+        ComplexQuery {
             modifier: Modifier::new(),
             modification: None,
+            comments: None,
+            cdatas: None,
+            processing_instructions: None,
+            expand_array: None,
+            collapse_array: None,
+            templates: IndexMap::new(),
+            tables: IndexMap::new(),
+            wrap: None,
+            unwrap: false,
+            otherwise: None,
             subqueries,
-        })
+        }
+    }
+}
+
+impl From<IndexMap<Regex, Query>> for Query {
+    fn from(subqueries: IndexMap<Regex, Query>) -> Self {
+        Query::Complex(ComplexQuery::from(subqueries))
     }
 }
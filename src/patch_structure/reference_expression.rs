@@ -1,26 +1,28 @@
-use super::super::xml_structure::bidirectional_xml_tree::XmlNode;
+use super::super::xml_structure::bidirectional_xml_tree::{NodeId, PathOptions, XmlTree};
 use super::refex_segment::{CaptureReference, Segment, SegmentReference};
-use serde::Deserialize;
-use std::cell::RefCell;
+use super::NodePath;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::rc::Rc;
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(from = "String", into = "String")]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct ReferenceExpression {
     #[serde(skip_serializing)]
     pub segments: Vec<Segment>,
 }
 
 impl ReferenceExpression {
-    pub fn parse(string: String) -> ReferenceExpression {
+    /// Parses an expression, which fails for unbalanced brackets and invalid references
+    pub fn parse(string: String) -> Result<ReferenceExpression, String> {
         let mut segments = Vec::new();
         let mut buf = String::new();
         let mut escaping = false;
         let mut is_reference = false;
         for c in string.chars() {
             if escaping {
-                buf.push(match c {
+                let unescaped = match c {
                     '[' => '[',
                     ']' => ']',
                     '\\' => '\\',
@@ -29,74 +31,220 @@ impl ReferenceExpression {
                     't' => '\t',
                     '\'' => '\'',
                     '\"' => '\"',
-                    _ => panic!("Escaped unescapable character {}", c),
-                });
+                    // Other escapes are kept for regular expressions in paths (e.g. `\d`)
+                    _ => {
+                        buf.push('\\');
+                        c
+                    }
+                };
+                buf.push(unescaped);
                 escaping = false;
+            } else if c == '\\' {
+                escaping = true;
+            } else if c == '[' {
+                if is_reference {
+                    return Err(format!("Nested opening bracket in \"{}\"", string));
+                }
+                if !buf.is_empty() {
+                    segments.push(Segment::String(buf.clone()));
+                    buf.clear();
+                }
+                is_reference = true;
+            } else if c == ']' {
+                if !is_reference {
+                    return Err(format!(
+                        "Closing bracket without preceding opening bracket in \"{}\"",
+                        string
+                    ));
+                }
+                is_reference = false;
+                segments.push(Segment::Reference(SegmentReference::try_from(buf.clone())?));
+                buf.clear();
             } else {
-                if c == '\\' {
-                    escaping = true;
-                } else if c == '[' || c == ']' {
-                    if c == '[' {
-                        if !buf.is_empty() {
-                            segments.push(Segment::String(buf.clone()));
-                            buf.clear();
+                buf.push(c);
+            }
+        }
+        if is_reference {
+            return Err(format!(
+                "Opening bracket without closing bracket in \"{}\"",
+                string
+            ));
+        }
+        if !buf.is_empty() {
+            segments.push(Segment::String(buf.clone()));
+        }
+        Ok(ReferenceExpression { segments })
+    }
+    /// Splits the expression at every `/` outside of references, giving one expression per path segment
+    pub fn split_path(&self) -> Vec<ReferenceExpression> {
+        let mut path = vec![ReferenceExpression { segments: vec![] }];
+        for segment in &self.segments {
+            match segment {
+                Segment::String(s) => {
+                    for (i, part) in s.split('/').enumerate() {
+                        if i > 0 {
+                            path.push(ReferenceExpression { segments: vec![] });
                         }
-                        is_reference = true;
-                    } else {
-                        if !is_reference {
-                            panic!("Closing bracket without preceding opening bracket.")
-                        } else {
-                            is_reference = false;
-                            segments.push(Segment::Reference(SegmentReference::from(buf.clone())));
-                            buf.clear();
+                        if !part.is_empty() {
+                            let last = path.last_mut().unwrap();
+                            last.segments.push(Segment::String(part.to_string()));
                         }
                     }
-                } else {
-                    buf.push(c);
                 }
+                Segment::Reference(_) => path.last_mut().unwrap().segments.push(segment.clone()),
             }
         }
-        if !buf.is_empty() {
-            segments.push(Segment::String(buf.clone()));
-        }
-        ReferenceExpression { segments }
+        path
     }
-    pub fn evaluate(&self, current_node: &Rc<RefCell<XmlNode>>) -> String {
+    pub fn evaluate(&self, tree: &XmlTree, current_node: NodeId) -> Result<String, String> {
+        self.evaluate_with(tree, current_node, &[])
+    }
+    /// Evaluates the expression with additional variables, which take precedence over the bound ones
+    pub fn evaluate_with(
+        &self,
+        tree: &XmlTree,
+        current_node: NodeId,
+        bindings: &[(String, String)],
+    ) -> Result<String, String> {
+        let variable = |name: &str| {
+            bindings
+                .iter()
+                .rev()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+                .or_else(|| tree.variable(current_node, name))
+                .ok_or_else(|| format!("Unknown variable \"{}\"", name))
+        };
         let mut result = String::new();
         for segment in &self.segments {
             match segment {
                 Segment::String(s) => result.push_str(s),
                 Segment::Reference(reference) => {
-                    let splitted_path = reference.path.split("/").map(|s| s.to_string()).collect();
-                    let current_node =
-                        XmlNode::get_node_info_by_path(current_node.clone(), splitted_path, false);
-                    let (regex, name) = match (
-                        current_node.borrow().get_regex(),
-                        current_node.borrow().name(),
-                    ) {
-                        (Some(regex), Some(name)) => (regex, name),
-                        (_, _) => panic!("Could not evaluate regular expression"),
+                    // References starting with "$" are loop variables
+                    if let Some(name) = reference.path.strip_prefix('$') {
+                        result.push_str(&variable(name)?);
+                        continue;
+                    }
+                    // References starting with "%" are table lookups: `[%table]` or `[%table(argument)]`
+                    if let Some(call) = reference.path.strip_prefix('%') {
+                        result.push_str(&Self::lookup(tree, current_node, call, variable)?);
+                        continue;
+                    }
+                    let mut path = NodePath::try_from(reference.path.clone())?;
+                    // A last path segment "@" refers to the attribute that is currently modified
+                    let is_attribute_reference = path
+                        .segments
+                        .last()
+                        .is_some_and(|s| s.name == ReferenceExpression::from("@"));
+                    if is_attribute_reference {
+                        path.segments.pop();
+                    }
+                    let options = PathOptions {
+                        create: false,
+                        multiple: false,
+                    };
+                    let current_node = match tree
+                        .resolve_path(current_node, current_node, &path, options)?
+                        .first()
+                    {
+                        Some(path_match) => &tree[path_match.node],
+                        None => return Err(format!("No element is matching \"{}\"", path)),
+                    };
+                    let matched = if is_attribute_reference {
+                        current_node.get_attribute_match()
+                    } else {
+                        current_node
+                            .get_regex()
+                            .zip(current_node.name().map(String::from))
+                    };
+                    let (regex, name) = match matched {
+                        Some(matched) => matched,
+                        None => {
+                            return Err(format!(
+                                "Reference \"{}\" has no matching regular expression",
+                                reference
+                            ))
+                        }
                     };
+                    let has_group = match &reference.capture {
+                        CaptureReference::Number(n) => *n < regex.captures_len(),
+                        CaptureReference::Name(n) => regex.capture_names().any(|g| g == Some(n)),
+                        CaptureReference::WholeExpression => true,
+                    };
+                    if !has_group {
+                        return Err(format!(
+                            "Reference \"{}\" refers to a capture group that does not exist",
+                            reference
+                        ));
+                    }
                     for capture in regex.captures_iter(&name) {
+                        // Optional groups that did not participate in the match are empty
                         result.push_str(match &reference.capture {
-                            CaptureReference::Number(n) => capture.get(n.clone()).unwrap().as_str(),
-                            CaptureReference::Name(n) => capture.name(&n).unwrap().as_str(),
+                            CaptureReference::Number(n) => {
+                                capture.get(*n).map_or("", |m| m.as_str())
+                            }
+                            CaptureReference::Name(n) => capture.name(n).map_or("", |m| m.as_str()),
                             CaptureReference::WholeExpression => name.as_str(),
                         });
                     }
                 }
             }
         }
-        result
+        Ok(result)
     }
-    pub fn to_string(&self) -> String {
-        self.segments.iter().map(Segment::to_string).collect()
+}
+
+impl ReferenceExpression {
+    /**
+    Translates a value through a bound table. The argument is a variable (`$i`), a path to an element
+    whose text is used or empty for the text of the current element. Unmapped values are kept.
+     **/
+    fn lookup(
+        tree: &XmlTree,
+        current_node: NodeId,
+        call: &str,
+        variable: impl Fn(&str) -> Result<String, String>,
+    ) -> Result<String, String> {
+        let (name, argument) = match call.split_once('(') {
+            Some((name, argument)) => (name, argument.strip_suffix(')').unwrap_or(argument)),
+            None => (call, ""),
+        };
+        let table = match tree.table(current_node, name) {
+            Some(table) => table,
+            None => return Err(format!("Unknown table \"{}\"", name)),
+        };
+        let value = if let Some(name) = argument.strip_prefix('$') {
+            variable(name)?
+        } else {
+            let options = PathOptions {
+                create: false,
+                multiple: true,
+            };
+            let path = NodePath::try_from(argument.to_string())?;
+            match tree
+                .resolve_path(current_node, current_node, &path, options)?
+                .first()
+            {
+                Some(path_match) => tree.text(path_match.node),
+                None => return Err(format!("No element is matching \"{}\"", argument)),
+            }
+        };
+        Ok(table.get(&value).cloned().unwrap_or(value))
+    }
+}
+
+impl fmt::Display for ReferenceExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
     }
 }
 
 impl PartialEq for ReferenceExpression {
     fn eq(&self, other: &Self) -> bool {
-        (&self.segments.len() == &other.segments.len()) &&  // zip stops at the shortest
+        (self.segments.len() == other.segments.len()) &&  // zip stops at the shortest
             self.segments.iter()
                 .zip(&other.segments)
                 .all(|(a,b)| a == b )
@@ -110,25 +258,27 @@ impl Hash for ReferenceExpression {
         H: Hasher,
     {
         state.write(self.to_string().as_bytes());
-        state.finish();
     }
 }
 
-impl From<String> for ReferenceExpression {
-    fn from(string: String) -> Self {
+impl TryFrom<String> for ReferenceExpression {
+    type Error = String;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
         ReferenceExpression::parse(string)
     }
 }
 
+// For expressions given in code, like in the builder; panics if the expression is invalid
 impl From<&str> for ReferenceExpression {
-    fn from(regex_string: &str) -> Self {
-        ReferenceExpression::from(regex_string.to_string())
+    fn from(string: &str) -> Self {
+        ReferenceExpression::parse(string.to_string()).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
-impl Into<String> for ReferenceExpression {
-    fn into(self) -> String {
-        self.to_string()
+impl From<ReferenceExpression> for String {
+    fn from(reference_expression: ReferenceExpression) -> Self {
+        reference_expression.to_string()
     }
 }
 
@@ -139,14 +289,28 @@ mod tests {
     #[test]
     fn there_and_back_again() {
         let pattern = "hello[world]lovely[myra]end".to_string();
-        let refex = ReferenceExpression::parse(pattern.clone());
+        let refex = ReferenceExpression::parse(pattern.clone()).unwrap();
         assert_eq!(refex.to_string(), pattern)
     }
 
+    #[test]
+    fn split_path() {
+        let path = ReferenceExpression::from(r"../TIM\d+/[.:1]_[../..:2]/").split_path();
+        assert_eq!(
+            path,
+            vec![
+                ReferenceExpression::from(".."),
+                ReferenceExpression::from(r"TIM\d+"),
+                ReferenceExpression::from("[.:1]_[../..:2]"),
+                ReferenceExpression::from(""),
+            ]
+        )
+    }
+
     #[test]
     fn common_pattern() {
         let pattern = "hello[world]lovely[myra]end".to_string();
-        let refex = ReferenceExpression::parse(pattern.clone());
+        let refex = ReferenceExpression::parse(pattern.clone()).unwrap();
         assert_eq!(
             ReferenceExpression {
                 segments: vec![
@@ -164,7 +328,7 @@ mod tests {
     #[test]
     fn escaped_bracket() {
         let pattern = r#"hello[world]lovely\[myra\]end"#.to_string();
-        let refex = ReferenceExpression::parse(pattern.clone());
+        let refex = ReferenceExpression::parse(pattern.clone()).unwrap();
         assert_eq!(
             ReferenceExpression {
                 segments: vec![
@@ -180,7 +344,7 @@ mod tests {
     #[test]
     fn wrong_separation() {
         let pattern = "hello[world]lovely\\[myra\\]end".to_string();
-        let refex = ReferenceExpression::parse(pattern.clone());
+        let refex = ReferenceExpression::parse(pattern.clone()).unwrap();
         assert_ne!(
             ReferenceExpression {
                 segments: vec![
@@ -198,7 +362,7 @@ mod tests {
     #[test]
     fn escaped_token_pattern() {
         let pattern = r#"hello[\[\]\n\r\t\\\'\"]world\[\]\n\r\t\\\'\""#.to_string();
-        let refex = ReferenceExpression::parse(pattern.clone());
+        let refex = ReferenceExpression::parse(pattern.clone()).unwrap();
         assert_eq!(
             ReferenceExpression {
                 segments: vec![
@@ -210,4 +374,29 @@ mod tests {
             refex
         )
     }
+
+    #[test]
+    fn invalid() {
+        let parse = |pattern: &str| ReferenceExpression::parse(pattern.to_string());
+        assert_eq!(
+            parse("x]y"),
+            Err("Closing bracket without preceding opening bracket in \"x]y\"".to_string())
+        );
+        assert_eq!(
+            parse("x[y"),
+            Err("Opening bracket without closing bracket in \"x[y\"".to_string())
+        );
+        assert_eq!(
+            parse("[a[b]]"),
+            Err("Nested opening bracket in \"[a[b]]\"".to_string())
+        );
+        assert_eq!(
+            parse("x[]"),
+            Err("Empty references are not allowed".to_string())
+        );
+        assert_eq!(
+            parse("[a:b:c]"),
+            Err("Reference \"a:b:c\" has more than a path and a capture group".to_string())
+        );
+    }
 }
@@ -1,3 +1,5 @@
+use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
@@ -19,45 +21,53 @@ pub enum Segment {
     Reference(SegmentReference),
 }
 
-impl From<String> for SegmentReference {
-    fn from(segment_reference_string: String) -> Self {
-        let parts: Vec<&str> = segment_reference_string.split(":").collect();
-        if parts.len() == 0 {
-            panic!("Empty references are not allowed");
-        } else if parts.len() == 1 {
-            SegmentReference {
-                path: parts.get(0).unwrap().to_string(),
-                capture: CaptureReference::WholeExpression,
-            }
-        } else if parts.len() == 2 {
-            let capture_reference = parts.get(1).unwrap().to_string();
-            match usize::from_str(&capture_reference) {
-                Ok(index) => SegmentReference {
-                    path: parts.get(0).unwrap().to_string(),
-                    capture: CaptureReference::Number(index),
-                },
-                Err(_) => SegmentReference {
-                    path: parts.get(0).unwrap().to_string(),
-                    capture: CaptureReference::Name(capture_reference),
-                },
-            }
-        } else {
-            panic!("A reference must have a path and optionally a capture-reference. Not more.");
+impl TryFrom<String> for SegmentReference {
+    type Error = String;
+
+    fn try_from(segment_reference_string: String) -> Result<Self, Self::Error> {
+        let parts: Vec<&str> = segment_reference_string.split(':').collect();
+        let path = parts[0].to_string();
+        if path.is_empty() {
+            return Err("Empty references are not allowed".to_string());
         }
+        let capture = match parts.len() {
+            1 => CaptureReference::WholeExpression,
+            2 => match usize::from_str(parts[1]) {
+                Ok(index) => CaptureReference::Number(index),
+                Err(_) => CaptureReference::Name(parts[1].to_string()),
+            },
+            _ => {
+                return Err(format!(
+                    "Reference \"{}\" has more than a path and a capture group",
+                    segment_reference_string
+                ))
+            }
+        };
+        Ok(SegmentReference { path, capture })
     }
 }
 
+// For references given in code; panics if the reference is invalid
 impl From<&str> for SegmentReference {
     fn from(segment_reference_string: &str) -> Self {
-        SegmentReference::from(segment_reference_string.to_string())
+        SegmentReference::try_from(segment_reference_string.to_string())
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
-impl Segment {
-    pub fn to_string(&self) -> String {
+// Escapes the characters that have a meaning in reference expressions
+fn escape(string: &str) -> String {
+    string
+        .replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Segment::String(segment) => segment.clone(),
-            Segment::Reference(reference) => format!("[{}]", reference.to_string()),
+            Segment::String(segment) => write!(f, "{}", escape(segment)),
+            Segment::Reference(reference) => write!(f, "[{}]", reference),
         }
     }
 }
@@ -72,12 +82,13 @@ impl PartialEq for Segment {
     }
 }
 
-impl SegmentReference {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for SegmentReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = escape(&self.path);
         match &self.capture {
-            CaptureReference::Number(n) => format!("{}:{}", self.path, n).to_string(),
-            CaptureReference::Name(n) => format!("{}:{}", self.path, n).to_string(),
-            CaptureReference::WholeExpression => format!("{}", self.path).to_string(),
+            CaptureReference::Number(n) => write!(f, "{}:{}", path, n),
+            CaptureReference::Name(n) => write!(f, "{}:{}", path, n),
+            CaptureReference::WholeExpression => write!(f, "{}", path),
         }
     }
 }
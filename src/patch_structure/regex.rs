@@ -1,16 +1,46 @@
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::hash::{Hash, Hasher};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(from = "String", into = "String")]
+#[serde(try_from = "String", into = "String")]
 pub struct Regex {
     #[serde(skip_serializing)]
     pub regex: regex::Regex,
+    // The name if the expression is a plain name, detected once while parsing
+    #[serde(skip)]
+    literal: Option<String>,
+}
+
+impl Regex {
+    /// Compiles the expression, which has to match the whole name
+    pub fn new(expression: &str) -> Result<Regex, regex::Error> {
+        Ok(Regex {
+            regex: regex::Regex::new(&format!("^{}$", expression))?,
+            literal: Some(expression.to_string()).filter(|s| is_plain_name(s)),
+        })
+    }
+    /// Returns the plain name if the expression only matches this single literal string
+    pub fn as_literal(&self) -> Option<&str> {
+        self.literal.as_deref()
+    }
+}
+
+/**
+Returns whether a name or path segment is a plain element name (letters, digits, `_`, `-` and `:`).
+Plain names only match themselves, so they are looked up exactly and missing elements can be created.
+Everything else, including names with a `.`, is a regular expression.
+ **/
+pub fn is_plain_name(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-:".contains(c))
 }
 
 impl PartialEq for Regex {
     fn eq(&self, other: &Self) -> bool {
-        self.regex.as_str().to_string() == other.regex.as_str().to_string()
+        self.regex.as_str() == other.regex.as_str()
     }
 }
 impl Eq for Regex {}
@@ -20,26 +50,62 @@ impl Hash for Regex {
         H: Hasher,
     {
         state.write(self.regex.as_str().as_bytes());
-        state.finish();
     }
 }
 
-impl From<String> for Regex {
-    fn from(regex_string: String) -> Self {
-        Regex {
-            regex: regex::Regex::new(format!("^{}$", regex_string).as_str()).unwrap(),
-        }
+impl TryFrom<String> for Regex {
+    type Error = String;
+
+    fn try_from(regex_string: String) -> Result<Self, Self::Error> {
+        Regex::new(&regex_string)
+            .map_err(|e| format!("Invalid regular expression \"{}\": {}", regex_string, e))
     }
 }
 
+// For expressions given in code, like in the builder; panics if the expression is invalid
 impl From<&str> for Regex {
     fn from(regex_string: &str) -> Self {
-        Regex::from(regex_string.to_string())
+        Regex::try_from(regex_string.to_string()).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
-impl Into<String> for Regex {
-    fn into(self) -> String {
-        self.regex.as_str().to_string()
+impl From<Regex> for String {
+    fn from(regex: Regex) -> Self {
+        // Strip the anchors added by `Regex::new`
+        let expression = regex.regex.as_str();
+        expression[1..expression.len() - 1].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        assert_eq!(Regex::from("xml:id").as_literal(), Some("xml:id"));
+        assert_eq!(Regex::from("data-foo").as_literal(), Some("data-foo"));
+        assert_eq!(Regex::new("reg").unwrap().as_literal(), Some("reg"));
+    }
+    #[test]
+    fn no_literal() {
+        assert_eq!(Regex::from("data-.*").as_literal(), None);
+        assert_eq!(Regex::from("reg(1|2)").as_literal(), None);
+    }
+    #[test]
+    fn plain_name() {
+        assert!(is_plain_name("foo_bar-1"));
+        assert!(is_plain_name("xml:id"));
+        assert!(!is_plain_name("TIM."));
+        assert_eq!(Regex::from("TIM.").as_literal(), None);
+        assert!(!is_plain_name("TIM\\d+"));
+        assert!(!is_plain_name("reg.*"));
+        assert!(!is_plain_name(""));
+    }
+    #[test]
+    fn invalid() {
+        assert!(Regex::try_from("(".to_string())
+            .unwrap_err()
+            .starts_with("Invalid regular expression \"(\""));
     }
 }
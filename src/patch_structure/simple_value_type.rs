@@ -1,10 +1,8 @@
 use crate::patch_structure::ReferenceExpression;
-use crate::xml_structure::bidirectional_xml_tree::{XmlNode, XmlNodeData};
-use serde::Deserialize;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::xml_structure::bidirectional_xml_tree::{NodeId, XmlNodeData, XmlTree};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum SimpleValueType {
     Pattern(ReferenceExpression),
@@ -16,21 +14,81 @@ pub enum SimpleValueType {
 }
 
 impl SimpleValueType {
-    //ToDo: Add element as argument to avoid accidently mixups when using multiple same elements
-    pub fn to_xml_node(&self, current_node: &Rc<RefCell<XmlNode>>) -> Option<XmlNodeData> {
-        match self.eval_to_string(current_node) {
-            Some(text) => Some(XmlNodeData::Text(text)),
-            None => None,
+    /**
+    Parses a plain scalar like it is written in a patch, independent of the patch format:
+    `~` and `null` remove, `true`/`false` are booleans, numbers are integers or floats
+    and quotes (`'...'` or `"..."`) keep everything else as pattern.
+    Fails if the pattern is not a valid reference expression.
+     **/
+    pub fn parse_scalar(s: &str) -> Result<SimpleValueType, String> {
+        let s = s.trim();
+        let is_quoted = |quote: char| s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote);
+        if is_quoted('\'') || is_quoted('"') {
+            let pattern = ReferenceExpression::parse(s[1..s.len() - 1].to_string())?;
+            return Ok(SimpleValueType::Pattern(pattern));
+        }
+        match s {
+            "~" | "null" => return Ok(SimpleValueType::Remove),
+            "true" => return Ok(SimpleValueType::Boolean(true)),
+            "false" => return Ok(SimpleValueType::Boolean(false)),
+            _ => {}
+        }
+        if let Ok(n) = s.parse() {
+            return Ok(SimpleValueType::UnsignedInteger(n));
+        }
+        if let Ok(n) = s.parse() {
+            return Ok(SimpleValueType::SignedInteger(n));
+        }
+        let is_float = s.contains(|c: char| c.is_ascii_digit())
+            && s.chars().all(|c| c.is_ascii_digit() || ".eE+-".contains(c));
+        match s.parse() {
+            Ok(f) if is_float => Ok(SimpleValueType::Float(f)),
+            _ => Ok(SimpleValueType::Pattern(ReferenceExpression::parse(
+                s.to_string(),
+            )?)),
         }
     }
-    pub fn eval_to_string(&self, current_node: &Rc<RefCell<XmlNode>>) -> Option<String> {
+    /// Writes the value as scalar that is parsed back to the same value by `parse_scalar`
+    pub fn to_scalar(&self) -> String {
         match self {
-            SimpleValueType::Pattern(p) => Some(p.evaluate(current_node)),
+            SimpleValueType::Pattern(p) => {
+                let pattern = p.to_string();
+                if SimpleValueType::parse_scalar(&pattern).as_ref() == Ok(self) {
+                    pattern
+                } else {
+                    format!("'{}'", pattern)
+                }
+            }
+            SimpleValueType::Boolean(b) => b.to_string(),
+            SimpleValueType::UnsignedInteger(n) => n.to_string(),
+            SimpleValueType::SignedInteger(n) => n.to_string(),
+            SimpleValueType::Float(f) => format!("{:?}", f),
+            SimpleValueType::Remove => "~".to_string(),
+        }
+    }
+    //ToDo: Add element as argument to avoid accidently mixups when using multiple same elements
+    pub fn to_xml_node(
+        &self,
+        tree: &XmlTree,
+        current_node: NodeId,
+    ) -> Result<Option<XmlNodeData>, String> {
+        Ok(self
+            .eval_to_string(tree, current_node)?
+            .map(XmlNodeData::Text))
+    }
+    // Returns `None` for `~`, which removes the target
+    pub fn eval_to_string(
+        &self,
+        tree: &XmlTree,
+        current_node: NodeId,
+    ) -> Result<Option<String>, String> {
+        Ok(match self {
+            SimpleValueType::Pattern(p) => Some(p.evaluate(tree, current_node)?),
             SimpleValueType::Boolean(b) => Some(b.to_string()),
             SimpleValueType::UnsignedInteger(ui) => Some(ui.to_string()),
             SimpleValueType::SignedInteger(si) => Some(si.to_string()),
             SimpleValueType::Float(fl) => Some(fl.to_string()),
             SimpleValueType::Remove => None,
-        }
+        })
     }
 }
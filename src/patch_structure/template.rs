@@ -0,0 +1,170 @@
+use crate::patch_structure::{
+    ComplexQuery, ComplexValue, ModificationValue, Query, SimpleValueType,
+};
+use indexmap::map::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A named patch fragment that is instantiated by `$use`.
+///
+/// The fragment is either a query (for `$use` in a query) or a modification (for `$use` in a `$modify` value).
+/// Its parameters are available as variables (`[$name]`) inside the fragment.
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<Query>,
+    #[serde(rename = "modify", skip_serializing_if = "Option::is_none")]
+    pub modification: Option<ModificationValue>,
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateUse {
+    pub template: String,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub args: IndexMap<String, SimpleValueType>,
+    /// The used template, set by `Query::resolve_templates`. Shared by all uses of the template.
+    #[serde(skip)]
+    pub resolved: Option<Arc<Template>>,
+}
+
+/// The templates that are visible at a position in the patch, innermost definitions last
+pub type TemplateScope = Vec<IndexMap<String, Arc<Template>>>;
+
+impl TemplateUse {
+    fn resolve(
+        &mut self,
+        scope: &[IndexMap<String, Arc<Template>>],
+        in_query: bool,
+    ) -> Result<(), String> {
+        let template = scope
+            .iter()
+            .rev()
+            .find_map(|templates| templates.get(&self.template))
+            .ok_or_else(|| format!("Unknown template \"{}\"", self.template))?;
+        if in_query && template.query.is_none() {
+            return Err(format!("Template \"{}\" has no query", self.template));
+        }
+        if !in_query && template.modification.is_none() {
+            return Err(format!(
+                "Template \"{}\" has no modification",
+                self.template
+            ));
+        }
+        if let Some(param) = template.params.iter().find(|p| !self.args.contains_key(*p)) {
+            return Err(format!(
+                "Missing argument \"{}\" for template \"{}\"",
+                param, self.template
+            ));
+        }
+        if let Some(arg) = self.args.keys().find(|a| !template.params.contains(a)) {
+            return Err(format!(
+                "Template \"{}\" has no parameter \"{}\"",
+                self.template, arg
+            ));
+        }
+        // `~` has no text that could be bound to the parameter
+        if let Some((arg, _)) = self
+            .args
+            .iter()
+            .find(|(_, value)| **value == SimpleValueType::Remove)
+        {
+            return Err(format!(
+                "Argument \"{}\" of template \"{}\" must not be empty",
+                arg, self.template
+            ));
+        }
+        self.resolved = Some(template.clone());
+        Ok(())
+    }
+}
+
+impl Template {
+    fn resolve_templates(&mut self, scope: &mut TemplateScope) -> Result<(), String> {
+        if let Some(query) = &mut self.query {
+            query.resolve_templates(scope)?;
+        }
+        if let Some(modification) = &mut self.modification {
+            modification.resolve_templates(scope)?;
+        }
+        Ok(())
+    }
+}
+
+impl Query {
+    /**
+    Looks up the template of each `$use` and checks its arguments.
+    Templates are visible below the query that defines them and in the templates defined after them.
+    Each definition is copied once and shared by all its uses, so nested templates are not copied again.
+     **/
+    pub fn resolve_templates(&mut self, scope: &mut TemplateScope) -> Result<(), String> {
+        match self {
+            Query::Simple(_) => Ok(()),
+            Query::Complex(complex_query) => complex_query.resolve_templates(scope),
+            Query::ComplexVec(complex_queries) => complex_queries
+                .iter_mut()
+                .try_for_each(|complex_query| complex_query.resolve_templates(scope)),
+        }
+    }
+}
+
+impl ComplexQuery {
+    fn resolve_templates(&mut self, scope: &mut TemplateScope) -> Result<(), String> {
+        scope.push(IndexMap::new());
+        let result = self.resolve_templates_in_scope(scope);
+        scope.pop();
+        result
+    }
+    fn resolve_templates_in_scope(&mut self, scope: &mut TemplateScope) -> Result<(), String> {
+        for (name, template) in self.templates.iter_mut() {
+            template.resolve_templates(scope)?;
+            scope
+                .last_mut()
+                .unwrap()
+                .insert(name.clone(), Arc::new(template.clone()));
+        }
+        if let Some(template_use) = &mut self.modifier.use_template {
+            template_use.resolve(scope, true)?;
+        }
+        if let Some(modification) = &mut self.modification {
+            modification.resolve_templates(scope)?;
+        }
+        if let Some(otherwise) = &mut self.otherwise {
+            otherwise.resolve_templates(scope)?;
+        }
+        self.subqueries
+            .values_mut()
+            .try_for_each(|query| query.resolve_templates(scope))
+    }
+}
+
+impl ModificationValue {
+    fn resolve_templates(&mut self, scope: &mut TemplateScope) -> Result<(), String> {
+        match self {
+            ModificationValue::SimpleValue(_) => Ok(()),
+            ModificationValue::ComplexValue(complex_value) => {
+                complex_value.resolve_templates(scope)
+            }
+            ModificationValue::ComplexValueVec(complex_values) => complex_values
+                .iter_mut()
+                .try_for_each(|complex_value| complex_value.resolve_templates(scope)),
+        }
+    }
+}
+
+impl ComplexValue {
+    fn resolve_templates(&mut self, scope: &mut TemplateScope) -> Result<(), String> {
+        if let Some(template_use) = &mut self.modifier.use_template {
+            template_use.resolve(scope, false)?;
+        }
+        if let Some(otherwise) = &mut self.otherwise {
+            otherwise.resolve_templates(scope)?;
+        }
+        self.subvalues
+            .values_mut()
+            .try_for_each(|value| value.resolve_templates(scope))
+    }
+}
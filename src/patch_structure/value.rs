@@ -1,21 +1,135 @@
-use crate::patch_structure::{ModificationIdentifier, Modifier, SimpleValueType};
+use crate::patch_structure::regex::Regex;
+use crate::patch_structure::{
+    deserialize_some, is_false, serialize_some, ModificationIdentifier, Modifier,
+    ReferenceExpression, SimpleValueType,
+};
 use indexmap::map::IndexMap;
-use serde::Deserialize;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 
 #[derive(Debug, PartialEq, Clone, Deserialize)]
 pub struct ComplexValue {
     #[serde(flatten)]
     pub modifier: Modifier,
     #[serde(rename = "$attributes")]
-    pub attributes: Option<IndexMap<String, SimpleValueType>>,
+    pub attributes: Option<IndexMap<Regex, AttributeValue>>,
+    #[serde(rename = "$text", default, deserialize_with = "deserialize_some")]
+    pub text: Option<TextValue>,
+    #[serde(rename = "$append_text")]
+    pub append_text: Option<SimpleValueType>,
+    #[serde(rename = "$comment")]
+    pub comment: Option<SimpleValueType>,
+    #[serde(rename = "$cdata")]
+    pub cdata: Option<SimpleValueType>,
+    #[serde(rename = "$pi")]
+    pub processing_instructions: Option<IndexMap<String, SimpleValueType>>,
+    /// Alternative modification for elements that do not match the `$if` filter
+    #[serde(rename = "$else")]
+    pub otherwise: Option<Box<ComplexValue>>,
     #[serde(flatten)]
     pub subvalues: IndexMap<ModificationIdentifier, ModificationValue>,
 }
 
-#[derive(Debug, PartialEq, Clone, Deserialize)]
+impl ComplexValue {
+    pub fn new() -> ComplexValue {
+        ComplexValue {
+            modifier: Modifier::new(),
+            attributes: None,
+            text: None,
+            append_text: None,
+            comment: None,
+            cdata: None,
+            processing_instructions: None,
+            otherwise: None,
+            subvalues: IndexMap::new(),
+        }
+    }
+}
+
+// Writes the directives in the same order as `ComplexQuery`, followed by the subvalues
+impl Serialize for ComplexValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let modifier = &self.modifier;
+        let mut map = serializer.serialize_map(None)?;
+        serialize_some(&mut map, "$foreach", &modifier.foreach)?;
+        serialize_some(&mut map, "$if", &modifier.filter)?;
+        serialize_some(&mut map, "$else", &self.otherwise)?;
+        serialize_some(&mut map, "$use", &modifier.use_template)?;
+        serialize_some(&mut map, "$copy_from", &modifier.copy_from)?;
+        serialize_some(&mut map, "$move_from", &modifier.move_from)?;
+        serialize_some(&mut map, "$merge_from", &modifier.merge_from)?;
+        serialize_some(&mut map, "$merge", &modifier.merge)?;
+        serialize_some(&mut map, "$attributes", &self.attributes)?;
+        serialize_some(&mut map, "$text", &self.text)?;
+        serialize_some(&mut map, "$append_text", &self.append_text)?;
+        serialize_some(&mut map, "$comment", &self.comment)?;
+        serialize_some(&mut map, "$cdata", &self.cdata)?;
+        serialize_some(&mut map, "$pi", &self.processing_instructions)?;
+        serialize_some(&mut map, "$map", &modifier.map)?;
+        serialize_some(&mut map, "$copy", &modifier.copy)?;
+        serialize_some(&mut map, "$move", &modifier.move_to)?;
+        for (identifier, value) in &self.subvalues {
+            map.serialize_entry(identifier, value)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum ModificationValue {
     SimpleValue(SimpleValueType),
     ComplexValue(ComplexValue),
     ComplexValueVec(Vec<ComplexValue>),
 }
+
+/// Edits only the text children of an element, leaving all other children untouched
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TextValue {
+    Set(SimpleValueType),
+    Edit(TextEdit),
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextEdit {
+    #[serde(
+        rename = "$set",
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub set: Option<SimpleValueType>,
+    #[serde(rename = "$prepend", skip_serializing_if = "Option::is_none")]
+    pub prepend: Option<SimpleValueType>,
+    #[serde(rename = "$append", skip_serializing_if = "Option::is_none")]
+    pub append: Option<SimpleValueType>,
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AttributeValue {
+    Simple(SimpleValueType),
+    Complex(AttributeModification),
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttributeModification {
+    #[serde(
+        rename = "$value",
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub value: Option<SimpleValueType>,
+    #[serde(rename = "$rename", skip_serializing_if = "Option::is_none")]
+    pub rename: Option<ReferenceExpression>,
+    #[serde(rename = "$move", skip_serializing_if = "Option::is_none")]
+    pub move_to: Option<ReferenceExpression>,
+    // Report an error if no attribute is matching
+    #[serde(rename = "$required", default, skip_serializing_if = "is_false")]
+    pub required: bool,
+}
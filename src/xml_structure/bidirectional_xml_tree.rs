@@ -1,26 +1,114 @@
-use crate::patch_structure::ReferenceExpression;
-use itertools::Itertools;
+use crate::patch_structure::{
+    is_plain_name, MergeOptions, MergePolicy, NodePath, PathSegment, ReferenceExpression,
+    Regex as PatchRegex,
+};
+use indexmap::IndexMap;
 use regex::Regex;
-use std::cell::RefCell;
-use std::ops::{Deref, DerefMut};
-use std::rc::{Rc, Weak};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+use xml::attribute::Attribute;
+use xml::common::XmlVersion;
+use xml::name::Name;
+use xml::namespace::Namespace;
+use xml::writer::{EmitterConfig, Error as EmitterError, EventWriter, XmlEvent};
 use xmltree::XMLNode;
 
+/// Identifies a node of an `XmlTree`. The id stays valid while the node is detached and reattached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/**
+The nodes of an XML document, stored in an arena and linked by their ids.
+Parents and children are reached in constant time. Removed nodes stay in the arena,
+so they can be reattached at another position (e.g. when moving elements), until `compact` drops them.
+
+The children of an element are a doubly linked list of siblings, and the child elements with the same name
+form a second list, so detaching a node takes constant time. Inserting an element in front of a sibling
+takes time proportional to the distance to the nearest sibling with the same name.
+ **/
 pub struct XmlTree {
-    pub root: Rc<RefCell<XmlNode>>,
+    nodes: Vec<XmlNode>,
+    pub root: NodeId,
+    // The number of nodes after the tree was created or last compacted
+    compacted_len: usize,
+}
+
+impl Index<NodeId> for XmlTree {
+    type Output = XmlNode;
+
+    fn index(&self, node: NodeId) -> &XmlNode {
+        &self.nodes[node.0]
+    }
+}
+
+impl IndexMut<NodeId> for XmlTree {
+    fn index_mut(&mut self, node: NodeId) -> &mut XmlNode {
+        &mut self.nodes[node.0]
+    }
 }
 
 impl XmlTree {
     pub fn new(parsed_tree: &xmltree::Element) -> XmlTree {
-        let mut xml_tree = XmlTree {
-            root: Rc::new(RefCell::new(XmlNode {
-                parent: None,
-                data: XmlNodeData::Element(Self::parse_from_element(parsed_tree)),
-            })),
-        };
-        Self::add_element_children(&mut xml_tree.root, parsed_tree);
+        let mut xml_tree = XmlTree::with_root(Self::parse_from_element(parsed_tree));
+        xml_tree.add_element_children(xml_tree.root, parsed_tree);
+        xml_tree.compacted_len = xml_tree.nodes.len();
         xml_tree
     }
+    // Creates a tree that only contains the given element
+    pub fn with_root(root: Element) -> XmlTree {
+        XmlTree {
+            nodes: vec![XmlNode::new(XmlNodeData::Element(root))],
+            root: NodeId(0),
+            compacted_len: 1,
+        }
+    }
+    /**
+    Drops the nodes that are not reachable from the root. The ids of all other nodes become invalid.
+    The tree is only compacted once the arena has grown to twice its size after the last compaction,
+    so calling this after every change costs amortized constant time per created node.
+     **/
+    pub fn compact(&mut self) {
+        if self.nodes.len() < 2 * self.compacted_len {
+            return;
+        }
+        // Number the reachable nodes in document order
+        let mut new_ids = vec![None; self.nodes.len()];
+        let mut order = vec![];
+        let mut stack = vec![self.root];
+        while let Some(node) = stack.pop() {
+            new_ids[node.0] = Some(NodeId(order.len()));
+            order.push(node);
+            let children: Vec<NodeId> = self.children(node).collect();
+            stack.extend(children.into_iter().rev());
+        }
+        let new_id = |node: NodeId| new_ids[node.0].unwrap();
+        let mut nodes: Vec<Option<XmlNode>> = self.nodes.drain(..).map(Some).collect();
+        for old in order {
+            let mut node = nodes[old.0].take().unwrap();
+            if old == self.root {
+                // Detached roots may still point to their former parent and siblings
+                node.parent = None;
+                node.siblings = Links::default();
+                node.same_name = Links::default();
+            }
+            node.parent = node.parent.map(new_id);
+            node.siblings = node.siblings.map(new_id);
+            node.same_name = node.same_name.map(new_id);
+            if let XmlNodeData::Element(e) = &mut node.data {
+                e.children = e.children.map(|ends| ends.map(new_id));
+                for ends in e.children_by_name.values_mut() {
+                    *ends = ends.map(new_id);
+                }
+            }
+            self.nodes.push(node);
+        }
+        self.root = NodeId(0);
+        self.compacted_len = self.nodes.len();
+    }
     fn parse_from_element(xmltree_element: &xmltree::Element) -> Element {
         Element {
             prefix: xmltree_element.prefix.clone(),
@@ -31,28 +119,32 @@ impl XmlTree {
                 .map(|(k, v)| (k.clone(), v.clone()))
                 .collect(),
             applied_regexp: None,
-            children: vec![],
+            applied_attribute: None,
+            variables: vec![],
+            tables: vec![],
+            children: None,
+            children_by_name: HashMap::new(),
         }
     }
-    fn add_element_children(parent: &mut Rc<RefCell<XmlNode>>, xmltree_element: &xmltree::Element) {
+    fn add_element_children(&mut self, parent: NodeId, xmltree_element: &xmltree::Element) {
         for c in &xmltree_element.children {
             match c {
                 XMLNode::Element(e) => {
-                    let mut child =
-                        Self::append(parent, XmlNodeData::Element(Self::parse_from_element(&e)));
-                    Self::add_element_children(&mut child, &e);
+                    let child =
+                        self.append(parent, XmlNodeData::Element(Self::parse_from_element(e)));
+                    self.add_element_children(child, e);
                 }
                 XMLNode::Comment(c) => {
-                    Self::append(parent, XmlNodeData::Comment(c.clone()));
+                    self.append(parent, XmlNodeData::Comment(c.clone()));
                 }
                 XMLNode::CData(c) => {
-                    Self::append(parent, XmlNodeData::CData(c.clone()));
+                    self.append(parent, XmlNodeData::CData(c.clone()));
                 }
                 XMLNode::Text(t) => {
-                    Self::append(parent, XmlNodeData::Text(t.clone()));
+                    self.append(parent, XmlNodeData::Text(t.clone()));
                 }
                 XMLNode::ProcessingInstruction(k, v) => {
-                    Self::append(
+                    self.append(
                         parent,
                         XmlNodeData::ProcessingInstruction(k.clone(), v.clone()),
                     );
@@ -60,312 +152,1072 @@ impl XmlTree {
             }
         }
     }
-    // Appends `data` to the chain of nodes. The implementation is recursive
-    // but one could rewrite it to use a while-let imperative loop instead
-    // without too much effort.
-    pub fn append(node: &Rc<RefCell<XmlNode>>, data: XmlNodeData) -> Rc<RefCell<XmlNode>> {
-        // If the current node is the last one, create a new node,
-        // set its prev pointer to the current node, and store it as
-        // the node after the current one.
-        let rc = Rc::new(RefCell::new(XmlNode {
-            parent: Some(Rc::downgrade(&node)),
-            data: data.clone(),
-        }));
-        match &mut node.deref().deref().borrow_mut().deref_mut().data {
-            XmlNodeData::Element(element) => element.children.push(rc.clone()),
-            _ => panic!("Children can only be added to elements"),
+    // Adds a new node without a parent to the arena
+    pub fn create(&mut self, data: XmlNodeData) -> NodeId {
+        self.nodes.push(XmlNode::new(data));
+        NodeId(self.nodes.len() - 1)
+    }
+    // Appends `data` as last child of `node`
+    pub fn append(&mut self, node: NodeId, data: XmlNodeData) -> NodeId {
+        self.insert_before(node, None, data)
+    }
+    // Inserts `data` as child of `node` in front of the child `before`, or as last child if it is `None`
+    pub fn insert_before(
+        &mut self,
+        node: NodeId,
+        before: Option<NodeId>,
+        data: XmlNodeData,
+    ) -> NodeId {
+        let child = self.create(data);
+        self.insert_node_before(node, before, child);
+        child
+    }
+    // Appends an existing (detached) node as last child of `node`
+    pub fn append_node(&mut self, node: NodeId, child: NodeId) {
+        self.insert_node_before(node, None, child)
+    }
+    // Inserts an existing (detached) node as child of `node` in front of the child `before`, or as last child
+    pub fn insert_node_before(&mut self, node: NodeId, before: Option<NodeId>, child: NodeId) {
+        self[child].parent = Some(node);
+        let ends = self.element(node).children;
+        let ends = self.link(child, ends, before, |n| &mut n.siblings);
+        self.element_mut(node).children = Some(ends);
+        if let Some(name) = self[child].name().map(str::to_string) {
+            let ends = self.element(node).children_by_name.get(&name).copied();
+            let before = ends.and_then(|_| self.next_with_name(child, &name));
+            let ends = self.link(child, ends, before, |n| &mut n.same_name);
+            self.element_mut(node).children_by_name.insert(name, ends);
         }
-        rc
     }
-
-    pub fn to_xmltree(&self) -> xmltree::Element {
-        match &self.root.deref().borrow().data {
-            XmlNodeData::Element(e) => Self::element_to_xmltree_element(e),
-            _ => panic!("Root node of XML must be an element"),
+    /**
+    Links the node into a list in front of `before`, or at its end, and returns the new ends of the list.
+    `links` selects the list: all siblings or the siblings with the same name.
+     **/
+    fn link(
+        &mut self,
+        node: NodeId,
+        ends: Option<ChildList>,
+        before: Option<NodeId>,
+        links: fn(&mut XmlNode) -> &mut Links,
+    ) -> ChildList {
+        let prev = match before {
+            Some(before) => links(&mut self[before]).prev,
+            None => ends.map(|ends| ends.last),
+        };
+        *links(&mut self[node]) = Links { prev, next: before };
+        if let Some(prev) = prev {
+            links(&mut self[prev]).next = Some(node);
+        }
+        if let Some(before) = before {
+            links(&mut self[before]).prev = Some(node);
+        }
+        ChildList {
+            first: ends
+                .filter(|_| prev.is_some())
+                .map_or(node, |ends| ends.first),
+            last: ends
+                .filter(|_| before.is_some())
+                .map_or(node, |ends| ends.last),
         }
     }
-    fn node_to_xmltree_node(node: &Rc<RefCell<XmlNode>>) -> xmltree::XMLNode {
-        match &node.deref().borrow().data {
-            XmlNodeData::Element(e) => {
-                xmltree::XMLNode::Element(Self::element_to_xmltree_element(&e))
+    // Unlinks the node from a list with the given ends and returns the new ends, `None` if the list is empty
+    fn unlink(
+        &mut self,
+        node: NodeId,
+        ends: ChildList,
+        links: fn(&mut XmlNode) -> &mut Links,
+    ) -> Option<ChildList> {
+        let Links { prev, next } = std::mem::take(links(&mut self[node]));
+        if let Some(prev) = prev {
+            links(&mut self[prev]).next = next;
+        }
+        if let Some(next) = next {
+            links(&mut self[next]).prev = prev;
+        }
+        match (prev, next) {
+            (None, None) => None,
+            _ => Some(ChildList {
+                first: if prev.is_some() {
+                    ends.first
+                } else {
+                    next.unwrap()
+                },
+                last: if next.is_some() {
+                    ends.last
+                } else {
+                    prev.unwrap()
+                },
+            }),
+        }
+    }
+    /**
+    Returns the next sibling with the given name of a linked node, given that one of its siblings has this name.
+    The siblings are searched in both directions, so this takes time proportional to the distance to the
+    nearest sibling with the name.
+     **/
+    fn next_with_name(&self, node: NodeId, name: &str) -> Option<NodeId> {
+        let (mut left, mut right) = (self[node].siblings.prev, self[node].siblings.next);
+        while left.is_some() || right.is_some() {
+            if let Some(next) = right {
+                if self[next].name() == Some(name) {
+                    return Some(next);
+                }
+                right = self[next].siblings.next;
             }
-            XmlNodeData::Comment(s) => xmltree::XMLNode::Comment(s.clone()),
-            XmlNodeData::CData(s) => xmltree::XMLNode::CData(s.clone()),
-            XmlNodeData::Text(s) => xmltree::XMLNode::Text(s.clone()),
-            XmlNodeData::ProcessingInstruction(k, v) => {
-                xmltree::XMLNode::ProcessingInstruction(k.clone(), v.clone())
+            if let Some(prev) = left {
+                if self[prev].name() == Some(name) {
+                    return self[prev].same_name.next;
+                }
+                left = self[prev].siblings.prev;
             }
         }
+        None
     }
-    fn element_to_xmltree_element(element: &Element) -> xmltree::Element {
-        xmltree::Element {
-            prefix: element.prefix.clone(),
-            namespace: None,
-            namespaces: None,
-            name: element.name.clone(),
-            attributes: element
-                .attributes
-                .iter()
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .collect(),
-            children: element
-                .children
-                .iter()
-                .map(|c| Self::node_to_xmltree_node(c))
-                .collect(),
+    fn element(&self, node: NodeId) -> &Element {
+        match &self[node].data {
+            XmlNodeData::Element(element) => element,
+            _ => panic!("Children can only be added to elements"),
         }
     }
-}
-
-#[derive(Clone, Debug)]
-pub struct XmlNode {
-    pub parent: Option<Weak<RefCell<XmlNode>>>,
-    pub data: XmlNodeData,
-}
-
-pub enum MoveCopyAction {
-    Move,
-    Copy,
-}
-
-impl XmlNode {
-    pub fn children(&self) -> XmlNodeIntoIterator {
-        match &self.data {
-            XmlNodeData::Element(e) => XmlNodeIntoIterator {
-                nodes: e.children.iter().map(Rc::downgrade).collect(),
-                index: 0,
-            },
-            _ => XmlNodeIntoIterator {
-                nodes: vec![],
-                index: 0,
-            },
+    fn element_mut(&mut self, node: NodeId) -> &mut Element {
+        match &mut self[node].data {
+            XmlNodeData::Element(element) => element,
+            _ => panic!("Children can only be added to elements"),
         }
     }
-    pub fn clear_children(&mut self) -> bool {
-        match &mut self.data {
+
+    /// Writes the tree as XML document with the root element
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), EmitterError> {
+        let mut emitter = EventWriter::new_with_config(writer, EmitterConfig::new());
+        emitter.write(XmlEvent::StartDocument {
+            version: XmlVersion::Version10,
+            encoding: None,
+            standalone: None,
+        })?;
+        self.write_node(self.root, &mut emitter)
+    }
+    /// Writes the node and its descendants
+    pub fn write_node<W: Write>(
+        &self,
+        node: NodeId,
+        emitter: &mut EventWriter<W>,
+    ) -> Result<(), EmitterError> {
+        match &self[node].data {
             XmlNodeData::Element(e) => {
-                e.children.clear();
-                true
+                Self::write_start(e, emitter)?;
+                for child in self.children(node) {
+                    self.write_node(child, emitter)?;
+                }
+                emitter.write(XmlEvent::end_element())
+            }
+            XmlNodeData::Comment(s) => emitter.write(XmlEvent::Comment(s)),
+            XmlNodeData::CData(s) => emitter.write(XmlEvent::CData(s)),
+            XmlNodeData::Text(s) => emitter.write(XmlEvent::Characters(s)),
+            XmlNodeData::ProcessingInstruction(name, data) => {
+                emitter.write(XmlEvent::ProcessingInstruction {
+                    name,
+                    data: data.as_deref(),
+                })
             }
-            _ => false,
         }
     }
-    pub fn remove(node: Rc<RefCell<XmlNode>>) -> bool {
-        let parent = match &node.borrow().parent {
-            None => {
-                return false;
-            }
-            Some(p) => p.upgrade(),
-        };
-        let parent = match parent {
-            None => {
-                return false;
-            }
-            Some(p) => p.clone(),
+    /// Writes the start tag of the element. Namespaces are not kept, only the prefix.
+    pub fn write_start<W: Write>(
+        element: &Element,
+        emitter: &mut EventWriter<W>,
+    ) -> Result<(), EmitterError> {
+        let mut name = Name::local(&element.name);
+        name.prefix = element.prefix.as_deref();
+        let attributes: Vec<_> = element
+            .attributes
+            .iter()
+            .map(|(name, value)| Attribute::new(Name::local(name), value))
+            .collect();
+        emitter.write(XmlEvent::StartElement {
+            name,
+            attributes: Cow::Owned(attributes),
+            namespace: Cow::Owned(Namespace::empty()),
+        })
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self[node].parent
+    }
+    // Returns the children of an element; other nodes have no children
+    pub fn children(&self, node: NodeId) -> Children<'_> {
+        let first = match &self[node].data {
+            XmlNodeData::Element(e) => e.children.map(|ends| ends.first),
+            _ => None,
         };
-        node.borrow_mut().parent = None;
-        let result = parent.borrow_mut().cleanup_dependencies();
-        result
+        Children {
+            tree: self,
+            next: first,
+            same_name: false,
+        }
     }
-    pub fn cleanup_dependencies(&mut self) -> bool {
-        match &mut self.data {
+    pub fn first_child(&self, node: NodeId) -> Option<NodeId> {
+        self.children(node).next()
+    }
+    pub fn last_child(&self, node: NodeId) -> Option<NodeId> {
+        match &self[node].data {
+            XmlNodeData::Element(e) => e.children.map(|ends| ends.last),
+            _ => None,
+        }
+    }
+    // Returns the sibling following the node, `None` for the last child or a detached node
+    pub fn next_sibling(&self, node: NodeId) -> Option<NodeId> {
+        self[node].siblings.next
+    }
+    pub fn clear_children(&mut self, node: NodeId) -> bool {
+        let children: Vec<NodeId> = self.children(node).collect();
+        match &mut self[node].data {
             XmlNodeData::Element(e) => {
-                e.children.retain(|e| e.deref().borrow().parent.is_some());
-            }
-            _ => {
-                return false;
+                e.children = None;
+                e.children_by_name.clear();
             }
+            _ => return false,
+        }
+        for child in children {
+            let child = &mut self[child];
+            child.parent = None;
+            child.siblings = Links::default();
+            child.same_name = Links::default();
         }
         true
     }
-    pub fn name(&self) -> Option<String> {
-        match &self.data {
-            XmlNodeData::Element(e) => Some(e.name.clone()),
+    // Returns the child elements with the given name in document order, without scanning all children
+    pub fn children_by_name(&self, node: NodeId, name: &str) -> Children<'_> {
+        let first = match &self[node].data {
+            XmlNodeData::Element(e) => e.children_by_name.get(name).map(|ends| ends.first),
             _ => None,
+        };
+        Children {
+            tree: self,
+            next: first,
+            same_name: true,
         }
     }
-    pub fn set_name(&mut self, new_name: &String) -> bool {
-        match &mut self.data {
+    // Returns the first child element with the given name
+    pub fn child_by_name(&self, node: NodeId, name: &str) -> Option<NodeId> {
+        self.children_by_name(node, name).next()
+    }
+    /// Renames the element and updates the name index of its parent. Returns false for other nodes.
+    pub fn set_name(&mut self, node: NodeId, new_name: &str) -> bool {
+        if self[node].name().is_none() {
+            return false;
+        }
+        // Reinserting keeps the position among the siblings
+        let position = self
+            .parent(node)
+            .map(|parent| (parent, self.next_sibling(node)));
+        self.remove(node);
+        if let XmlNodeData::Element(e) = &mut self[node].data {
+            e.name = new_name.to_string();
+        }
+        if let Some((parent, before)) = position {
+            self.insert_node_before(parent, before, node);
+        }
+        true
+    }
+    // Returns the concatenated texts and CDATA sections of this node
+    pub fn text(&self, node: NodeId) -> String {
+        self.children(node)
+            .filter_map(|c| match &self[c].data {
+                XmlNodeData::Text(s) | XmlNodeData::CData(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+    // Replaces `from` by `to` in all texts and attribute values of this node and its descendants
+    pub fn replace_in_texts(&mut self, node: NodeId, from: &str, to: &str) {
+        match &mut self[node].data {
             XmlNodeData::Element(e) => {
-                e.name = new_name.clone();
-                true
+                for (_, value) in e.attributes.iter_mut() {
+                    *value = value.replace(from, to);
+                }
+            }
+            XmlNodeData::Text(s) | XmlNodeData::CData(s) => *s = s.replace(from, to),
+            _ => {}
+        }
+        let children: Vec<NodeId> = self.children(node).collect();
+        for child in children {
+            self.replace_in_texts(child, from, to);
+        }
+    }
+    /// Replaces all text children by a single text at the position of the first one.
+    /// Other children are kept. If `text` is `None`, all text children are removed.
+    pub fn set_text(&mut self, node: NodeId, text: Option<String>) {
+        if !matches!(self[node].data, XmlNodeData::Element(_)) {
+            return;
+        }
+        let texts: Vec<NodeId> = self
+            .children(node)
+            .filter(|c| matches!(self[*c].data, XmlNodeData::Text(_)))
+            .collect();
+        if let Some(text) = text {
+            let before = texts.first().copied();
+            self.insert_before(node, before, XmlNodeData::Text(text));
+        }
+        for text in texts {
+            self.remove(text);
+        }
+    }
+    /// Prepends `text` to the first child if it is a text, otherwise a new text is inserted in front
+    pub fn prepend_text(&mut self, node: NodeId, text: String) {
+        match self.first_child(node) {
+            Some(first) if matches!(self[first].data, XmlNodeData::Text(_)) => {
+                let data = &mut self[first].data;
+                let content = format!("{}{}", text, data.content().unwrap_or_default());
+                data.set_content(content);
+            }
+            first => {
+                self.insert_before(node, first, XmlNodeData::Text(text));
+            }
+        }
+    }
+    /// Appends `text` to the last child if it is a text, otherwise a new text is appended
+    pub fn append_text(&mut self, node: NodeId, text: String) {
+        if !matches!(self[node].data, XmlNodeData::Element(_)) {
+            return;
+        }
+        match self.last_child(node) {
+            Some(last) if matches!(self[last].data, XmlNodeData::Text(_)) => {
+                let data = &mut self[last].data;
+                let content = format!("{}{}", data.content().unwrap_or_default(), text);
+                data.set_content(content);
+            }
+            _ => {
+                self.append(node, XmlNodeData::Text(text));
             }
-            _ => false,
         }
     }
-    pub fn get_node_info_by_path(
-        current_node: Rc<RefCell<XmlNode>>,
-        path: Vec<String>,
-        auto_create: bool,
-    ) -> Rc<RefCell<XmlNode>> {
-        let mut current_node = current_node.clone();
-        for segment in &path {
-            match segment.as_str() {
-                ".." => {
-                    let new_node = match &current_node.deref().borrow().parent {
-                        None => panic!("Path reaches end of XML tree!"),
-                        Some(p) => p.upgrade().unwrap().clone(),
-                    };
-                    current_node = new_node;
+    // Detaches the node from its parent in constant time. The node and its descendants can be inserted again.
+    pub fn remove(&mut self, node: NodeId) -> bool {
+        let parent = match self[node].parent.take() {
+            None => return false,
+            Some(parent) => parent,
+        };
+        let ends = self.element(parent).children.unwrap();
+        let ends = self.unlink(node, ends, |n| &mut n.siblings);
+        self.element_mut(parent).children = ends;
+        if let Some(name) = self[node].name().map(str::to_string) {
+            let ends = self.element(parent).children_by_name[&name];
+            match self.unlink(node, ends, |n| &mut n.same_name) {
+                Some(ends) => self.element_mut(parent).children_by_name.insert(name, ends),
+                None => self.element_mut(parent).children_by_name.remove(&name),
+            };
+        }
+        true
+    }
+    /// Looks up a variable on the node and its ancestors, the innermost binding wins
+    pub fn variable(&self, node: NodeId, name: &str) -> Option<String> {
+        let mut current = Some(node);
+        while let Some(node) = current {
+            if let XmlNodeData::Element(e) = &self[node].data {
+                if let Some((_, value)) = e.variables.iter().rev().find(|(n, _)| n == name) {
+                    return Some(value.clone());
                 }
-                "." => {}
-                queried_name => {
-                    let children_candidates: Vec<(String, Rc<RefCell<XmlNode>>)> = current_node
-                        .deref()
-                        .borrow()
-                        .children()
-                        .filter_map(|c| match c.deref().borrow().name() {
-                            None => None,
-                            Some(name) => Some((name, c.clone())),
-                        })
-                        .filter(|(name, _)| name == &String::from(queried_name))
-                        .collect();
-                    current_node = match &children_candidates.len() {
-                        1 => children_candidates.first().unwrap().clone().1,
-                        0 => {
-                            if auto_create {
-                                XmlTree::append(
-                                    &current_node,
-                                    XmlNodeData::Element(Element {
-                                        prefix: None,
-                                        name: queried_name.to_string(),
-                                        attributes: Vec::new(),
-                                        applied_regexp: None,
-                                        children: vec![],
-                                    }),
-                                )
-                            } else {
-                                panic!("Path not found! ({:?})", path)
-                            }
-                        }
-                        _ => panic!("More than one XML node is matching the path!"),
-                    };
+            }
+            current = self[node].parent;
+        }
+        None
+    }
+    /// Looks up a table on the node and its ancestors, the innermost binding wins
+    pub fn table(&self, node: NodeId, name: &str) -> Option<Arc<IndexMap<String, String>>> {
+        let mut current = Some(node);
+        while let Some(node) = current {
+            if let XmlNodeData::Element(e) = &self[node].data {
+                if let Some((_, table)) = e.tables.iter().rev().find(|(n, _)| n == name) {
+                    return Some(table.clone());
                 }
             }
+            current = self[node].parent;
         }
-        current_node
+        None
     }
-    pub fn set_regex(&mut self, regex: Option<Regex>) {
-        match &mut self.data {
-            XmlNodeData::Element(e) => e.applied_regexp = regex,
-            _ => {}
+    // A key is either the name of a child element (compared with its text) or an attribute name prefixed with `@`
+    pub fn has_key(&self, node: NodeId, key: &str, value: &str) -> bool {
+        match key.strip_prefix('@') {
+            Some(attribute) => self[node].get_attribute(attribute).as_deref() == Some(value),
+            None => self
+                .child_by_name(node, key)
+                .is_some_and(|child| self.text(child) == value),
         }
     }
-    pub fn get_regex(&self) -> Option<Regex> {
-        match &self.data {
-            XmlNodeData::Element(e) => e.applied_regexp.clone(),
-            _ => None,
+    pub fn key_value(&self, node: NodeId, key: &str) -> Option<String> {
+        match key.strip_prefix('@') {
+            Some(attribute) => self[node].get_attribute(attribute),
+            None => self.child_by_name(node, key).map(|child| self.text(child)),
         }
     }
-    pub fn deep_clone(node: Rc<RefCell<XmlNode>>) -> Rc<RefCell<XmlNode>> {
-        let (node_data, children) = match &node.borrow().data {
-            XmlNodeData::Element(e) => (
-                XmlNodeData::Element(e.deep_clone()),
-                Some(e.children.clone()),
-            ),
-            XmlNodeData::Comment(s) => (XmlNodeData::Comment(s.clone()), None),
-            XmlNodeData::CData(s) => (XmlNodeData::CData(s.clone()), None),
-            XmlNodeData::Text(s) => (XmlNodeData::Text(s.clone()), None),
-            XmlNodeData::ProcessingInstruction(k, v) => (
-                XmlNodeData::ProcessingInstruction(k.clone(), v.clone()),
-                None,
-            ),
-        };
-        let cloned = Rc::new(RefCell::new(XmlNode {
-            parent: None,
-            data: node_data,
-        }));
-        match children {
-            None => {}
-            Some(children) => {
-                for c in children {
-                    let cloned_child = (&XmlNode::deep_clone(c).borrow().data).clone();
-                    XmlTree::append(&cloned, cloned_child);
-                }
+    pub fn set_key(&mut self, node: NodeId, key: &str, value: String) {
+        match key.strip_prefix('@') {
+            Some(attribute) => {
+                self[node].set_attribute(attribute, value);
+            }
+            None => {
+                let child = self.append(node, XmlNodeData::Element(Element::new(key.to_string())));
+                self.append(child, XmlNodeData::Text(value));
             }
         }
+    }
+    // Copies the node and its descendants. The copy has no parent.
+    pub fn deep_clone(&mut self, node: NodeId) -> NodeId {
+        let node_data = match &self[node].data {
+            XmlNodeData::Element(e) => XmlNodeData::Element(e.deep_clone()),
+            data => data.clone(),
+        };
+        let children: Vec<NodeId> = self.children(node).collect();
+        let cloned = self.create(node_data);
+        for c in children {
+            let child = self.deep_clone(c);
+            self.append_node(cloned, child);
+        }
         cloned
     }
+    // Returns the position of the node within the children of its parent, in time linear in the position
+    pub fn index_in_parent(&self, node: NodeId) -> Option<usize> {
+        self[node].parent?;
+        let mut index = 0;
+        let mut current = self[node].siblings.prev;
+        while let Some(prev) = current {
+            index += 1;
+            current = self[prev].siblings.prev;
+        }
+        Some(index)
+    }
+    /// Encloses the node in a new element at the same position
+    pub fn wrap(&mut self, node: NodeId, name: String) -> Result<(), String> {
+        let parent = match self[node].parent {
+            None => return Err(format!("Cannot wrap \"{}\" without a parent", name)),
+            Some(parent) => parent,
+        };
+        let before = self.next_sibling(node);
+        self.remove(node);
+        let wrapper = self.insert_before(parent, before, XmlNodeData::Element(Element::new(name)));
+        self.append_node(wrapper, node);
+        Ok(())
+    }
+    /// Replaces the node by its children. The root element must have exactly one element child.
+    pub fn unwrap(&mut self, node: NodeId) -> Result<(), String> {
+        let parent = match self[node].parent {
+            None => return Err("Cannot unwrap an element without a parent".to_string()),
+            Some(parent) => parent,
+        };
+        let children: Vec<NodeId> = self.children(node).collect();
+        if self[parent].parent.is_none()
+            && children
+                .iter()
+                .filter(|c| matches!(self[**c].data, XmlNodeData::Element(_)))
+                .count()
+                != 1
+        {
+            return Err(
+                "The root element can only be unwrapped if it has exactly one element child"
+                    .to_string(),
+            );
+        }
+        let before = self.next_sibling(node);
+        self.remove(node);
+        self.clear_children(node);
+        for child in children {
+            self.insert_node_before(parent, before, child);
+        }
+        Ok(())
+    }
+    /**
+    Moves or copies a node to the path given by the expression, relative to the parent of the node.
+    The last path segment is the new name of the node (empty to keep the name).
+    Missing elements with plain names are created. Other segments are regular expressions; a copy is made
+    for every matching destination and the captures of the destination matches are available as variables
+    (`[$1]`, `[$name]`). A node can only be moved to a single destination.
+     **/
     pub fn move_copy_node(
-        xml_parent_node: &Rc<RefCell<XmlNode>>,
+        &mut self,
+        xml_node: NodeId,
         move_copy_expression: &ReferenceExpression,
         move_copy: MoveCopyAction,
-    ) {
-        //Moving parent_node to somewhere else...
-        let move_expression = move_copy_expression.evaluate(xml_parent_node);
-        let path = move_expression.split("/").map(String::from);
-        let mut path = path.collect_vec();
+    ) -> Result<(), String> {
+        let mut path = move_copy_expression.split_path();
         let new_name = path.pop().unwrap();
-        if !new_name.is_empty() {
-            if !xml_parent_node.borrow_mut().set_name(&new_name) {
-                panic!("Could not set name \"{}\" for XML node.", new_name)
+        if path.is_empty() && matches!(move_copy, MoveCopyAction::Move) {
+            // Renaming only, which is allowed for the root node, too
+            let new_name = new_name.evaluate(self, xml_node)?;
+            if !new_name.is_empty() && !self.set_name(xml_node, &new_name) {
+                return Err(format!("Could not set name \"{}\" for XML node.", new_name));
             }
+            return Ok(());
         }
-        if !path.is_empty() {
-            //Start searching from parent of parent_node (the location of parent_node)...
-            let parent_parent_node = match &xml_parent_node.borrow().parent {
-                None => panic!("Root node is not allowed to be moved..."),
-                Some(p) => p.upgrade().unwrap(),
+        let parent_node = match self[xml_node].parent {
+            None => return Err("Root node is not allowed to be moved or copied".to_string()),
+            Some(parent) => parent,
+        };
+        let path = NodePath {
+            segments: path
+                .into_iter()
+                .map(|name| PathSegment { name, key: None })
+                .collect(),
+            content_only: false,
+        };
+        let options = PathOptions {
+            create: true,
+            multiple: matches!(move_copy, MoveCopyAction::Copy),
+        };
+        let targets = self
+            .resolve_path(xml_node, parent_node, &path, options)
+            .map_err(|e| format!("Destination of \"{}\": {}", move_copy_expression, e))?;
+        if targets.is_empty() {
+            return Err(format!(
+                "\"{}\" is matching no destination",
+                move_copy_expression
+            ));
+        }
+        for target in targets {
+            if matches!(move_copy, MoveCopyAction::Move)
+                && self.is_ancestor_or_self(xml_node, target.node)
+            {
+                return Err(format!(
+                    "\"{}\" cannot move an element into itself or its descendants",
+                    move_copy_expression
+                ));
+            }
+            let new_name = new_name.evaluate_with(self, xml_node, &target.bindings)?;
+            let target_node = self.create_missing(&target);
+            let node = match move_copy {
+                MoveCopyAction::Move => {
+                    self.remove(xml_node);
+                    xml_node
+                }
+                MoveCopyAction::Copy => self.deep_clone(xml_node),
             };
-            let new_parent_node =
-                XmlNode::get_node_info_by_path(parent_parent_node.clone(), path, true);
-            match move_copy {
+            if !new_name.is_empty() && !self.set_name(node, &new_name) {
+                return Err(format!("Could not set name \"{}\" for XML node.", new_name));
+            }
+            self.append_node(target_node, node);
+        }
+        Ok(())
+    }
+    /**
+    Copies or moves the elements found at the path into the given node. If the path ends with `/`,
+    the children and missing attributes of the found elements are merged into the node instead.
+     **/
+    pub fn pull_nodes(
+        &mut self,
+        xml_node: NodeId,
+        path: &NodePath,
+        move_copy: MoveCopyAction,
+    ) -> Result<(), String> {
+        let sources: Vec<NodeId> = self
+            .resolve_path(
+                xml_node,
+                xml_node,
+                path,
+                PathOptions {
+                    create: false,
+                    multiple: true,
+                },
+            )?
+            .into_iter()
+            .map(|path_match| path_match.node)
+            .collect();
+        if sources.is_empty() {
+            return Err(format!("No element is matching \"{}\"", path));
+        }
+        for source in sources {
+            if self.is_ancestor_or_self(source, xml_node) {
+                return Err(format!(
+                    "\"{}\" cannot be pulled into itself or its descendants",
+                    path
+                ));
+            }
+            let source = match move_copy {
                 MoveCopyAction::Move => {
-                    XmlNode::remove(xml_parent_node.clone());
-                    let xml_node_data = xml_parent_node.borrow().data.clone();
-                    XmlTree::append(&new_parent_node, xml_node_data);
+                    self.remove(source);
+                    source
+                }
+                MoveCopyAction::Copy => self.deep_clone(source),
+            };
+            if !path.content_only {
+                self.append_node(xml_node, source);
+                continue;
+            }
+            for name in self[source].attribute_names() {
+                if self[xml_node].get_attribute(&name).is_none() {
+                    let value = self[source].get_attribute(&name).unwrap();
+                    self[xml_node].set_attribute(&name, value);
+                }
+            }
+            let children: Vec<NodeId> = self.children(source).collect();
+            self.clear_children(source);
+            for child in children {
+                self.append_node(xml_node, child);
+            }
+        }
+        Ok(())
+    }
+    /**
+    Recursively merges the elements found at the path of the options into the given node.
+    Attributes are overlaid, texts are replaced and children without a counterpart are appended.
+     **/
+    pub fn merge_nodes(&mut self, xml_node: NodeId, options: &MergeOptions) -> Result<(), String> {
+        let sources: Vec<NodeId> = self
+            .resolve_path(
+                xml_node,
+                xml_node,
+                &options.from,
+                PathOptions {
+                    create: false,
+                    multiple: true,
+                },
+            )?
+            .into_iter()
+            .map(|path_match| path_match.node)
+            .collect();
+        if sources.is_empty() {
+            return Err(format!("No element is matching \"{}\"", options.from));
+        }
+        for source in sources {
+            let source = self.deep_clone(source);
+            self.merge_node(xml_node, source, options)?;
+        }
+        Ok(())
+    }
+    fn merge_node(
+        &mut self,
+        ours: NodeId,
+        theirs: NodeId,
+        options: &MergeOptions,
+    ) -> Result<(), String> {
+        let name = self[ours].name().unwrap_or_default().to_string();
+        let conflict = |what: String, our_value: &str, their_value: &str| -> Result<bool, String> {
+            match options.policy {
+                MergePolicy::Ours => Ok(false),
+                MergePolicy::Theirs => Ok(true),
+                MergePolicy::Error => Err(format!(
+                    "Merge conflict at {} of \"{}\": \"{}\" != \"{}\"",
+                    what, name, our_value, their_value
+                )),
+            }
+        };
+        for attribute in self[theirs].attribute_names() {
+            let value = self[theirs].get_attribute(&attribute).unwrap();
+            let overwrite = match self[ours].get_attribute(&attribute) {
+                None => true,
+                Some(current) if current == value => false,
+                Some(current) => {
+                    conflict(format!("attribute \"{}\"", attribute), &current, &value)?
+                }
+            };
+            if overwrite {
+                self[ours].set_attribute(&attribute, value);
+            }
+        }
+        let (our_text, their_text) = (self.text(ours), self.text(theirs));
+        if !their_text.is_empty()
+            && our_text != their_text
+            && (our_text.is_empty() || conflict("text".to_string(), &our_text, &their_text)?)
+        {
+            self.set_text(ours, Some(their_text));
+        }
+        let mut matched: Vec<NodeId> = vec![];
+        let children: Vec<NodeId> = self.children(theirs).collect();
+        for child in children {
+            let child_name = match &self[child].data {
+                XmlNodeData::Element(e) => Some(e.name.clone()),
+                XmlNodeData::Text(_) => continue,
+                _ => None,
+            };
+            let child_name = match child_name {
+                Some(child_name) => child_name,
+                None => {
+                    // Comments, CDATA sections and processing instructions are only added once
+                    if !self
+                        .children(ours)
+                        .any(|c| self[c].data == self[child].data)
+                    {
+                        self.remove(child);
+                        self.append_node(ours, child);
+                    }
+                    continue;
+                }
+            };
+            let key_value = options
+                .key
+                .as_ref()
+                .and_then(|key| self.key_value(child, key).map(|value| (key, value)));
+            let counterpart = self.children(ours).find(|c| {
+                self[*c].name() == Some(child_name.as_str())
+                    && !matched.contains(c)
+                    && key_value
+                        .as_ref()
+                        .is_none_or(|(key, value)| self.has_key(*c, key, value))
+            });
+            match counterpart {
+                Some(counterpart) => {
+                    self.merge_node(counterpart, child, options)?;
+                    matched.push(counterpart);
                 }
-                MoveCopyAction::Copy => {
-                    // Copying...
-                    let xml_node_data = XmlNode::deep_clone(xml_parent_node.clone())
-                        .borrow()
-                        .data
-                        .clone();
-                    XmlTree::append(&new_parent_node, xml_node_data.clone());
+                None => {
+                    self.remove(child);
+                    self.append_node(ours, child);
+                    matched.push(child);
                 }
             }
         }
+        Ok(())
+    }
+    /**
+    Follows the path from `start` and returns the matching elements. The segments are evaluated at `context`.
+
+    Segments are `..`, `.` (or empty) or element names, optionally with a key (`register[name=CR1]`).
+    Plain names are matched exactly, other names are regular expressions whose captures are returned
+    as bindings (`1`, `2`, ... and the group names), which are available to the following segments.
+    With `create`, plain names that match no element are returned as missing elements instead of ending
+    the path. Without `multiple`, matching more than one element is an error.
+     **/
+    pub fn resolve_path(
+        &self,
+        context: NodeId,
+        start: NodeId,
+        path: &NodePath,
+        options: PathOptions,
+    ) -> Result<Vec<PathMatch>, String> {
+        let mut matches = vec![PathMatch {
+            node: start,
+            missing: vec![],
+            bindings: vec![],
+        }];
+        for segment in &path.segments {
+            let mut next_matches = vec![];
+            for path_match in matches {
+                let name = segment
+                    .name
+                    .evaluate_with(self, context, &path_match.bindings)?;
+                if !path_match.missing.is_empty() {
+                    next_matches.extend(Self::step_missing(path_match, &name));
+                    continue;
+                }
+                match name.as_str() {
+                    ".." => match self[path_match.node].parent {
+                        None => return Err(format!("\"{}\" leaves the XML tree", path)),
+                        Some(parent) => next_matches.push(PathMatch {
+                            node: parent,
+                            ..path_match
+                        }),
+                    },
+                    "." | "" => next_matches.push(path_match),
+                    name => {
+                        let key = match &segment.key {
+                            Some(key) => Some((
+                                key.name.as_str(),
+                                key.value
+                                    .evaluate_with(self, context, &path_match.bindings)?,
+                            )),
+                            None => None,
+                        };
+                        let has_key = |c: NodeId| {
+                            key.as_ref()
+                                .is_none_or(|(key, value)| self.has_key(c, key, value))
+                        };
+                        let found = next_matches.len();
+                        if is_plain_name(name) {
+                            for child in self.children_by_name(path_match.node, name) {
+                                if has_key(child) {
+                                    next_matches.push(PathMatch {
+                                        node: child,
+                                        ..path_match.clone()
+                                    });
+                                }
+                            }
+                            if next_matches.len() == found && options.create && key.is_none() {
+                                next_matches.push(PathMatch {
+                                    missing: vec![name.to_string()],
+                                    ..path_match
+                                });
+                            }
+                            continue;
+                        }
+                        let regex = PatchRegex::try_from(name.to_string())?.regex;
+                        let offset = path_match
+                            .bindings
+                            .iter()
+                            .filter(|(n, _)| n.parse::<usize>().is_ok())
+                            .count();
+                        for child in self.children(path_match.node) {
+                            let captures = match self[child].name().and_then(|n| regex.captures(n))
+                            {
+                                Some(captures) if has_key(child) => captures,
+                                _ => continue,
+                            };
+                            let mut bindings = path_match.bindings.clone();
+                            for (i, group) in regex.capture_names().enumerate().skip(1) {
+                                let value = captures.get(i).map_or("", |m| m.as_str()).to_string();
+                                if let Some(group) = group {
+                                    bindings.push((group.to_string(), value.clone()));
+                                }
+                                bindings.push(((offset + i).to_string(), value));
+                            }
+                            next_matches.push(PathMatch {
+                                node: child,
+                                missing: vec![],
+                                bindings,
+                            });
+                        }
+                    }
+                }
+            }
+            if !options.multiple && next_matches.len() > 1 {
+                return Err(format!(
+                    "\"{}\" is matching {} elements, but only one is allowed",
+                    path,
+                    next_matches.len()
+                ));
+            }
+            matches = next_matches;
+        }
+        Ok(matches)
+    }
+    // Continues a path below an element that does not exist yet
+    fn step_missing(mut path_match: PathMatch, name: &str) -> Option<PathMatch> {
+        match name {
+            ".." => {
+                path_match.missing.pop();
+            }
+            "." | "" => {}
+            name if is_plain_name(name) => path_match.missing.push(name.to_string()),
+            // Regular expressions cannot be created
+            _ => return None,
+        }
+        Some(path_match)
+    }
+    /// Creates the missing elements of a match of `resolve_path` and returns the last one
+    pub fn create_missing(&mut self, path_match: &PathMatch) -> NodeId {
+        let mut node = path_match.node;
+        for name in &path_match.missing {
+            node = self.append(node, XmlNodeData::Element(Element::new(name.clone())));
+        }
+        node
+    }
+    fn is_ancestor_or_self(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut current = Some(node);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self[node].parent;
+        }
+        false
     }
 }
 
-impl PartialEq for XmlNode {
-    fn eq(&self, other: &Self) -> bool {
-        let parents_equal = match (&self.parent, &other.parent) {
-            (None, None) => true,
-            (Some(s), Some(o)) => match (s.upgrade(), o.upgrade()) {
-                (None, None) => true,
-                (Some(s), Some(o)) => s.deref().borrow().deref() == o.deref().borrow().deref(),
-                (_, _) => false,
-            },
-            (_, _) => false,
-        };
-        &self.data == &other.data && parents_equal
+/// Options of `XmlTree::resolve_path`
+#[derive(Clone, Copy, Debug)]
+pub struct PathOptions {
+    /// Plain names that match no element are returned as missing elements
+    pub create: bool,
+    /// More than one element may match the path
+    pub multiple: bool,
+}
+
+/// An element found by `XmlTree::resolve_path`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathMatch {
+    pub node: NodeId,
+    /// The names of the elements below `node` that are missing, created by `XmlTree::create_missing`
+    pub missing: Vec<String>,
+    /// The captures of the regular expressions on the path, usable as variables
+    pub bindings: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct XmlNode {
+    pub parent: Option<NodeId>,
+    // The neighbours among all children of the parent
+    siblings: Links,
+    // The neighbours among the children of the parent with the same name, only used for elements
+    same_name: Links,
+    pub data: XmlNodeData,
+}
+
+// The neighbours of a node in a list of children
+#[derive(Clone, Copy, Debug, Default)]
+struct Links {
+    prev: Option<NodeId>,
+    next: Option<NodeId>,
+}
+
+impl Links {
+    fn map(self, f: impl Fn(NodeId) -> NodeId) -> Links {
+        Links {
+            prev: self.prev.map(&f),
+            next: self.next.map(&f),
+        }
     }
 }
 
-impl Eq for XmlNode {}
+// The first and the last node of a non-empty list of children
+#[derive(Clone, Copy, Debug)]
+struct ChildList {
+    first: NodeId,
+    last: NodeId,
+}
+
+impl ChildList {
+    fn map(self, f: impl Fn(NodeId) -> NodeId) -> ChildList {
+        ChildList {
+            first: f(self.first),
+            last: f(self.last),
+        }
+    }
+}
 
-pub struct XmlNodeIntoIterator {
-    nodes: Vec<Weak<RefCell<XmlNode>>>,
-    index: usize,
+/// Iterates over the children of an element, or over its child elements with one name
+#[derive(Clone)]
+pub struct Children<'a> {
+    tree: &'a XmlTree,
+    next: Option<NodeId>,
+    same_name: bool,
 }
 
-impl Iterator for XmlNodeIntoIterator {
-    type Item = Rc<RefCell<XmlNode>>;
-    fn next(&mut self) -> Option<Rc<RefCell<XmlNode>>> {
-        let result = match self.nodes.get(self.index) {
-            None => None,
-            Some(node_ref) => match node_ref.upgrade() {
-                None => None,
-                Some(node_cell) => Some(node_cell.clone()),
-            },
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        let links = match self.same_name {
+            true => &self.tree[node].same_name,
+            false => &self.tree[node].siblings,
         };
-        self.index += 1;
-        result
+        self.next = links.next;
+        Some(node)
+    }
+}
+
+pub enum MoveCopyAction {
+    Move,
+    Copy,
+}
+
+impl XmlNode {
+    fn new(data: XmlNodeData) -> XmlNode {
+        XmlNode {
+            parent: None,
+            siblings: Links::default(),
+            same_name: Links::default(),
+            data,
+        }
+    }
+    pub fn name(&self) -> Option<&str> {
+        match &self.data {
+            XmlNodeData::Element(e) => Some(&e.name),
+            _ => None,
+        }
+    }
+    pub fn set_regex(&mut self, regex: Option<Regex>) {
+        if let XmlNodeData::Element(e) = &mut self.data {
+            e.applied_regexp = regex
+        }
+    }
+    pub fn get_regex(&self) -> Option<Regex> {
+        match &self.data {
+            XmlNodeData::Element(e) => e.applied_regexp.clone(),
+            _ => None,
+        }
+    }
+    pub fn set_attribute_match(&mut self, attribute_match: Option<(Regex, String)>) {
+        if let XmlNodeData::Element(e) = &mut self.data {
+            e.applied_attribute = attribute_match
+        }
+    }
+    pub fn get_attribute_match(&self) -> Option<(Regex, String)> {
+        match &self.data {
+            XmlNodeData::Element(e) => e.applied_attribute.clone(),
+            _ => None,
+        }
+    }
+    pub fn bind_variables(&mut self, bindings: Vec<(String, String)>) {
+        if let XmlNodeData::Element(e) = &mut self.data {
+            e.variables.extend(bindings)
+        }
+    }
+    pub fn unbind_variables(&mut self, count: usize) {
+        if let XmlNodeData::Element(e) = &mut self.data {
+            e.variables
+                .truncate(e.variables.len().saturating_sub(count))
+        }
+    }
+    pub fn bind_tables(&mut self, tables: Vec<(String, Arc<IndexMap<String, String>>)>) {
+        if let XmlNodeData::Element(e) = &mut self.data {
+            e.tables.extend(tables)
+        }
+    }
+    pub fn unbind_tables(&mut self, count: usize) {
+        if let XmlNodeData::Element(e) = &mut self.data {
+            e.tables.truncate(e.tables.len().saturating_sub(count))
+        }
+    }
+    pub fn attribute_names(&self) -> Vec<String> {
+        match &self.data {
+            XmlNodeData::Element(e) => e.attributes.iter().map(|(k, _)| k.clone()).collect(),
+            _ => vec![],
+        }
+    }
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        match &self.data {
+            XmlNodeData::Element(e) => e
+                .attributes
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone()),
+            _ => None,
+        }
+    }
+    pub fn set_attribute(&mut self, name: &str, value: String) -> bool {
+        match &mut self.data {
+            XmlNodeData::Element(e) => {
+                match e.attributes.iter_mut().find(|(k, _)| k == name) {
+                    Some((_, v)) => *v = value,
+                    None => e.attributes.push((name.to_string(), value)),
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        match &mut self.data {
+            XmlNodeData::Element(e) => {
+                let position = e.attributes.iter().position(|(k, _)| k == name)?;
+                Some(e.attributes.remove(position).1)
+            }
+            _ => None,
+        }
+    }
+    // Renames an attribute in place. An existing attribute with the new name is replaced.
+    pub fn rename_attribute(&mut self, name: &str, new_name: &str) -> bool {
+        if name == new_name {
+            return true;
+        }
+        match &mut self.data {
+            XmlNodeData::Element(e) => {
+                if e.attributes.iter().all(|(k, _)| k != name) {
+                    return false;
+                }
+                e.attributes.retain(|(k, _)| k != new_name);
+                for (k, _) in e.attributes.iter_mut() {
+                    if k == name {
+                        *k = new_name.to_string();
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
     }
 }
 
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum XmlNodeData {
     Element(Element),
     Comment(String),
@@ -374,6 +1226,26 @@ pub enum XmlNodeData {
     ProcessingInstruction(String, Option<String>),
 }
 
+impl XmlNodeData {
+    /// Returns the content of comments, CDATA sections and texts or the target of processing instructions
+    pub fn content(&self) -> Option<&str> {
+        match self {
+            XmlNodeData::Element(_) => None,
+            XmlNodeData::Comment(s) | XmlNodeData::CData(s) | XmlNodeData::Text(s) => Some(s),
+            XmlNodeData::ProcessingInstruction(target, _) => Some(target),
+        }
+    }
+    /// Replaces the content of comments, CDATA sections and texts or the data of processing instructions
+    pub fn set_content(&mut self, content: String) -> bool {
+        match self {
+            XmlNodeData::Element(_) => return false,
+            XmlNodeData::Comment(s) | XmlNodeData::CData(s) | XmlNodeData::Text(s) => *s = content,
+            XmlNodeData::ProcessingInstruction(_, data) => *data = Some(content),
+        }
+        true
+    }
+}
+
 impl PartialEq for XmlNodeData {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -402,38 +1274,54 @@ pub struct Element {
     //This regular expression is set while traversing down the XML-tree. When going back, it is resetted again.
     pub applied_regexp: Option<Regex>,
 
+    //The regular expression and the name of the attribute that is currently modified. Used to resolve `@` references.
+    pub applied_attribute: Option<(Regex, String)>,
+
+    //Loop variables that are bound while a `$foreach` is evaluated on this element.
+    pub variables: Vec<(String, String)>,
+
+    //Lookup tables that are bound while a query with `$tables` is evaluated on this element.
+    pub tables: Vec<(String, Arc<IndexMap<String, String>>)>,
+
     pub attributes: Vec<(String, String)>,
 
-    pub children: Vec<Rc<RefCell<XmlNode>>>,
+    //The ends of the list of children. Kept up to date by the `XmlTree` methods.
+    children: Option<ChildList>,
+
+    //The ends of the lists of child elements by name, in document order. Kept up to date by the `XmlTree` methods.
+    children_by_name: HashMap<String, ChildList>,
 }
 
 impl Element {
+    pub fn new(name: String) -> Element {
+        Element {
+            prefix: None,
+            name,
+            attributes: Vec::new(),
+            applied_regexp: None,
+            applied_attribute: None,
+            variables: vec![],
+            tables: vec![],
+            children: None,
+            children_by_name: HashMap::new(),
+        }
+    }
+    // Copies the element without its children
     pub fn deep_clone(&self) -> Element {
         Element {
             prefix: self.prefix.clone(),
             name: self.name.clone(),
             attributes: self.attributes.clone(),
             applied_regexp: self.applied_regexp.clone(),
-            children: vec![],
+            applied_attribute: self.applied_attribute.clone(),
+            variables: vec![],
+            tables: vec![],
+            children: None,
+            children_by_name: HashMap::new(),
         }
     }
 }
 
-impl PartialEq for Element {
-    fn eq(&self, other: &Self) -> bool {
-        let children_equal = self.children.len() == other.children.len()
-            && self
-                .children
-                .iter()
-                .zip(&other.children)
-                .map(|(c1, c2)| c1.deref().borrow().deref() == c2.deref().borrow().deref())
-                .all(|b| b);
-        self.prefix == other.prefix && self.name == other.name && children_equal
-    }
-}
-
-impl Eq for Element {}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -443,33 +1331,43 @@ mod tests {
         let xml_tree = xmltree::Element::parse(xml_str.as_bytes()).unwrap();
         XmlTree::new(&xml_tree)
     }
+    fn to_xml(xml_tree: &XmlTree) -> String {
+        let mut result = Vec::new();
+        xml_tree.write(&mut result).unwrap();
+        String::from_utf8(result).unwrap()
+    }
 
     mod single_query_tests {
         use super::*;
 
         fn get_test_xml_tree() -> XmlTree {
-            XmlTree {
-                root: Rc::new(RefCell::new(XmlNode {
-                    parent: None,
-                    data: XmlNodeData::Element(Element {
-                        prefix: None,
-                        name: "element".to_string(),
-                        attributes: Vec::new(),
-                        applied_regexp: None,
-                        children: vec![],
-                    }),
-                })),
-            }
+            let mut xml_tree = XmlTree {
+                nodes: vec![],
+                root: NodeId(0),
+                compacted_len: 1,
+            };
+            xml_tree.root = xml_tree.create(XmlNodeData::Element(Element {
+                prefix: None,
+                name: "element".to_string(),
+                attributes: Vec::new(),
+                applied_regexp: None,
+                applied_attribute: None,
+                variables: vec![],
+                tables: vec![],
+                children: None,
+                children_by_name: HashMap::new(),
+            }));
+            xml_tree
         }
 
         #[test]
         fn successful_append() {
             let foo_element = XmlNodeData::Text(String::from("Foo"));
             let mut xmltree = get_test_xml_tree();
-            XmlTree::append(&mut xmltree.root, foo_element);
-            let root = xmltree.root.deref().borrow();
-            assert!(root.parent.is_none());
-            let root_element = match &root.data {
+            let root = xmltree.root;
+            let foo_child = xmltree.append(root, foo_element);
+            assert!(xmltree[root].parent.is_none());
+            let root_element = match &xmltree[root].data {
                 XmlNodeData::Element(e) => Some(e),
                 XmlNodeData::Comment(_) => None,
                 XmlNodeData::CData(_) => None,
@@ -480,31 +1378,9 @@ mod tests {
             let root_element = root_element.unwrap();
             assert_eq!(root_element.name, String::from("element"));
             assert_eq!(root_element.prefix, None);
-            assert_eq!(root_element.children.len(), 1);
-            let foo_child = root_element.children.first();
-            assert!(foo_child.is_some());
-            let foo_child = foo_child.unwrap().deref().borrow();
-            assert!(foo_child.parent.is_some());
-            let foo_child_parent = match &foo_child
-                .parent
-                .as_ref()
-                .unwrap()
-                .upgrade()
-                .unwrap()
-                .deref()
-                .borrow()
-                .data
-            {
-                XmlNodeData::Element(e) => Some(e.clone()),
-                XmlNodeData::Comment(_) => None,
-                XmlNodeData::CData(_) => None,
-                XmlNodeData::Text(_) => None,
-                XmlNodeData::ProcessingInstruction(_, _) => None,
-            };
-            assert!(foo_child_parent.is_some());
-            let foo_child_parent = foo_child_parent.unwrap();
-            assert_eq!(&foo_child_parent, root_element);
-            let foo_child = match &foo_child.data {
+            assert_eq!(xmltree.children(root).collect::<Vec<_>>(), [foo_child]);
+            assert_eq!(xmltree.parent(foo_child), Some(root));
+            let foo_child = match &xmltree[foo_child].data {
                 XmlNodeData::Element(_) => None,
                 XmlNodeData::Comment(_) => None,
                 XmlNodeData::CData(_) => None,
@@ -520,10 +1396,132 @@ mod tests {
         fn simple_element() {
             let foo_element = XmlNodeData::Text(String::from("Foo"));
             let mut xmltree = get_test_xml_tree();
-            XmlTree::append(&mut xmltree.root, foo_element);
+            let root = xmltree.root;
+            xmltree.append(root, foo_element);
+            assert_eq!(
+                to_xml(&read_xml_tree(indoc!(r#"<element>Foo</element>"#))),
+                to_xml(&xmltree)
+            );
+        }
+
+        #[test]
+        fn detach_and_reattach() {
+            let mut xmltree = read_xml_tree(r#"<root><a><item>1</item></a><b /></root>"#);
+            let root = xmltree.root;
+            let (a, b) = (
+                xmltree.child_by_name(root, "a").unwrap(),
+                xmltree.child_by_name(root, "b").unwrap(),
+            );
+            let item = xmltree.child_by_name(a, "item").unwrap();
+            assert!(xmltree.remove(item));
+            assert!(!xmltree.remove(item));
+            assert_eq!(xmltree.parent(item), None);
+            xmltree.append_node(b, item);
+            assert_eq!(xmltree.parent(item), Some(b));
+            assert_eq!(xmltree.index_in_parent(item), Some(0));
+            assert_eq!(xmltree.text(item), "1");
+            assert_eq!(
+                to_xml(&xmltree),
+                r#"<?xml version="1.0" encoding="UTF-8"?><root><a /><b><item>1</item></b></root>"#
+            );
+        }
+
+        #[test]
+        fn compact() {
+            let mut xmltree = read_xml_tree(r#"<root><a>1</a><b /><a>2</a></root>"#);
+            let expected = to_xml(&xmltree);
+            // Not compacted before the arena has doubled
+            xmltree.compact();
+            assert_eq!(xmltree.nodes.len(), 6);
+            for _ in 0..6 {
+                let root = xmltree.root;
+                let c = xmltree.append(root, XmlNodeData::Element(Element::new("c".to_string())));
+                xmltree.remove(c);
+            }
+            xmltree.compact();
+            assert_eq!(xmltree.nodes.len(), 6);
+            assert_eq!(xmltree.root, NodeId(0));
+            assert_eq!(to_xml(&xmltree), expected);
+            let root = xmltree.root;
+            let a: Vec<NodeId> = xmltree.children_by_name(root, "a").collect();
+            assert_eq!(a.len(), 2);
+            assert_eq!(xmltree.parent(a[1]), Some(root));
+            assert_eq!(xmltree.text(a[1]), "2");
+        }
+
+        #[test]
+        fn name_index() {
+            fn by_name(xmltree: &XmlTree, node: NodeId, name: &str) -> Vec<NodeId> {
+                xmltree.children_by_name(node, name).collect()
+            }
+            let mut xmltree = read_xml_tree(r#"<root><a>1</a><b /><a>2</a></root>"#);
+            let root = xmltree.root;
+            let children: Vec<NodeId> = xmltree.children(root).collect();
+            assert_eq!(by_name(&xmltree, root, "a"), [children[0], children[2]]);
+            // Insert in the middle
+            let a = xmltree.insert_before(
+                root,
+                Some(children[1]),
+                XmlNodeData::Element(Element::new("a".to_string())),
+            );
+            assert_eq!(by_name(&xmltree, root, "a"), [children[0], a, children[2]]);
+            assert_eq!(xmltree.index_in_parent(a), Some(1));
+            // Remove
+            xmltree.remove(children[0]);
+            assert_eq!(by_name(&xmltree, root, "a"), [a, children[2]]);
+            // Rename
+            assert!(xmltree.set_name(children[1], "a"));
+            assert_eq!(by_name(&xmltree, root, "a"), [a, children[1], children[2]]);
+            assert!(by_name(&xmltree, root, "b").is_empty());
+            // Move
+            xmltree
+                .move_copy_node(a, &ReferenceExpression::from("b/c"), MoveCopyAction::Move)
+                .unwrap();
+            let b = xmltree.child_by_name(root, "b").unwrap();
+            assert_eq!(by_name(&xmltree, root, "a"), [children[1], children[2]]);
+            assert_eq!(by_name(&xmltree, b, "c"), [a]);
+            assert_eq!(
+                to_xml(&xmltree),
+                r#"<?xml version="1.0" encoding="UTF-8"?><root><a /><a>2</a><b><c /></b></root>"#
+            );
+            // Clear
+            xmltree.clear_children(root);
+            assert!(by_name(&xmltree, root, "a").is_empty());
+            assert_eq!(xmltree.first_child(root), None);
+        }
+
+        #[test]
+        fn sibling_links() {
+            let mut xmltree =
+                read_xml_tree(r#"<root><a>1</a><b>2</b><a>3</a><c>4</c><a>5</a></root>"#);
+            let root = xmltree.root;
+            let children: Vec<NodeId> = xmltree.children(root).collect();
+            // Renaming and wrapping in the middle keep the document order of the name index
+            assert!(xmltree.set_name(children[3], "a"));
+            assert!(xmltree.set_name(children[1], "c"));
+            assert_eq!(
+                xmltree.children_by_name(root, "a").collect::<Vec<_>>(),
+                [children[0], children[2], children[3], children[4]]
+            );
+            xmltree.wrap(children[2], "w".to_string()).unwrap();
+            assert_eq!(
+                xmltree.children_by_name(root, "a").collect::<Vec<_>>(),
+                [children[0], children[3], children[4]]
+            );
+            let w = xmltree.child_by_name(root, "w").unwrap();
+            xmltree.unwrap(w).unwrap();
+            assert_eq!(
+                xmltree.children_by_name(root, "a").collect::<Vec<_>>(),
+                [children[0], children[2], children[3], children[4]]
+            );
+            // Removing the first and the last child updates the ends of the lists
+            xmltree.remove(children[0]);
+            xmltree.remove(children[4]);
+            assert_eq!(xmltree.first_child(root), Some(children[1]));
+            assert_eq!(xmltree.last_child(root), Some(children[3]));
             assert_eq!(
-                read_xml_tree(indoc!(r#"<element>Foo</element>"#)).root,
-                xmltree.root
+                to_xml(&xmltree),
+                r#"<?xml version="1.0" encoding="UTF-8"?><root><c>2</c><a>3</a><a>4</a></root>"#
             );
         }
     }
@@ -0,0 +1,111 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn expand_array() {
+    test_patch(
+        indoc!(
+            r#"<registers><register><dim>3</dim><dimIncrement>0x4</dimIncrement><name>CH%s</name><addressOffset>0x10</addressOffset></register></registers>"#
+        ),
+        indoc!(
+            r#"
+                    registers:
+                      register:
+                        $expand_array: {}
+                    "#
+        ),
+        indoc!(
+            r#"<registers><register><name>CH0</name><addressOffset>0x10</addressOffset></register><register><name>CH1</name><addressOffset>0x14</addressOffset></register><register><name>CH2</name><addressOffset>0x18</addressOffset></register></registers>"#
+        ),
+    );
+}
+#[test]
+fn expand_array_with_dim_index() {
+    test_patch(
+        indoc!(
+            r#"<fields><field><dim>2</dim><dimIndex>A-B</dimIndex><name>EN_%s</name><description>Enable %s</description></field></fields>"#
+        ),
+        indoc!(
+            r#"
+                    fields:
+                      field:
+                        $expand_array: {}
+                    "#
+        ),
+        indoc!(
+            r#"<fields><field><name>EN_A</name><description>Enable A</description></field><field><name>EN_B</name><description>Enable B</description></field></fields>"#
+        ),
+    );
+}
+#[test]
+fn expand_array_with_wrong_index_count() {
+    let result = patch_xml::patch_xml(
+        r#"<fields><field><dim>3</dim><dimIndex>a,b</dimIndex><name>F%s</name></field></fields>"#
+            .to_string(),
+        indoc!(
+            r#"
+                    fields:
+                      field:
+                        $expand_array: {}
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
+#[test]
+fn collapse_array() {
+    test_patch(
+        indoc!(
+            r#"<registers><register><name>CH1</name><addressOffset>0x14</addressOffset></register><register><name>CH0</name><addressOffset>0x10</addressOffset></register><register><name>CTRL</name><addressOffset>0x0</addressOffset></register></registers>"#
+        ),
+        indoc!(
+            r#"
+                    registers:
+                      $collapse_array:
+                        element: register
+                        name: CH(\d+)
+                    "#
+        ),
+        indoc!(
+            r#"<registers><register><dim>2</dim><dimIncrement>0x4</dimIncrement><dimIndex>0-1</dimIndex><name>CH%s</name><addressOffset>0x10</addressOffset></register><register><name>CTRL</name><addressOffset>0x0</addressOffset></register></registers>"#
+        ),
+    );
+}
+#[test]
+fn collapse_array_with_custom_placeholder() {
+    test_patch(
+        indoc!(
+            r#"<fields><field><name>EN_A</name></field><field><name>EN_B</name></field></fields>"#
+        ),
+        indoc!(
+            r#"
+                    fields:
+                      $collapse_array:
+                        name: EN_(?P<index>[A-Z])
+                        placeholder: "[%s]"
+                    "#
+        ),
+        indoc!(
+            r#"<fields><field><dim>2</dim><dimIndex>A,B</dimIndex><name>EN_[%s]</name></field></fields>"#
+        ),
+    );
+}
+#[test]
+fn collapse_array_not_equally_spaced() {
+    let result = patch_xml::patch_xml(
+        r#"<registers><register><name>CH0</name><addressOffset>0</addressOffset></register><register><name>CH1</name><addressOffset>4</addressOffset></register><register><name>CH2</name><addressOffset>12</addressOffset></register></registers>"#
+            .to_string(),
+        indoc!(
+            r#"
+                    registers:
+                      $collapse_array:
+                        name: CH(\d+)
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
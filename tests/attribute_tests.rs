@@ -32,3 +32,115 @@ fn simple_patched_attribute() {
         indoc!(r#"<element attr1="new value1" attr3="new value3">Foo</element>"#),
     );
 }
+#[test]
+fn remove_attributes_by_regex() {
+    test_patch(
+        indoc!(r#"<element id="1" data-foo="a" data-bar="b">Foo</element>"#),
+        indoc!(
+            r#"
+                element:
+                    $modify:
+                        $attributes:
+                            data-.*: ~
+                "#
+        ),
+        indoc!(r#"<element id="1">Foo</element>"#),
+    );
+}
+#[test]
+fn rewrite_attribute_with_capture() {
+    test_patch(
+        indoc!(r#"<element data-foo="a" data-bar="b" />"#),
+        indoc!(
+            r#"
+                element:
+                    $modify:
+                        $attributes:
+                            data-(.+): "[@:1]=[@]"
+                "#
+        ),
+        indoc!(r#"<element data-foo="foo=data-foo" data-bar="bar=data-bar" />"#),
+    );
+}
+#[test]
+fn rename_attribute() {
+    test_patch(
+        indoc!(r#"<element id="1" name="foo" />"#),
+        indoc!(
+            r#"
+                element:
+                    $modify:
+                        $attributes:
+                            id:
+                                $rename: xml:id
+                "#
+        ),
+        indoc!(r#"<element xml:id="1" name="foo" />"#),
+    );
+}
+#[test]
+fn rename_attributes_by_capture() {
+    test_patch(
+        indoc!(r#"<element old-a="1" old-b="2" />"#),
+        indoc!(
+            r#"
+                element:
+                    $modify:
+                        $attributes:
+                            old-(.+):
+                                $rename: new-[@:1]
+                "#
+        ),
+        indoc!(r#"<element new-a="1" new-b="2" />"#),
+    );
+}
+#[test]
+fn move_attribute_to_other_element() {
+    test_patch(
+        indoc!(r#"<element><sub1 id="1" /><sub2 /></element>"#),
+        indoc!(
+            r#"
+                element:
+                    sub1:
+                        $modify:
+                            $attributes:
+                                id:
+                                    $move: ../sub2/ref
+                "#
+        ),
+        indoc!(r#"<element><sub1 /><sub2 ref="1" /></element>"#),
+    );
+}
+#[test]
+fn optional_removal_of_missing_attribute() {
+    test_patch(
+        indoc!(r#"<element attr1="value1" />"#),
+        indoc!(
+            r#"
+                element:
+                    $modify:
+                        $attributes:
+                            attr2: ~
+                "#
+        ),
+        indoc!(r#"<element attr1="value1" />"#),
+    );
+}
+#[test]
+fn required_removal_of_missing_attribute() {
+    let result = patch_xml::patch_xml(
+        r#"<element attr1="value1" />"#.to_string(),
+        indoc!(
+            r#"
+                element:
+                    $modify:
+                        $attributes:
+                            attr2:
+                                $value: ~
+                                $required: true
+                "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
@@ -0,0 +1,81 @@
+use patch_xml::{Condition, Modification, Patch};
+
+fn test_builder(xml_str: &str, patch: Patch, expected_result: &str) {
+    let result_str = patch_xml::apply_patch(xml_str.to_string(), &patch);
+    assert_eq!(
+        result_str.unwrap(),
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+            expected_result
+        )
+    );
+}
+
+#[test]
+fn builder_filter_and_modify() {
+    test_builder(
+        r#"<device><peripheral><name>UART</name><size>8</size></peripheral><peripheral><name>SPI</name><size>16</size></peripheral></device>"#,
+        Patch::query("device")
+            .child("peripheral")
+            .filter(Condition::child("size", Condition::greater_equal(16u64)))
+            .modify(Modification::new().set("size", 32u64).add("enabled", true))
+            .build(),
+        r#"<device><peripheral><name>UART</name><size>8</size></peripheral><peripheral><name>SPI</name><size>32</size><enabled>true</enabled></peripheral></device>"#,
+    );
+}
+#[test]
+fn builder_set_and_remove() {
+    test_builder(
+        r#"<device><name>Old</name><reserved /><version>1</version></device>"#,
+        Patch::query("device")
+            .child("name")
+            .set("New")
+            .child("reserved")
+            .remove()
+            .child("version")
+            .set(2u64)
+            .build(),
+        r#"<device><name>New</name><version>2</version></device>"#,
+    );
+}
+#[test]
+fn builder_move_and_copy() {
+    test_builder(
+        r#"<device><a><item>1</item></a><b /></device>"#,
+        Patch::query("device")
+            .child("a")
+            .child("item")
+            .copy_to("../b/")
+            .parent()
+            .move_to("b/")
+            .build(),
+        r#"<device><b><item>1</item><a><item>1</item></a></b></device>"#,
+    );
+}
+#[test]
+fn builder_attributes() {
+    test_builder(
+        r#"<device><field id="1" access="RW" old="x" /></device>"#,
+        Patch::query("device")
+            .child("field")
+            .modify(
+                Modification::new()
+                    .attribute("access", "read-write")
+                    .rename_attribute("id", "uid")
+                    .remove_attribute("old"),
+            )
+            .build(),
+        r#"<device><field uid="1" access="read-write" /></device>"#,
+    );
+}
+#[test]
+fn builder_to_yaml() {
+    let patch = Patch::query("device")
+        .child("field")
+        .filter(Condition::parse(">4").or(Condition::empty()))
+        .build();
+    assert_eq!(
+        patch.to_yaml().unwrap(),
+        "device:\n  field:\n    $if:\n      $or:\n        - \">4\"\n        - ~\n"
+    );
+}
@@ -0,0 +1,97 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn conditional_query() {
+    test_patch(
+        indoc!(
+            r#"<register><field><name>EN</name></field><field><name>RST</name></field></register>"#
+        ),
+        indoc!(
+            r#"
+                    register:
+                      field:
+                        $if:
+                          name: ^R.*
+                        $modify:
+                          access: read-only
+                    "#
+        ),
+        indoc!(
+            r#"<register><field><name>EN</name></field><field><name>RST</name><access>read-only</access></field></register>"#
+        ),
+    );
+}
+#[test]
+fn query_with_else() {
+    test_patch(
+        indoc!(
+            r#"<registers><register><name>A</name><resetValue>0x1</resetValue></register><register><name>B</name></register></registers>"#
+        ),
+        indoc!(
+            r#"
+                    registers:
+                      register:
+                        $if:
+                          resetValue: ~
+                        $modify:
+                          resetValue: "0x0"
+                        $else:
+                          resetValue:
+                            $modify:
+                              $append_text: "0"
+                    "#
+        ),
+        indoc!(
+            r#"<registers><register><name>A</name><resetValue>0x10</resetValue></register><register><name>B</name><resetValue>0x0</resetValue></register></registers>"#
+        ),
+    );
+}
+#[test]
+fn value_with_else() {
+    test_patch(
+        indoc!(r#"<element><field>1</field><field>7</field></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        field:
+                          $if: "<5"
+                          $attributes:
+                            size: small
+                          $else:
+                            $attributes:
+                              size: large
+                    "#
+        ),
+        indoc!(r#"<element><field size="small">1</field><field size="large">7</field></element>"#),
+    );
+}
+#[test]
+fn else_on_created_element() {
+    test_patch(
+        indoc!(r#"<element />"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        field:
+                          $if: "^x"
+                          $text: matched
+                          $else:
+                            $text: created
+                    "#
+        ),
+        indoc!(r#"<element><field>created</field></element>"#),
+    );
+}
+#[test]
+fn invalid_regex() {
+    let xml = "<element><a>1</a></element>";
+    for patch in ["element: { \"(\": x }", "element: { $if: \"^(\", a: 2 }"] {
+        let result = patch_xml::patch_xml(xml.to_string(), patch.to_string());
+        assert!(result.is_err());
+    }
+}
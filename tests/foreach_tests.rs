@@ -0,0 +1,119 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn foreach_range_in_modification() {
+    test_patch(
+        indoc!(r#"<registers />"#),
+        indoc!(
+            r#"
+                    registers:
+                      $modify:
+                        $foreach: { var: i, in: "0..3" }
+                        +register:
+                          name: CH[$i]
+                    "#
+        ),
+        indoc!(
+            r#"<registers><register><name>CH0</name></register><register><name>CH1</name></register><register><name>CH2</name></register></registers>"#
+        ),
+    );
+}
+#[test]
+fn foreach_inclusive_range_in_element_name() {
+    test_patch(
+        indoc!(r#"<element />"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        $foreach: { var: i, in: "1..=2" }
+                        +child_[$i]: ~
+                    "#
+        ),
+        indoc!(r#"<element><child_1 /><child_2 /></element>"#),
+    );
+}
+#[test]
+fn foreach_list_on_query() {
+    test_patch(
+        indoc!(r#"<element><a>x</a></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $foreach: { var: suffix, in: [b, 1] }
+                      a:
+                        $modify:
+                          $append_text: "[$suffix]"
+                    "#
+        ),
+        indoc!(r#"<element><a>xb1</a></element>"#),
+    );
+}
+#[test]
+fn foreach_map_with_key_and_value() {
+    test_patch(
+        indoc!(r#"<registers />"#),
+        indoc!(
+            r#"
+                    registers:
+                      $modify:
+                        $foreach:
+                          var: [name, offset]
+                          in:
+                            CTRL: "0x00"
+                            STATUS: "0x04"
+                        +register:
+                          name: "[$name]"
+                          addressOffset: "[$offset]"
+                    "#
+        ),
+        indoc!(
+            r#"<registers><register><name>CTRL</name><addressOffset>0x00</addressOffset></register><register><name>STATUS</name><addressOffset>0x04</addressOffset></register></registers>"#
+        ),
+    );
+}
+#[test]
+fn nested_foreach() {
+    test_patch(
+        indoc!(r#"<element />"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        $foreach: { var: i, in: "0..2" }
+                        +row:
+                          $foreach: { var: j, in: [a, b] }
+                          +cell: "[$i][$j]"
+                    "#
+        ),
+        indoc!(
+            r#"<element><row><cell>0a</cell><cell>0b</cell></row><row><cell>1a</cell><cell>1b</cell></row></element>"#
+        ),
+    );
+}
+#[test]
+fn unknown_variable() {
+    let result = patch_xml::patch_xml(
+        r#"<element>Foo</element>"#.to_string(),
+        r#"element: "[$nope]""#.to_string(),
+    );
+    assert_eq!(
+        result,
+        Err("Error while applying patch: Unknown variable \"nope\"".to_string())
+    );
+}
+#[test]
+fn inclusive_range_overflow() {
+    let result = patch_xml::Patch::from_yaml(indoc!(
+        r#"
+                registers:
+                  $modify:
+                    $foreach: { var: i, in: "0..=9223372036854775807" }
+                    +register: CH[$i]
+                "#
+    ));
+    assert!(result.is_err());
+}
@@ -0,0 +1,101 @@
+mod utils;
+
+use indoc::indoc;
+use patch_xml::PatchFormat;
+use utils::test_patch;
+
+const XML: &str = r#"<device><peripheral><name>UART</name><size>8</size></peripheral><peripheral><name>SPI</name><size>16</size></peripheral></device>"#;
+const EXPECTED: &str = r#"<device><peripheral><name>UART</name><size>8</size><enabled>true</enabled></peripheral><peripheral><name>SPI</name><size>32</size></peripheral></device>"#;
+
+fn test_patch_with_format(patch_str: &str, format: PatchFormat) {
+    let result_str =
+        patch_xml::patch_xml_with_format(XML.to_string(), patch_str.to_string(), format);
+    assert_eq!(
+        result_str.unwrap(),
+        format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}"#, EXPECTED)
+    );
+}
+
+#[test]
+fn format_yaml() {
+    test_patch(
+        XML,
+        indoc!(
+            r#"
+                    device:
+                      peripheral:
+                        - $if:
+                            size: 8
+                          $modify:
+                            +enabled: true
+                        - $if:
+                            name: SPI
+                          size: 32
+                    "#
+        ),
+        EXPECTED,
+    );
+}
+#[test]
+fn format_json() {
+    test_patch_with_format(
+        indoc!(
+            r#"
+                    {
+                      "device": {
+                        "peripheral": [
+                          { "$if": { "size": 8 }, "$modify": { "+enabled": true } },
+                          { "$if": { "name": "SPI" }, "size": 32 }
+                        ]
+                      }
+                    }
+                    "#
+        ),
+        PatchFormat::Json,
+    );
+}
+#[test]
+fn format_toml() {
+    test_patch_with_format(
+        indoc!(
+            r#"
+                    [[device.peripheral]]
+                    "$if" = { size = 8 }
+                    "$modify" = { "+enabled" = true }
+
+                    [[device.peripheral]]
+                    "$if" = { name = "SPI" }
+                    size = 32
+                    "#
+        ),
+        PatchFormat::Toml,
+    );
+}
+#[test]
+fn format_json_filter_strings() {
+    let result = patch_xml::patch_xml_with_format(
+        r#"<list><item>3</item><item>5</item><item>1.5</item></list>"#.to_string(),
+        r#"{ "list": { "item": { "$if": ">4", "$modify": "big" } } }"#.to_string(),
+        PatchFormat::Json,
+    );
+    assert_eq!(
+        result.unwrap(),
+        r#"<?xml version="1.0" encoding="UTF-8"?><list><item>3</item><item>big</item><item>1.5</item></list>"#
+    );
+}
+#[test]
+fn format_from_path() {
+    assert_eq!(PatchFormat::from_path("patch.json"), PatchFormat::Json);
+    assert_eq!(PatchFormat::from_path("dir/patch.toml"), PatchFormat::Toml);
+    assert_eq!(PatchFormat::from_path("patch.yaml"), PatchFormat::Yaml);
+    assert_eq!(PatchFormat::from_path("patch"), PatchFormat::Yaml);
+}
+#[test]
+fn format_invalid_json() {
+    let result = patch_xml::patch_xml_with_format(
+        XML.to_string(),
+        "device:\n  peripheral: ~".to_string(),
+        PatchFormat::Json,
+    );
+    assert!(result.is_err());
+}
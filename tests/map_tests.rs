@@ -0,0 +1,194 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn map_inline_table() {
+    test_patch(
+        indoc!(
+            r#"<device><field><access>RW</access></field><field><access>R</access></field></device>"#
+        ),
+        indoc!(
+            r#"
+                    device:
+                      field:
+                        access:
+                          $map:
+                            table:
+                              RW: read-write
+                              R: read-only
+                    "#
+        ),
+        indoc!(
+            r#"<device><field><access>read-write</access></field><field><access>read-only</access></field></device>"#
+        ),
+    );
+}
+#[test]
+fn map_named_table_with_default() {
+    test_patch(
+        indoc!(
+            r#"<device><field><access>rw</access></field><field><access>x</access></field></device>"#
+        ),
+        indoc!(
+            r#"
+                    device:
+                      $tables:
+                        access:
+                          rw: read-write
+                      field:
+                        access:
+                          $map:
+                            table: access
+                            default: read-only
+                    "#
+        ),
+        indoc!(
+            r#"<device><field><access>read-write</access></field><field><access>read-only</access></field></device>"#
+        ),
+    );
+}
+#[test]
+fn map_attribute_in_value() {
+    test_patch(
+        indoc!(r#"<device><field access="RW" /></device>"#),
+        indoc!(
+            r#"
+                    device:
+                      $tables:
+                        access:
+                          RW: read-write
+                      $modify:
+                        field:
+                          $map:
+                            table: access
+                            attribute: access
+                    "#
+        ),
+        indoc!(r#"<device><field access="read-write" /></device>"#),
+    );
+}
+#[test]
+fn map_table_from_csv_file() {
+    let dir = std::env::temp_dir().join("patch_xml_map_tests");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("access.csv"),
+        "# vendor,svd\nRW,read-write\nRO,\"read-only, always\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("patch.yaml"),
+        indoc!(
+            r#"
+                device:
+                  $tables:
+                    access:
+                      file: access.csv
+                  field:
+                    access:
+                      $map: access
+                "#
+        ),
+    )
+    .unwrap();
+    // The table file is resolved relative to the patch file
+    let patch = patch_xml::Patch::from_file(dir.join("patch.yaml")).unwrap();
+    assert_eq!(
+        patch_xml::apply_patch(
+            "<device><field><access>RO</access></field></device>".to_string(),
+            &patch
+        ),
+        Ok(indoc!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><device><field><access>read-only, always</access></field></device>"#
+        )
+        .to_string())
+    );
+}
+#[test]
+fn table_file_not_loaded() {
+    let xml = "<device><field><access>RO</access></field></device>";
+    let patch = indoc!(
+        r#"
+            device:
+              $tables:
+                access:
+                  file: access.csv
+              field:
+                access:
+                  $map: access
+            "#
+    );
+    let result = patch_xml::patch_xml(xml.to_string(), patch.to_string());
+    assert_eq!(
+        result,
+        Err("Error while applying patch: Table file \"access.csv\" is not loaded".to_string())
+    );
+}
+#[test]
+fn map_in_reference_expression() {
+    test_patch(
+        indoc!(r#"<device><field><access>RW</access></field></device>"#),
+        indoc!(
+            r#"
+                    device:
+                      $tables:
+                        access:
+                          RW: read-write
+                      field:
+                        $modify:
+                          description: Access is [%access(../access)]
+                    "#
+        ),
+        indoc!(
+            r#"<device><field><access>RW</access><description>Access is read-write</description></field></device>"#
+        ),
+    );
+}
+#[test]
+fn map_unmapped_error() {
+    let result = patch_xml::patch_xml(
+        r#"<device><access>WO</access></device>"#.to_string(),
+        indoc!(
+            r#"
+                    device:
+                      access:
+                        $map:
+                          table: { RW: read-write }
+                          unmapped: error
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
+#[test]
+fn unknown_table() {
+    let result = patch_xml::patch_xml(
+        r#"<element>Foo</element>"#.to_string(),
+        r#"element: "[%nope]""#.to_string(),
+    );
+    assert_eq!(
+        result,
+        Err("Error while applying patch: Unknown table \"nope\"".to_string())
+    );
+}
+#[test]
+fn lookup_without_element() {
+    let result = patch_xml::patch_xml(
+        r#"<device><access>RW</access></device>"#.to_string(),
+        indoc!(
+            r#"
+                    device:
+                      $tables: { access: { RW: read-write } }
+                      access: "[%access(missing)]"
+                    "#
+        )
+        .to_string(),
+    );
+    assert_eq!(
+        result,
+        Err("Error while applying patch: No element is matching \"missing\"".to_string())
+    );
+}
@@ -0,0 +1,101 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn merge_by_name() {
+    test_patch(
+        indoc!(
+            r#"<device><base a="1" b="2"><x>old</x><y>keep</y></base><board b="3"><x>new</x><z>added</z></board></device>"#
+        ),
+        indoc!(
+            r#"
+                    device:
+                      base:
+                        $merge_from: ../board
+                    "#
+        ),
+        indoc!(
+            r#"<device><base a="1" b="3"><x>new</x><y>keep</y><z>added</z></base><board b="3"><x>new</x><z>added</z></board></device>"#
+        ),
+    );
+}
+#[test]
+fn merge_by_key() {
+    test_patch(
+        indoc!(
+            r#"<device><base><field><name>EN</name><width>1</width></field><field><name>RST</name><width>1</width></field></base><board><field><name>RST</name><width>2</width></field><field><name>IRQ</name><width>1</width></field></board></device>"#
+        ),
+        indoc!(
+            r#"
+                    device:
+                      base:
+                        $merge:
+                          from: ../board
+                          key: name
+                    "#
+        ),
+        indoc!(
+            r#"<device><base><field><name>EN</name><width>1</width></field><field><name>RST</name><width>2</width></field><field><name>IRQ</name><width>1</width></field></base><board><field><name>RST</name><width>2</width></field><field><name>IRQ</name><width>1</width></field></board></device>"#
+        ),
+    );
+}
+#[test]
+fn merge_by_attribute_keeping_ours() {
+    test_patch(
+        indoc!(
+            r#"<device><base><irq id="1" prio="0" /><irq id="2" prio="0" /></base><board><irq id="2" prio="3" mask="1" /></board></device>"#
+        ),
+        indoc!(
+            r#"
+                    device:
+                      base:
+                        $merge:
+                          from: ../board
+                          key: "@id"
+                          policy: ours
+                    "#
+        ),
+        indoc!(
+            r#"<device><base><irq id="1" prio="0" /><irq id="2" prio="0" mask="1" /></base><board><irq id="2" prio="3" mask="1" /></board></device>"#
+        ),
+    );
+}
+#[test]
+fn merge_in_value() {
+    test_patch(
+        indoc!(
+            r#"<device><defaults><size>32</size><access>rw</access></defaults><reg /></device>"#
+        ),
+        indoc!(
+            r#"
+                    device:
+                      $modify:
+                        reg:
+                          $merge_from: ../defaults
+                          access: ro
+                    "#
+        ),
+        indoc!(
+            r#"<device><defaults><size>32</size><access>rw</access></defaults><reg><size>32</size><access>ro</access></reg></device>"#
+        ),
+    );
+}
+#[test]
+fn merge_conflict_error() {
+    let result = patch_xml::patch_xml(
+        r#"<device><base><x>old</x></base><board><x>new</x></board></device>"#.to_string(),
+        indoc!(
+            r#"
+                    device:
+                      base:
+                        $merge:
+                          from: ../board
+                          policy: error
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
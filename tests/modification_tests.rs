@@ -58,3 +58,173 @@ fn complex_add() {
         indoc!(r#"<element><subelement>Foo</subelement><subelement>Bar</subelement></element>"#),
     );
 }
+#[test]
+fn conditional_update() {
+    test_patch(
+        indoc!(
+            r#"<element><field><name>EN</name></field><field><name>RST</name></field></element>"#
+        ),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        field:
+                          $if:
+                            name: EN
+                          description: Enable
+                    "#
+        ),
+        indoc!(
+            r#"<element><field><name>EN</name><description>Enable</description></field><field><name>RST</name></field></element>"#
+        ),
+    );
+}
+#[test]
+fn conditional_update_with_comparison() {
+    test_patch(
+        indoc!(
+            r#"<element><field><offset>0x2</offset></field><field><offset>0x8</offset></field><field><offset>4</offset></field></element>"#
+        ),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        field:
+                          $if:
+                            offset: ">=4"
+                          high: true
+                    "#
+        ),
+        indoc!(
+            r#"<element><field><offset>0x2</offset></field><field><offset>0x8</offset><high>true</high></field><field><offset>4</offset><high>true</high></field></element>"#
+        ),
+    );
+}
+#[test]
+fn keyed_update() {
+    test_patch(
+        indoc!(
+            r#"<fields><field><name>EN</name><bitOffset>0</bitOffset></field><field><name>RST</name><bitOffset>1</bitOffset></field></fields>"#
+        ),
+        indoc!(
+            r#"
+                    fields:
+                      $modify:
+                        field[name=RST]:
+                          bitOffset: 4
+                    "#
+        ),
+        indoc!(
+            r#"<fields><field><name>EN</name><bitOffset>0</bitOffset></field><field><name>RST</name><bitOffset>4</bitOffset></field></fields>"#
+        ),
+    );
+}
+#[test]
+fn keyed_creation() {
+    test_patch(
+        indoc!(r#"<fields><field><name>EN</name></field></fields>"#),
+        indoc!(
+            r#"
+                    fields:
+                      $modify:
+                        field[name=RST]:
+                          bitOffset: 1
+                    "#
+        ),
+        indoc!(
+            r#"<fields><field><name>EN</name></field><field><name>RST</name><bitOffset>1</bitOffset></field></fields>"#
+        ),
+    );
+}
+#[test]
+fn keyed_by_attribute() {
+    test_patch(
+        indoc!(r#"<list><item id="1">a</item><item id="3">b</item></list>"#),
+        indoc!(
+            r#"
+                    list:
+                      $modify:
+                        item[@id=3]: c
+                        item[@id=4]: d
+                    "#
+        ),
+        indoc!(r#"<list><item id="1">a</item><item id="3">c</item><item id="4">d</item></list>"#),
+    );
+}
+#[test]
+fn ambiguous_key() {
+    let result = patch_xml::patch_xml(
+        r#"<list><item id="1">a</item><item id="1">b</item></list>"#.to_string(),
+        indoc!(
+            r#"
+                    list:
+                      $modify:
+                        item[@id=1]: c
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
+#[test]
+fn add_if_absent() {
+    let patch = indoc!(
+        r#"
+                    element:
+                      $modify:
+                        ?subelement: Bar
+                        ?other: Baz
+                    "#
+    );
+    test_patch(
+        indoc!(r#"<element><subelement>Foo</subelement></element>"#),
+        patch,
+        indoc!(r#"<element><subelement>Foo</subelement><other>Baz</other></element>"#),
+    );
+    // Re-running the patch must not add duplicates
+    let result = patch_xml::patch_xml(
+        r#"<element><subelement>Foo</subelement></element>"#.to_string(),
+        patch.to_string(),
+    )
+    .unwrap();
+    let result = result.trim_start_matches(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    test_patch(
+        result,
+        patch,
+        indoc!(r#"<element><subelement>Foo</subelement><other>Baz</other></element>"#),
+    );
+}
+#[test]
+fn keyed_add_if_absent() {
+    test_patch(
+        indoc!(r#"<fields><field><name>EN</name><bitOffset>0</bitOffset></field></fields>"#),
+        indoc!(
+            r#"
+                    fields:
+                      $modify:
+                        ?field[name=EN]:
+                          bitOffset: 5
+                        ?field[name=RST]:
+                          bitOffset: 1
+                    "#
+        ),
+        indoc!(
+            r#"<fields><field><name>EN</name><bitOffset>0</bitOffset></field><field><name>RST</name><bitOffset>1</bitOffset></field></fields>"#
+        ),
+    );
+}
+#[test]
+fn replace_only_updates() {
+    test_patch(
+        indoc!(r#"<element><subelement>Foo</subelement></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        ~subelement: Bar
+                        ~other: Baz
+                    "#
+        ),
+        indoc!(r#"<element><subelement>Bar</subelement></element>"#),
+    );
+}
@@ -86,3 +86,253 @@ fn simple_copy() {
         ),
     );
 }
+#[test]
+fn move_created_element() {
+    test_patch(
+        indoc!(r#"<root><a /><b /></root>"#),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $modify:
+                          +new:
+                            $text: Foo
+                            $move: ../b/
+                    "#
+        ),
+        indoc!(r#"<root><a /><b><new>Foo</new></b></root>"#),
+    );
+}
+#[test]
+fn copy_modified_element() {
+    test_patch(
+        indoc!(r#"<root><a><field>Foo</field></a><b /></root>"#),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $modify:
+                          field:
+                            $append_text: Bar
+                            $copy: ../b/
+                    "#
+        ),
+        indoc!(r#"<root><a><field>FooBar</field></a><b><field>FooBar</field></b></root>"#),
+    );
+}
+#[test]
+fn copy_to_all_matching_destinations() {
+    test_patch(
+        indoc!(
+            r#"<peripherals><template><interrupt>IRQ</interrupt></template><TIM1 /><TIM2 /><UART1 /></peripherals>"#
+        ),
+        indoc!(
+            r#"
+                    peripherals:
+                      template:
+                        interrupt:
+                          $copy: ../TIM\d+/
+                    "#
+        ),
+        indoc!(
+            r#"<peripherals><template><interrupt>IRQ</interrupt></template><TIM1><interrupt>IRQ</interrupt></TIM1><TIM2><interrupt>IRQ</interrupt></TIM2><UART1 /></peripherals>"#
+        ),
+    );
+}
+#[test]
+fn copy_with_destination_captures() {
+    test_patch(
+        indoc!(r#"<peripherals><template><interrupt /></template><TIM1 /><TIM2 /></peripherals>"#),
+        indoc!(
+            r#"
+                    peripherals:
+                      template:
+                        interrupt:
+                          $copy: ../TIM(?P<index>\d+)/interrupt_[$index]
+                    "#
+        ),
+        indoc!(
+            r#"<peripherals><template><interrupt /></template><TIM1><interrupt_1 /></TIM1><TIM2><interrupt_2 /></TIM2></peripherals>"#
+        ),
+    );
+}
+#[test]
+fn move_to_multiple_destinations() {
+    let result = patch_xml::patch_xml(
+        r#"<peripherals><template><interrupt /></template><TIM1 /><TIM2 /></peripherals>"#
+            .to_string(),
+        indoc!(
+            r#"
+                    peripherals:
+                      template:
+                        interrupt:
+                          $move: ../TIM\d+/
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
+#[test]
+fn copy_from_keyed_path() {
+    test_patch(
+        indoc!(
+            r#"<registers><register><name>CR1</name><fields><field>EN</field></fields></register><register><name>CR2</name></register></registers>"#
+        ),
+        indoc!(
+            r#"
+                    registers:
+                      register:
+                        $if:
+                          name: CR2
+                        $copy_from: ../register[name=CR1]/fields
+                    "#
+        ),
+        indoc!(
+            r#"<registers><register><name>CR1</name><fields><field>EN</field></fields></register><register><name>CR2</name><fields><field>EN</field></fields></register></registers>"#
+        ),
+    );
+}
+#[test]
+fn copy_from_merges_content() {
+    test_patch(
+        indoc!(
+            r#"<root><defaults size="32" access="rw"><reset>0</reset></defaults><register access="ro" /></root>"#
+        ),
+        indoc!(
+            r#"
+                    root:
+                      register:
+                        $copy_from: ../defaults/
+                    "#
+        ),
+        indoc!(
+            r#"<root><defaults size="32" access="rw"><reset>0</reset></defaults><register access="ro" size="32"><reset>0</reset></register></root>"#
+        ),
+    );
+}
+#[test]
+fn move_from_in_modification() {
+    test_patch(
+        indoc!(r#"<root><old><item>a</item><item>b</item></old></root>"#),
+        indoc!(
+            r#"
+                    root:
+                      $modify:
+                        new:
+                          $move_from: ../old/item
+                    "#
+        ),
+        indoc!(r#"<root><old /><new><item>a</item><item>b</item></new></root>"#),
+    );
+}
+#[test]
+fn copy_from_missing_path() {
+    let result = patch_xml::patch_xml(
+        r#"<root><a /></root>"#.to_string(),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $copy_from: ../b
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.is_err());
+}
+#[test]
+fn copy_creates_plain_names() {
+    test_patch(
+        indoc!(r#"<root><a>1</a></root>"#),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $copy: foo_bar/
+                    "#
+        ),
+        indoc!(r#"<root><a>1</a><foo_bar><a>1</a></foo_bar></root>"#),
+    );
+}
+#[test]
+fn copy_to_names_with_dot() {
+    // A `.` makes the name a regular expression, like everywhere else in a patch
+    test_patch(
+        indoc!(r#"<root><a>1</a><TIM1 /></root>"#),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $copy: TIM./
+                    "#
+        ),
+        indoc!(r#"<root><a>1</a><TIM1><a>1</a></TIM1></root>"#),
+    );
+}
+#[test]
+fn copy_without_destination() {
+    let result = patch_xml::patch_xml(
+        r#"<root><a /></root>"#.to_string(),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $copy: b\d+/
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.unwrap_err().contains("is matching no destination"));
+}
+#[test]
+fn move_into_itself() {
+    let result = patch_xml::patch_xml(
+        r#"<root><a><b /></a></root>"#.to_string(),
+        indoc!(
+            r#"
+                    root:
+                      a:
+                        $move: a/b/
+                    "#
+        )
+        .to_string(),
+    );
+    assert_eq!(
+        result,
+        Err("Error while applying patch: \"a/b/\" cannot move an element into itself or its descendants".to_string())
+    );
+}
+#[test]
+fn reference_to_missing_element() {
+    let result = patch_xml::patch_xml(
+        r#"<root><a /></root>"#.to_string(),
+        indoc!(
+            r#"
+                    root:
+                      $modify:
+                        +copy: "[name]"
+                    "#
+        )
+        .to_string(),
+    );
+    assert_eq!(
+        result,
+        Err("Error while applying patch: No element is matching \"name\"".to_string())
+    );
+}
+#[test]
+fn reference_to_several_elements() {
+    let result = patch_xml::patch_xml(
+        r#"<root><a /><a /></root>"#.to_string(),
+        indoc!(
+            r#"
+                    root:
+                      $modify:
+                        +copy: "[../a]"
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.unwrap_err().contains("is matching 2 elements"));
+}
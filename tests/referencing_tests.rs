@@ -84,3 +84,34 @@ fn referencing_multiple_parallel() {
         ),
     );
 }
+#[test]
+fn referencing_missing_capture_group() {
+    let result = patch_xml::patch_xml(
+        "<element>Foo</element>".to_string(),
+        indoc!(
+            r#"
+                    ele(.+):
+                      Referenced [.:2]"#
+        )
+        .to_string(),
+    );
+    assert_eq!(
+        result,
+        Err("Error while applying patch: Reference \".:2\" refers to a capture group that does not exist".to_string())
+    );
+}
+#[test]
+fn invalid_references() {
+    for patch in [
+        "a: 'x]y'",
+        "a: 'x[y'",
+        "a: '[a:b:c]'",
+        "a: 'x[]'",
+        "a: { $move: 'b/[]' }",
+        "'a[]': x",
+        "a: { $copy_from: '../b]' }",
+        "a: { $if: { b: '[]' } }",
+    ] {
+        assert!(patch_xml::Patch::from_yaml(patch).is_err(), "{}", patch);
+    }
+}
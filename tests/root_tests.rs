@@ -0,0 +1,88 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn wrap_root() {
+    test_patch(
+        indoc!(r#"<device><name>A</name></device>"#),
+        indoc!(
+            r#"
+                    device:
+                      $wrap: devices
+                    "#
+        ),
+        indoc!(r#"<devices><device><name>A</name></device></devices>"#),
+    );
+}
+#[test]
+fn move_root_into_new_root() {
+    test_patch(
+        indoc!(r#"<device><name>A</name></device>"#),
+        indoc!(
+            r#"
+                    device:
+                      $move: devices/chip
+                    "#
+        ),
+        indoc!(r#"<devices><chip><name>A</name></chip></devices>"#),
+    );
+}
+#[test]
+fn unwrap_root() {
+    test_patch(
+        indoc!(r#"<devices><device><name>A</name></device></devices>"#),
+        indoc!(
+            r#"
+                    devices:
+                      $unwrap: true
+                    "#
+        ),
+        indoc!(r#"<device><name>A</name></device>"#),
+    );
+}
+#[test]
+fn unwrap_element() {
+    test_patch(
+        indoc!(r#"<device><group><a /><b /></group><c /></device>"#),
+        indoc!(
+            r#"
+                    device:
+                      group:
+                        $unwrap: true
+                    "#
+        ),
+        indoc!(r#"<device><a /><b /><c /></device>"#),
+    );
+}
+#[test]
+fn replace_root() {
+    test_patch(
+        indoc!(r#"<device><name>A</name></device>"#),
+        indoc!(
+            r#"
+                    device: ~
+                    $modify:
+                      chip:
+                        name: B
+                    "#
+        ),
+        indoc!(r#"<chip><name>B</name></chip>"#),
+    );
+}
+#[test]
+fn no_single_root() {
+    for patch in [
+        "device: ~",
+        "device:\n  $copy: ../device2",
+        "device:\n  $unwrap: true",
+        "$modify:\n  +chip: ~",
+    ] {
+        let result = patch_xml::patch_xml(
+            r#"<device><a /><b /></device>"#.to_string(),
+            patch.to_string(),
+        );
+        assert!(result.is_err(), "{}", patch);
+    }
+}
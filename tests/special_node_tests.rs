@@ -0,0 +1,108 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn add_comment() {
+    test_patch(
+        indoc!(r#"<element>Foo</element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        $comment: patched by patch-xml
+                    "#
+        ),
+        indoc!(r#"<element>Foo<!-- patched by patch-xml --></element>"#),
+    );
+}
+#[test]
+fn add_cdata_to_new_element() {
+    test_patch(
+        indoc!(r#"<element />"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        code:
+                          $cdata: "a < b && c"
+                    "#
+        ),
+        indoc!(r#"<element><code><![CDATA[a < b && c]]></code></element>"#),
+    );
+}
+#[test]
+fn add_processing_instruction() {
+    test_patch(
+        indoc!(r#"<element />"#),
+        indoc!(
+            r#"
+                    element:
+                      $modify:
+                        $pi:
+                          target: some data
+                          empty: ~
+                    "#
+        ),
+        indoc!(r#"<element><?target some data?><?empty?></element>"#),
+    );
+}
+#[test]
+fn remove_all_comments() {
+    test_patch(
+        indoc!(r#"<element><!--first-->Foo<!--second--></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $comments: ~
+                    "#
+        ),
+        indoc!(r#"<element>Foo</element>"#),
+    );
+}
+#[test]
+fn rewrite_matching_comments() {
+    test_patch(
+        indoc!(r#"<element><!--generated--><sub>Foo</sub><!-- keep me --></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $comments:
+                        gen.*: patched
+                    "#
+        ),
+        indoc!(r#"<element><!-- patched --><sub>Foo</sub><!-- keep me --></element>"#),
+    );
+}
+#[test]
+fn rewrite_added_cdata_and_keep_text() {
+    test_patch(
+        indoc!(r#"<element>Foo</element>"#),
+        indoc!(
+            r#"
+                    - element:
+                        $modify:
+                          $cdata: old
+                    - element:
+                        $cdatas: new
+                    "#
+        ),
+        indoc!(r#"<element>Foo<![CDATA[new]]></element>"#),
+    );
+}
+#[test]
+fn remove_processing_instruction_by_target() {
+    test_patch(
+        indoc!(r#"<element><?first data?><?second data?></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      $pis:
+                        first: ~
+                        second: new data
+                    "#
+        ),
+        indoc!(r#"<element><?second new data?></element>"#),
+    );
+}
@@ -0,0 +1,81 @@
+use indoc::indoc;
+use patch_xml::Patch;
+
+const DEVICE: &str = r#"<device schemaVersion="1.1"><name>STM32</name><!-- generated --><peripherals><peripheral><name>UART0</name><baseAddress>0x4000</baseAddress><registers><register><name>CR</name><size>32</size></register><register><name>SR</name><size>32</size></register></registers></peripheral><peripheral><name>SPI0</name><baseAddress>0x5000</baseAddress></peripheral></peripherals></device>"#;
+
+fn test_streaming(patch: &str, streamable: bool) {
+    let patch = Patch::from_yaml(patch).unwrap();
+    assert_eq!(patch.is_streamable(), streamable);
+    let mut result = Vec::new();
+    patch
+        .apply_streaming(DEVICE.as_bytes(), &mut result)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        patch_xml::apply_patch(DEVICE.to_string(), &patch).unwrap()
+    );
+}
+
+#[test]
+fn stream_text_and_attributes() {
+    test_streaming(
+        indoc!(
+            r#"
+            device:
+              name: STM32F4
+              peripherals:
+                peripheral:
+                  $if: { name: "UART.*" }
+                  baseAddress: "0x4001"
+                  registers:
+                    register:
+                      $modify: { $attributes: { access: read-write } }
+            "#
+        ),
+        true,
+    );
+}
+#[test]
+fn stream_local_structure_changes() {
+    test_streaming(
+        indoc!(
+            r#"
+            device:
+              peripherals:
+                - peripheral:
+                    $if: { name: "SPI.*" }
+                    $wrap: spi
+                - peripheral:
+                    registers:
+                      register:
+                        $if: { name: SR }
+                        $move: "../status/"
+                    $modify: { +status: {} }
+            "#
+        ),
+        true,
+    );
+}
+#[test]
+fn fallback_to_tree_mode() {
+    test_streaming(
+        indoc!(
+            r#"
+            device:
+              peripherals:
+                peripheral:
+                  $if: { name: "SPI.*" }
+                  $copy: "../spi_copy"
+            "#
+        ),
+        false,
+    );
+}
+#[test]
+fn stream_invalid_xml() {
+    let patch = Patch::from_yaml("device: { name: x }").unwrap();
+    assert!(patch
+        .apply_streaming("<device><name>".as_bytes(), Vec::new())
+        .unwrap_err()
+        .starts_with("Error while reading XML"));
+}
@@ -0,0 +1,212 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn use_modification_template() {
+    test_patch(
+        indoc!(r#"<fields><field><name>EN</name></field><field><name>RST</name></field></fields>"#),
+        indoc!(
+            r#"
+                    $templates:
+                      enable_bits:
+                        params: [subject]
+                        modify:
+                          enumeratedValues:
+                            $foreach:
+                              var: [state, value]
+                              in: { disabled: 0, enabled: 1 }
+                            +enumeratedValue:
+                              name: "[$state]"
+                              description: "[$subject] [$state]"
+                              value: "[$value]"
+                    fields:
+                      field:
+                        $modify:
+                          $use:
+                            template: enable_bits
+                            args:
+                              subject: Channel
+                    "#
+        ),
+        indoc!(
+            r#"<fields><field><name>EN</name><enumeratedValues><enumeratedValue><name>disabled</name><description>Channel disabled</description><value>0</value></enumeratedValue><enumeratedValue><name>enabled</name><description>Channel enabled</description><value>1</value></enumeratedValue></enumeratedValues></field><field><name>RST</name><enumeratedValues><enumeratedValue><name>disabled</name><description>Channel disabled</description><value>0</value></enumeratedValue><enumeratedValue><name>enabled</name><description>Channel enabled</description><value>1</value></enumeratedValue></enumeratedValues></field></fields>"#
+        ),
+    );
+}
+#[test]
+fn use_query_template_with_references() {
+    test_patch(
+        indoc!(r#"<element><a_1>x</a_1><b_2>y</b_2></element>"#),
+        indoc!(
+            r#"
+                    $templates:
+                      tag:
+                        params: [label]
+                        query:
+                          $modify:
+                            $append_text: "-[$label]"
+                    element:
+                      (a|b)_(\d):
+                        $use:
+                          template: tag
+                          args:
+                            label: "[.:2]"
+                    "#
+        ),
+        indoc!(r#"<element><a_1>x-1</a_1><b_2>y-2</b_2></element>"#),
+    );
+}
+#[test]
+fn templates_using_templates() {
+    test_patch(
+        indoc!(r#"<element />"#),
+        indoc!(
+            r#"
+                    $templates:
+                      leaf:
+                        params: [value]
+                        modify:
+                          +leaf: "[$value]"
+                      pair:
+                        modify:
+                          $use: { template: leaf, args: { value: first } }
+                          +other: second
+                    element:
+                      $modify:
+                        $use: { template: pair }
+                    "#
+        ),
+        indoc!(r#"<element><leaf>first</leaf><other>second</other></element>"#),
+    );
+}
+#[test]
+fn unknown_template() {
+    let result = patch_xml::patch_xml(
+        r#"<element />"#.to_string(),
+        indoc!(
+            r#"
+                    element:
+                      $use: { template: missing }
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.unwrap_err().contains("Unknown template \"missing\""));
+}
+#[test]
+fn missing_template_argument() {
+    let result = patch_xml::patch_xml(
+        r#"<element />"#.to_string(),
+        indoc!(
+            r#"
+                    $templates:
+                      leaf:
+                        params: [value]
+                        modify:
+                          +leaf: "[$value]"
+                    element:
+                      $modify:
+                        $use: { template: leaf }
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result
+        .unwrap_err()
+        .contains("Missing argument \"value\" for template \"leaf\""));
+}
+#[test]
+fn else_templates_not_visible_in_if_branch() {
+    let result = patch_xml::patch_xml(
+        r#"<element><value>1</value></element>"#.to_string(),
+        indoc!(
+            r#"
+                    element:
+                      $if: { value: "1" }
+                      $else:
+                        $templates:
+                          leaf:
+                            query: { $modify: { +leaf: else } }
+                      value:
+                        $use: { template: leaf }
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result.unwrap_err().contains("Unknown template \"leaf\""));
+}
+#[test]
+fn empty_template_argument() {
+    let result = patch_xml::patch_xml(
+        r#"<element />"#.to_string(),
+        indoc!(
+            r#"
+                    $templates:
+                      leaf:
+                        params: [value]
+                        modify:
+                          +leaf: "[$value]"
+                    element:
+                      $modify:
+                        $use: { template: leaf, args: { value: ~ } }
+                    "#
+        )
+        .to_string(),
+    );
+    assert!(result
+        .unwrap_err()
+        .contains("Argument \"value\" of template \"leaf\" must not be empty"));
+}
+#[test]
+fn nested_templates_with_table_file() {
+    let dir = std::env::temp_dir().join("patch_xml_template_tests");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("access.csv"), "RO,read-only\n").unwrap();
+    std::fs::write(
+        dir.join("patch.yaml"),
+        indoc!(
+            r#"
+                $templates:
+                  access:
+                    modify:
+                      $map: { table: { file: access.csv } }
+                  field:
+                    modify:
+                      access:
+                        $use: { template: access }
+                device:
+                  field:
+                    $modify:
+                      $use: { template: field }
+                "#
+        ),
+    )
+    .unwrap();
+    let patch = patch_xml::Patch::from_file(dir.join("patch.yaml")).unwrap();
+    assert_eq!(
+        patch_xml::apply_patch(
+            "<device><field><access>RO</access></field></device>".to_string(),
+            &patch
+        ),
+        Ok(indoc!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><device><field><access>read-only</access></field></device>"#
+        )
+        .to_string())
+    );
+}
+#[test]
+fn deeply_nested_templates() {
+    // Every template uses the previous one twice; the definitions are shared instead of copied
+    let mut patch = String::from("$templates:\n  t0:\n    modify:\n      $append_text: x\n");
+    for i in 1..40 {
+        patch.push_str(&format!(
+            "  t{i}:\n    modify:\n      a:\n        $use: {{ template: t{j} }}\n      b:\n        $use: {{ template: t{j} }}\n",
+            i = i,
+            j = i - 1
+        ));
+    }
+    patch.push_str("element:\n  $modify:\n    $use: { template: t39 }\n");
+    assert!(patch_xml::Patch::from_yaml(&patch).is_ok());
+}
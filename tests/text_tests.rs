@@ -0,0 +1,93 @@
+mod utils;
+
+use indoc::indoc;
+use utils::test_patch;
+
+#[test]
+fn set_text_in_mixed_content() {
+    test_patch(
+        indoc!(r#"<p>Hello <b>x</b> world</p>"#),
+        indoc!(
+            r#"
+                    p:
+                      $modify:
+                        $text: Bye
+                    "#
+        ),
+        indoc!(r#"<p>Bye<b>x</b></p>"#),
+    );
+}
+#[test]
+fn set_text_without_existing_text() {
+    test_patch(
+        indoc!(r#"<p><b>x</b></p>"#),
+        indoc!(
+            r#"
+                    p:
+                      $modify:
+                        $text: Hello
+                    "#
+        ),
+        indoc!(r#"<p><b>x</b>Hello</p>"#),
+    );
+}
+#[test]
+fn remove_text_only() {
+    test_patch(
+        indoc!(r#"<p>Hello <b>x</b><!--comment--></p>"#),
+        indoc!(
+            r#"
+                    p:
+                      $modify:
+                        $text: ~
+                    "#
+        ),
+        indoc!(r#"<p><b>x</b><!-- comment --></p>"#),
+    );
+}
+#[test]
+fn prepend_and_append_text() {
+    test_patch(
+        indoc!(r#"<p>Hello <b>x</b></p>"#),
+        indoc!(
+            r#"
+                    p:
+                      $modify:
+                        $text:
+                          $prepend: "Well, "
+                          $append: " and bye"
+                    "#
+        ),
+        indoc!(r#"<p>Well, Hello <b>x</b> and bye</p>"#),
+    );
+}
+#[test]
+fn append_text_incrementally() {
+    test_patch(
+        indoc!(r#"<p>Foo</p>"#),
+        indoc!(
+            r#"
+                    p:
+                      $modify:
+                        - $append_text: Bar
+                        - $append_text: Baz
+                    "#
+        ),
+        indoc!(r#"<p>FooBarBaz</p>"#),
+    );
+}
+#[test]
+fn append_text_with_reference() {
+    test_patch(
+        indoc!(r#"<element><sub1>Foo</sub1></element>"#),
+        indoc!(
+            r#"
+                    element:
+                      sub(.+):
+                        $modify:
+                          $append_text: "[.:1]"
+                    "#
+        ),
+        indoc!(r#"<element><sub1>Foo1</sub1></element>"#),
+    );
+}
@@ -1,11 +1,15 @@
 pub fn test_patch(xml_str: &str, patch_str: &str, expected_result: &str) {
     let result_str = patch_xml::patch_xml(xml_str.to_string(), patch_str.to_string());
-    assert_eq!(
-        result_str.unwrap(),
-        format!(
-            r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
-            expected_result
-        )
-        .to_string()
+    let expected_result = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+        expected_result
     );
+    assert_eq!(result_str.unwrap(), expected_result);
+    // Streaming mode must give the same result
+    let patch = patch_xml::Patch::from_yaml(patch_str).unwrap();
+    let mut result = Vec::new();
+    patch
+        .apply_streaming(xml_str.as_bytes(), &mut result)
+        .unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected_result);
 }